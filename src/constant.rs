@@ -0,0 +1,89 @@
+use super::ssa::Value;
+use super::ssa::Op;
+use super::ssa::Terminator;
+use super::ssa::Function;
+use super::ssa::Program;
+use super::ssa;
+use super::eval;
+
+// Constant folding and propagation over the SSA form. An operator whose
+// arguments are all constant is replaced by its result wherever it is read, a
+// block parameter that receives the same value from every jump becomes that
+// value, and a branch on a constant becomes a jump to the block it takes,
+// which may leave more parameters with a single value. Calls, asm and print
+// are never folded: they have effects, or read the heap or input.
+
+fn pure(op: &Op) -> bool {
+    matches!(op, Op::Add | Op::Sub | Op::Multiply | Op::Division | Op::Modulo |
+                 Op::Equal | Op::LessThan | Op::Greater)
+}
+
+// Folds the statements of each block in order, so that a result feeds the
+// statements after it right away.
+fn fold_statements(func: &mut Function) -> bool {
+    let mut changed = false;
+    for b in 0..func.blocks.len() {
+        let mut i = 0;
+        while i < func.blocks[b].statements.len() {
+            let statement = &func.blocks[b].statements[i];
+            let folded = match statement.args[..] {
+                // Division by zero and overflow are left for the Piet program.
+                [Value::Immediate(a), Value::Immediate(b)] if pure(&statement.op) => {
+                    eval::binary(&ssa::operator(&statement.op), a, b)
+                }
+                _ => None
+            };
+            match folded {
+                Some(res) => {
+                    let ret = statement.rets[0];
+                    func.blocks[b].statements.remove(i);
+                    func.replace(ret, Value::Immediate(res));
+                    changed = true;
+                }
+                None => i += 1
+            }
+        }
+    }
+    changed
+}
+
+fn fold_branches(func: &mut Function) -> bool {
+    let mut changed = false;
+    for block in func.blocks.iter_mut() {
+        if let Terminator::Branch{cond: Value::Immediate(cond), then, otherwise} = block.terminator {
+            // Branch targets take no parameters.
+            let target = if cond != 0 { then } else { otherwise };
+            block.terminator = Terminator::Jump{target, args: Vec::new()};
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn params(func: &Function) -> usize {
+    func.blocks.iter().map(|block| block.params.len()).sum()
+}
+
+fn function(func: &mut Function) {
+    if func.blocks.is_empty() {
+        return;
+    }
+    loop {
+        let mut changed = fold_statements(func);
+        if fold_branches(func) {
+            func.remove_unreachable();
+            changed = true;
+        }
+        let before = params(func);
+        func.simplify_params();
+        if !changed && params(func) == before {
+            break;
+        }
+    }
+}
+
+pub fn fold(program: &mut Program) {
+    for func in program.funcs.values_mut() {
+        function(func);
+    }
+}
@@ -0,0 +1,175 @@
+use super::ssa::Reg;
+use super::ssa::Value;
+use super::ssa::Op;
+use super::ssa::Statement;
+use super::ssa::Terminator;
+use super::ssa::Function;
+use super::ssa::Program;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// Dead code elimination over the SSA form: drops the blocks no path from the
+// entry reaches, statements without side effects whose results are never
+// read, and block parameters that only feed themselves or other dead values,
+// and merges the blocks that are only entered from one jump.
+
+// A division is only pure when the divisor is a known non-zero constant, as
+// dividing by zero is left for the Piet program to deal with at run time.
+fn pure(statement: &Statement) -> bool {
+    match statement.op {
+        Op::Add | Op::Sub | Op::Multiply | Op::Equal | Op::LessThan | Op::Greater => true,
+        Op::Division | Op::Modulo => matches!(statement.args[1], Value::Immediate(divisor) if divisor != 0),
+        _ => false
+    }
+}
+
+// Marks the registers the effects of the function depend on, starting from
+// the arguments of statements with effects, branches and returns, and walking
+// back through pure statements and the jumps into block parameters.
+fn live(func: &Function) -> HashSet<Reg> {
+    let mut params = HashMap::new();
+    let mut defs = HashMap::new();
+    let mut stack: Vec<Value> = func.blocks[0].params.iter().map(|reg| Value::Reg(*reg)).collect();
+    for (b, block) in func.blocks.iter().enumerate() {
+        for (i, param) in block.params.iter().enumerate() {
+            params.insert(*param, (b, i));
+        }
+        for statement in &block.statements {
+            if pure(statement) {
+                defs.insert(statement.rets[0], &statement.args);
+            } else {
+                stack.extend(statement.args.iter().cloned());
+            }
+        }
+        match block.terminator {
+            Terminator::Branch{cond, ..} => stack.push(cond),
+            Terminator::Return(ref args) => stack.extend(args.iter().cloned()),
+            Terminator::Jump{..} => {}
+        }
+    }
+    let mut res = HashSet::new();
+    while let Some(value) = stack.pop() {
+        let reg = match value {
+            Value::Reg(reg) => reg,
+            _ => continue
+        };
+        if !res.insert(reg) {
+            continue;
+        }
+        if let Some(args) = defs.get(&reg) {
+            stack.extend(args.iter().cloned());
+        }
+        if let Some(&(target, i)) = params.get(&reg) {
+            for block in &func.blocks {
+                if let Terminator::Jump{target: t, ref args} = block.terminator {
+                    if t == target {
+                        stack.push(args[i]);
+                    }
+                }
+            }
+        }
+    }
+    res
+}
+
+fn retain<T>(values: &mut Vec<T>, keep: &[bool]) {
+    let mut i = 0;
+    values.retain(|_| {
+        i += 1;
+        keep[i - 1]
+    });
+}
+
+fn remove_unused(func: &mut Function) {
+    let live = live(func);
+    for block in func.blocks.iter_mut() {
+        block.statements.retain(|statement| !pure(statement) || live.contains(&statement.rets[0]));
+    }
+    // The parameters of the entry block are the arguments.
+    for target in 1..func.blocks.len() {
+        let keep: Vec<bool> = func.blocks[target].params.iter().map(|reg| live.contains(reg)).collect();
+        if keep.iter().all(|keep| *keep) {
+            continue;
+        }
+        retain(&mut func.blocks[target].params, &keep);
+        for block in func.blocks.iter_mut() {
+            if let Terminator::Jump{target: t, ref mut args} = block.terminator {
+                if t == target {
+                    retain(args, &keep);
+                }
+            }
+        }
+    }
+}
+
+// Merges a block into the only block that jumps to it, such as the rest of a
+// block after an inlined call.
+fn merge_blocks(func: &mut Function) {
+    loop {
+        let mut preds = vec![0; func.blocks.len()];
+        preds[0] = 1;
+        for block in &func.blocks {
+            for target in block.terminator.targets() {
+                preds[target] += 1;
+            }
+        }
+        let merge = (0..func.blocks.len()).find_map(|b| match func.blocks[b].terminator {
+            Terminator::Jump{target, ref args} if target != b && preds[target] == 1 => {
+                Some((b, target, args.clone()))
+            }
+            _ => None
+        });
+        let (b, target, args) = match merge {
+            Some(merge) => merge,
+            None => break
+        };
+        for (param, arg) in func.blocks[target].params.clone().into_iter().zip(args) {
+            func.replace(param, arg);
+        }
+        let next = func.blocks[target].clone();
+        let block = &mut func.blocks[b];
+        block.statements.extend(next.statements);
+        block.terminator = next.terminator;
+        func.remove_unreachable();
+    }
+}
+
+// Drops the functions that neither main nor a function value can reach
+// through calls, such as those inlined everywhere. A function value may call
+// any function with a label, so those are kept. Builtins are kept too, as
+// trans always emits them.
+fn remove_uncalled(program: &mut Program) {
+    let mut reached = HashSet::new();
+    let mut stack: Vec<&str> = program.labels.iter().map(|label| label.as_str()).collect();
+    stack.push("main");
+    while let Some(name) = stack.pop() {
+        if !reached.insert(name.to_string()) {
+            continue;
+        }
+        let func = match program.funcs.get(name) {
+            Some(func) => func,
+            None => continue
+        };
+        for block in &func.blocks {
+            for statement in &block.statements {
+                if let Op::Call{ref name} = statement.op {
+                    stack.push(name);
+                }
+            }
+        }
+    }
+    program.funcs.retain(|name, func| func.blocks.is_empty() || reached.contains(name));
+}
+
+pub fn eliminate(program: &mut Program) {
+    for func in program.funcs.values_mut() {
+        if func.blocks.is_empty() {
+            continue;
+        }
+        func.remove_unreachable();
+        merge_blocks(func);
+        remove_unused(func);
+    }
+    remove_uncalled(program);
+}
@@ -0,0 +1,134 @@
+use super::generate::Program;
+use super::generate::Function;
+use super::generate::Value;
+use super::generate::Operator;
+
+use std::collections::HashMap;
+
+// Limits for compile-time evaluation. Running out of either gives up on
+// folding the call, which is then left to the Piet program.
+pub const MAX_STEPS: usize = 1_000_000;
+pub const MAX_DEPTH: usize = 1_000;
+
+struct Machine<'a> {
+    program: &'a Program,
+    steps: usize
+}
+
+fn value(regs: &HashMap<usize, i32>, val: &Value) -> Option<i32> {
+    match val {
+        Value::Register(reg) => regs.get(reg).copied(),
+        Value::Immediate(imm) => Some(*imm),
+        Value::Label(label) => Some(*label as i32)
+    }
+}
+
+// Piet's mod takes the sign of the divisor.
+fn modulo(a: i32, b: i32) -> Option<i32> {
+    let r = a.checked_rem(b)?;
+    if r != 0 && (r < 0) != (b < 0) {
+        r.checked_add(b)
+    } else {
+        Some(r)
+    }
+}
+
+// Evaluates an arithmetic or comparison operator; None when it divides by
+// zero or overflows i32.
+pub fn binary(op: &Operator, a: i32, b: i32) -> Option<i32> {
+    match op {
+        Operator::Add => a.checked_add(b),
+        Operator::Sub => a.checked_sub(b),
+        Operator::Multiply => a.checked_mul(b),
+        Operator::Division => a.checked_div(b),
+        Operator::Modulo => modulo(a, b),
+        Operator::LessThan => Some((a < b) as i32),
+        Operator::Greater => Some((a > b) as i32),
+        Operator::Equal => Some((a == b) as i32),
+        _ => None
+    }
+}
+
+impl<'a> Machine<'a> {
+    fn call(&mut self, func: &Function, args: &[i32], depth: usize) -> Result<Vec<i32>, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!("exceeded {} nested calls", MAX_DEPTH));
+        }
+        if func.basicblocks.is_empty() {
+            return Err(format!("calls {}, which cannot run at compile time", func.name));
+        }
+        let mut regs: HashMap<usize, i32> = args.iter().cloned().enumerate().collect();
+        let mut index = 0;
+        loop {
+            let block = &func.basicblocks[index];
+            let mut next = index + 1;
+            for inst in &block.statements {
+                self.steps += 1;
+                if self.steps > MAX_STEPS {
+                    return Err(format!("did not finish within {} steps", MAX_STEPS));
+                }
+                let mut args = Vec::new();
+                for arg in &inst.args {
+                    match value(&regs, arg) {
+                        Some(v) => args.push(v),
+                        None => return Err(format!("reads undefined register {:?}", arg))
+                    }
+                }
+                let res = match inst.op {
+                    Operator::Substitute => Some(args[0]),
+                    Operator::Division | Operator::Modulo if args[1] == 0 => {
+                        return Err("divides by zero".to_string())
+                    }
+                    Operator::Add | Operator::Sub | Operator::Multiply | Operator::Division |
+                    Operator::Modulo | Operator::LessThan | Operator::Greater | Operator::Equal => {
+                        binary(&inst.op, args[0], args[1])
+                    }
+                    Operator::Call{ref name} => {
+                        let callee = self.program.funcs.get(name).unwrap();
+                        let rets = self.call(callee, &args, depth + 1)?;
+                        for (i, ret) in rets.into_iter().enumerate() {
+                            regs.insert(inst.ret.unwrap() + i, ret);
+                        }
+                        continue;
+                    }
+                    Operator::If => {
+                        next = if args[0] != 0 { block.nexts[0] } else { block.nexts[1] };
+                        break;
+                    }
+                    Operator::Jump => {
+                        next = block.nexts[0];
+                        break;
+                    }
+                    Operator::Return => return Ok(args),
+                    ref op => return Err(format!("uses {:?}, which cannot run at compile time", op))
+                };
+                match res {
+                    Some(res) => regs.insert(inst.ret.unwrap(), res),
+                    None => return Err("overflows i32".to_string())
+                };
+            }
+            if next >= func.basicblocks.len() {
+                if func.retnum == 0 {
+                    return Ok(Vec::new());
+                }
+                return Err(format!("reaches the end of {} without return", func.name));
+            }
+            index = next;
+        }
+    }
+}
+
+// Runs a function of the IR on constant arguments.
+pub fn eval(program: &Program, name: &str, args: &[i32]) -> Option<Vec<i32>> {
+    let mut machine = Machine { program, steps: 0 };
+    let func = program.funcs.get(name)?;
+    match machine.call(func, args, 0) {
+        Ok(rets) => Some(rets),
+        Err(message) => {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            eprintln!("Warning: cannot evaluate {}({}) at compile time: it {}",
+                     name, args.join(", "), message);
+            None
+        }
+    }
+}
@@ -0,0 +1,576 @@
+use super::tokenize::Keyword;
+use super::tokenize::Punctuator;
+use super::tokenize::TokenType;
+use super::tokenize::Token;
+use super::tokenize::Comment;
+use super::parse::Literal;
+use super::parse::Pattern;
+use super::parse::Piece;
+use super::parse::Inline;
+use super::parse::Call;
+use super::parse::Expr;
+use super::parse::Stmt;
+use super::parse::FnDecl;
+use super::parse::Decl;
+use super::parse::Program;
+use super::generate;
+use super::generate::Operator;
+use super::generate::Value;
+use super::format;
+
+// Serializations of each compiler stage for external tools.
+//
+// Every stage is first converted to a `Node` tree, so JSON and S-expressions
+// share one schema. Each object has a `kind` naming what it is, followed by
+// its fields in a fixed order; a missing optional value is `null` (`nil`).
+// In S-expressions an object is `(kind :field value ...)` and a list is
+// `(item ...)`. Lines are 1-based.
+//
+// Tokens: a list of `keyword{value, line, comments}`, `identifier{value, ..}`,
+// `constant{value, ..}`, `char{value, ..}`, `string{value, ..}` and
+// `punctuator{value, ..}`, where `value` is the keyword or punctuator as
+// written. A comment is `comment{text, line, trailing}`.
+//
+// AST: `program{decls}` with the declarations
+//   fn{name, args, retnum, rettype, public, constant, inline, body}, with
+//     each argument an `arg{name, type}` and inline one of "auto", "always"
+//     or "never"
+//   struct{name, fields, public}, with each field a `field{name, type}`
+//   enum{name, variants, public}, with each variant a `variant{name, value}`
+//   import{path} and comment
+// the statements
+//   substitute{name, value}, update{name, op, value}, call{name, args},
+//   if{cond, then, otherwise}, match{scrutinee, arms} with
+//   `arm{patterns, body}`, return{value},
+//   asm{inputs, instructions, outputs} with the instructions as a list of
+//   source lines, assert{cond, file, line, message}, print{pieces, args},
+//   fn and comment
+// the expressions
+//   constant{value}, char{value}, identifier{name}, function{name},
+//   binary{op, lhs, rhs}, call{name, args}, struct_literal{name, fields}
+//   with `field{name, value}`, field{base, name} and
+//   conditional{cond, then, otherwise}
+// the patterns range{lo, hi}, variant{name} and wildcard{}, and the print
+// pieces text{text}, number{width, fill, left} and char{width, left}.
+//
+// IR: `ir{functions, structs, enums, labels}`, sorted by name, with
+//   function{name, args, types, retnum, rettype, constant, inline, blocks}, where
+//     args names a register per struct field and types has an entry per
+//     argument as written
+//   block{statements, nexts}, with each statement `<op>{ret, args, ...}`
+//     where op is one of add, sub, multiply, division, modulo, substitute,
+//     equal, less_than, greater, if, call{name}, call_indirect{retnum},
+//     return, jump, asm{instructions, outputs} or print{pieces}, and each
+//     argument a register{index}, immediate{value} or label{index}
+//   struct{name, fields} and enum{name, variants}
+pub enum Node {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    List(Vec<Node>),
+    Object(&'static str, Vec<(&'static str, Node)>)
+}
+
+fn str(text: &str) -> Node {
+    Node::Str(text.to_string())
+}
+
+fn int(value: usize) -> Node {
+    Node::Int(value as i64)
+}
+
+fn optional(value: &Option<String>) -> Node {
+    match value {
+        Some(value) => str(value),
+        None => Node::Null
+    }
+}
+
+fn inline(attr: Inline) -> Node {
+    str(match attr {
+        Inline::Auto => "auto",
+        Inline::Always => "always",
+        Inline::Never => "never"
+    })
+}
+
+fn list<T>(items: &[T], f: impl Fn(&T) -> Node) -> Node {
+    Node::List(items.iter().map(f).collect())
+}
+
+fn keyword(keyword: &Keyword) -> &'static str {
+    match keyword {
+        Keyword::FN => "fn",
+        Keyword::RETURN => "return",
+        Keyword::VAL => "val",
+        Keyword::IF => "if",
+        Keyword::ELSIF => "elsif",
+        Keyword::ELSE => "else",
+        Keyword::STRUCT => "struct",
+        Keyword::IMPORT => "import",
+        Keyword::PUB => "pub",
+        Keyword::ASM => "asm",
+        Keyword::MATCH => "match",
+        Keyword::CONST => "const",
+        Keyword::ENUM => "enum"
+    }
+}
+
+fn punctuator(punctuator: &Punctuator) -> &'static str {
+    match punctuator {
+        Punctuator::BraceLeft => "{",
+        Punctuator::BraceRight => "}",
+        Punctuator::ParenthesisLeft => "(",
+        Punctuator::ParenthesisRight => ")",
+        Punctuator::BracketLeft => "[",
+        Punctuator::BracketRight => "]",
+        Punctuator::Comma => ",",
+        Punctuator::Plus => "+",
+        Punctuator::Minus => "-",
+        Punctuator::Star => "*",
+        Punctuator::Slash => "/",
+        Punctuator::Percent => "%",
+        Punctuator::Equal => "=",
+        Punctuator::DoubleEqual => "==",
+        Punctuator::SemiColon => ";",
+        Punctuator::LessThan => "<",
+        Punctuator::Greater => ">",
+        Punctuator::Dot => ".",
+        Punctuator::Colon => ":",
+        Punctuator::Ampersand => "&",
+        Punctuator::FatArrow => "=>",
+        Punctuator::Pipe => "|",
+        Punctuator::Hash => "#",
+        Punctuator::DotDotEqual => "..=",
+        Punctuator::PlusEqual => "+=",
+        Punctuator::MinusEqual => "-=",
+        Punctuator::StarEqual => "*=",
+        Punctuator::SlashEqual => "/=",
+        Punctuator::PercentEqual => "%="
+    }
+}
+
+fn comment(comment: &Comment) -> Node {
+    Node::Object("comment", vec![
+        ("text", str(&comment.text)),
+        ("line", int(comment.line + 1)),
+        ("trailing", Node::Bool(comment.trailing))
+    ])
+}
+
+pub fn tokens(tokens: &[Token]) -> Node {
+    list(tokens, |token| {
+        let (kind, value) = match &token.token {
+            TokenType::Keyword(kw) => ("keyword", str(keyword(kw))),
+            TokenType::Identifier(name) => ("identifier", str(name)),
+            TokenType::Constant(imm) => ("constant", Node::Int(*imm as i64)),
+            TokenType::Char(c) => ("char", Node::Str(c.to_string())),
+            TokenType::String(text) => ("string", str(text)),
+            TokenType::Punctuator(punc) => ("punctuator", str(punctuator(punc)))
+        };
+        Node::Object(kind, vec![
+            ("value", value),
+            ("line", int(token.pos.line + 1)),
+            ("comments", list(&token.comments, comment))
+        ])
+    })
+}
+
+fn literal(lit: &Literal) -> Node {
+    match lit {
+        Literal::Int(imm) => Node::Object("constant", vec![("value", Node::Int(*imm as i64))]),
+        Literal::Char(c) => Node::Object("char", vec![("value", Node::Str(c.to_string()))])
+    }
+}
+
+fn pattern(pattern: &Pattern) -> Node {
+    match pattern {
+        Pattern::Range(lo, hi) => Node::Object("range", vec![("lo", literal(lo)), ("hi", literal(hi))]),
+        Pattern::Variant(name) => Node::Object("variant", vec![("name", str(name))]),
+        Pattern::Wildcard => Node::Object("wildcard", vec![])
+    }
+}
+
+fn piece(piece: &Piece) -> Node {
+    match piece {
+        Piece::Text(text) => Node::Object("text", vec![("text", str(text))]),
+        Piece::Number{width, fill, left} => {
+            Node::Object("number", vec![
+                ("width", int(*width)),
+                ("fill", Node::Str(fill.to_string())),
+                ("left", Node::Bool(*left))
+            ])
+        }
+        Piece::Char{width, left} => {
+            Node::Object("char", vec![("width", int(*width)), ("left", Node::Bool(*left))])
+        }
+    }
+}
+
+fn call(call: &Call) -> Node {
+    Node::Object("call", vec![("name", str(&call.name)), ("args", list(&call.args, expr))])
+}
+
+fn expr(expr: &Expr) -> Node {
+    match expr {
+        Expr::Constant(imm) => literal(&Literal::Int(*imm)),
+        Expr::Char(c) => literal(&Literal::Char(*c)),
+        Expr::Identifier(name) => Node::Object("identifier", vec![("name", str(name))]),
+        Expr::Function(name) => Node::Object("function", vec![("name", str(name))]),
+        Expr::Binary{op, lhs, rhs} => {
+            Node::Object("binary", vec![
+                ("op", str(format::operator(op))),
+                ("lhs", self::expr(lhs)),
+                ("rhs", self::expr(rhs))
+            ])
+        }
+        Expr::Call(c) => call(c),
+        Expr::StructLiteral{name, fields} => {
+            Node::Object("struct_literal", vec![
+                ("name", str(name)),
+                ("fields", list(fields, |(field, value)| {
+                    Node::Object("field", vec![("name", str(field)), ("value", self::expr(value))])
+                }))
+            ])
+        }
+        Expr::Field{base, name} => {
+            Node::Object("field", vec![("base", self::expr(base)), ("name", str(name))])
+        }
+        Expr::Conditional{cond, then, otherwise} => {
+            Node::Object("conditional", vec![
+                ("cond", self::expr(cond)),
+                ("then", self::expr(then)),
+                ("otherwise", self::expr(otherwise))
+            ])
+        }
+    }
+}
+
+fn body(body: &[Stmt]) -> Node {
+    list(body, stmt)
+}
+
+fn stmt(stmt: &Stmt) -> Node {
+    match stmt {
+        Stmt::Substitute{name, value} => {
+            Node::Object("substitute", vec![("name", str(name)), ("value", expr(value))])
+        }
+        Stmt::Update{name, op, value} => {
+            Node::Object("update", vec![
+                ("name", str(name)),
+                ("op", str(format::operator(op))),
+                ("value", expr(value))
+            ])
+        }
+        Stmt::Call(c) => call(c),
+        Stmt::If{cond, then, otherwise} => {
+            Node::Object("if", vec![
+                ("cond", expr(cond)),
+                ("then", body(then)),
+                ("otherwise", match otherwise {
+                    Some(otherwise) => body(otherwise),
+                    None => Node::Null
+                })
+            ])
+        }
+        Stmt::Match{scrutinee, arms} => {
+            Node::Object("match", vec![
+                ("scrutinee", expr(scrutinee)),
+                ("arms", list(arms, |arm| {
+                    Node::Object("arm", vec![
+                        ("patterns", list(&arm.patterns, pattern)),
+                        ("body", body(&arm.body))
+                    ])
+                }))
+            ])
+        }
+        Stmt::Return(value) => {
+            Node::Object("return", vec![("value", match value {
+                Some(value) => expr(value),
+                None => Node::Null
+            })])
+        }
+        Stmt::Asm{inputs, instructions, outputs} => {
+            Node::Object("asm", vec![
+                ("inputs", list(inputs, expr)),
+                ("instructions", list(instructions, |line| list(line, |inst| str(inst)))),
+                ("outputs", list(outputs, |output| str(output)))
+            ])
+        }
+        Stmt::Assert{cond, file, line, message} => {
+            Node::Object("assert", vec![
+                ("cond", expr(cond)),
+                ("file", str(file)),
+                ("line", int(*line)),
+                ("message", optional(message))
+            ])
+        }
+        Stmt::Print{pieces, args} => {
+            Node::Object("print", vec![("pieces", list(pieces, piece)), ("args", list(args, expr))])
+        }
+        Stmt::Fn(decl) => function(decl),
+        Stmt::Comment(c) => comment(c)
+    }
+}
+
+fn typed(names: &[String], types: &[Option<String>], kind: &'static str) -> Node {
+    Node::List(names.iter().zip(types.iter()).map(|(name, ty)| {
+        Node::Object(kind, vec![("name", str(name)), ("type", optional(ty))])
+    }).collect())
+}
+
+fn function(decl: &FnDecl) -> Node {
+    Node::Object("fn", vec![
+        ("name", str(&decl.name)),
+        ("args", typed(&decl.args, &decl.types, "arg")),
+        ("retnum", int(decl.retnum)),
+        ("rettype", optional(&decl.rettype)),
+        ("public", Node::Bool(decl.public)),
+        ("constant", Node::Bool(decl.constant)),
+        ("inline", inline(decl.inline)),
+        ("body", body(&decl.body))
+    ])
+}
+
+fn decl(decl: &Decl) -> Node {
+    match decl {
+        Decl::Fn(decl) => function(decl),
+        Decl::Struct(decl) => {
+            Node::Object("struct", vec![
+                ("name", str(&decl.name)),
+                ("fields", typed(&decl.fields, &decl.types, "field")),
+                ("public", Node::Bool(decl.public))
+            ])
+        }
+        Decl::Enum(decl) => {
+            Node::Object("enum", vec![
+                ("name", str(&decl.name)),
+                ("variants", list(&decl.variants, |(name, value)| {
+                    Node::Object("variant", vec![
+                        ("name", str(name)),
+                        ("value", match value {
+                            Some(value) => literal(value),
+                            None => Node::Null
+                        })
+                    ])
+                })),
+                ("public", Node::Bool(decl.public))
+            ])
+        }
+        Decl::Import{path} => Node::Object("import", vec![("path", str(path))]),
+        Decl::Comment(c) => comment(c)
+    }
+}
+
+pub fn ast(program: &Program) -> Node {
+    Node::Object("program", vec![("decls", list(&program.decls, decl))])
+}
+
+fn value(value: &Value) -> Node {
+    match value {
+        Value::Register(index) => Node::Object("register", vec![("index", int(*index))]),
+        Value::Immediate(imm) => Node::Object("immediate", vec![("value", Node::Int(*imm as i64))]),
+        Value::Label(index) => Node::Object("label", vec![("index", int(*index))])
+    }
+}
+
+fn statement(statement: &generate::Statement) -> Node {
+    let (kind, mut fields) = match &statement.op {
+        Operator::Add => ("add", vec![]),
+        Operator::Sub => ("sub", vec![]),
+        Operator::Multiply => ("multiply", vec![]),
+        Operator::Division => ("division", vec![]),
+        Operator::Modulo => ("modulo", vec![]),
+        Operator::Substitute => ("substitute", vec![]),
+        Operator::Equal => ("equal", vec![]),
+        Operator::LessThan => ("less_than", vec![]),
+        Operator::Greater => ("greater", vec![]),
+        Operator::If => ("if", vec![]),
+        Operator::Call{name} => ("call", vec![("name", str(name))]),
+        Operator::CallIndirect{retnum} => ("call_indirect", vec![("retnum", int(*retnum))]),
+        Operator::Return => ("return", vec![]),
+        Operator::Jump => ("jump", vec![]),
+        Operator::Asm{instructions, outputs} => {
+            ("asm", vec![
+                ("instructions", list(instructions, |inst| str(inst))),
+                ("outputs", list(outputs, |output| str(output)))
+            ])
+        }
+        Operator::Print{pieces} => ("print", vec![("pieces", list(pieces, piece))])
+    };
+    let ret = match statement.ret {
+        Some(ret) => int(ret),
+        None => Node::Null
+    };
+    fields.insert(0, ("ret", ret));
+    fields.insert(1, ("args", list(&statement.args, value)));
+    Node::Object(kind, fields)
+}
+
+fn ir_function(func: &generate::Function) -> Node {
+    Node::Object("function", vec![
+        ("name", str(&func.name)),
+        ("args", list(&func.args, |arg| str(arg))),
+        ("types", list(&func.types, optional)),
+        ("retnum", int(func.retnum)),
+        ("rettype", optional(&func.rettype)),
+        ("constant", Node::Bool(func.constant)),
+        ("inline", inline(func.inline)),
+        ("blocks", list(&func.basicblocks, |block| {
+            Node::Object("block", vec![
+                ("statements", list(&block.statements, statement)),
+                ("nexts", list(&block.nexts, |next| int(*next)))
+            ])
+        }))
+    ])
+}
+
+pub fn ir(program: &generate::Program) -> Node {
+    let mut funcs: Vec<_> = program.funcs.values().collect();
+    funcs.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut structs: Vec<_> = program.structs.iter().collect();
+    structs.sort_by(|a, b| a.0.cmp(b.0));
+    let mut enums: Vec<_> = program.enums.iter().collect();
+    enums.sort_by(|a, b| a.0.cmp(b.0));
+    Node::Object("ir", vec![
+        ("functions", Node::List(funcs.into_iter().map(ir_function).collect())),
+        ("structs", Node::List(structs.into_iter().map(|(name, st)| {
+            Node::Object("struct", vec![
+                ("name", str(name)),
+                ("fields", typed(&st.fields, &st.types, "field"))
+            ])
+        }).collect())),
+        ("enums", Node::List(enums.into_iter().map(|(name, variants)| {
+            Node::Object("enum", vec![
+                ("name", str(name)),
+                ("variants", list(variants, |(variant, value)| {
+                    Node::Object("variant", vec![
+                        ("name", str(variant)),
+                        ("value", Node::Int(*value as i64))
+                    ])
+                }))
+            ])
+        }).collect())),
+        ("labels", list(&program.labels, |label| str(label)))
+    ])
+}
+
+fn quote(text: &str) -> String {
+    let mut res = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c)
+        }
+    }
+    res.push('"');
+    res
+}
+
+fn is_scalar(node: &Node) -> bool {
+    match node {
+        Node::List(items) => items.is_empty(),
+        Node::Object(..) => false,
+        _ => true
+    }
+}
+
+// Objects and lists holding only scalars stay on one line, so that a token or
+// a register is not spread over several.
+fn json_node(node: &Node, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth + 1);
+    match node {
+        Node::Null => out.push_str("null"),
+        Node::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Node::Int(imm) => out.push_str(&imm.to_string()),
+        Node::Str(text) => out.push_str(&quote(text)),
+        Node::List(items) => {
+            if items.iter().all(is_scalar) {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    json_node(item, depth, out);
+                }
+                out.push(']');
+                return;
+            }
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(&indent);
+                json_node(item, depth + 1, out);
+                out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&"  ".repeat(depth));
+            out.push(']');
+        }
+        Node::Object(kind, fields) => {
+            if fields.iter().all(|(_, value)| is_scalar(value)) {
+                out.push_str(&format!("{{\"kind\": {}", quote(kind)));
+                for (name, value) in fields {
+                    out.push_str(&format!(", {}: ", quote(name)));
+                    json_node(value, depth, out);
+                }
+                out.push('}');
+                return;
+            }
+            out.push_str(&format!("{{\n{}\"kind\": {}", indent, quote(kind)));
+            for (name, value) in fields {
+                out.push_str(&format!(",\n{}{}: ", indent, quote(name)));
+                json_node(value, depth + 1, out);
+            }
+            out.push('\n');
+            out.push_str(&"  ".repeat(depth));
+            out.push('}');
+        }
+    }
+}
+
+pub fn json(node: &Node) -> String {
+    let mut out = String::new();
+    json_node(node, 0, &mut out);
+    out.push('\n');
+    out
+}
+
+fn sexp_node(node: &Node, out: &mut String) {
+    match node {
+        Node::Null => out.push_str("nil"),
+        Node::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Node::Int(imm) => out.push_str(&imm.to_string()),
+        Node::Str(text) => out.push_str(&quote(text)),
+        Node::List(items) => {
+            out.push('(');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                sexp_node(item, out);
+            }
+            out.push(')');
+        }
+        Node::Object(kind, fields) => {
+            out.push('(');
+            out.push_str(kind);
+            for (name, value) in fields {
+                out.push_str(&format!(" :{} ", name));
+                sexp_node(value, out);
+            }
+            out.push(')');
+        }
+    }
+}
+
+pub fn sexp(node: &Node) -> String {
+    let mut out = String::new();
+    sexp_node(node, &mut out);
+    out.push('\n');
+    out
+}
@@ -0,0 +1,415 @@
+use super::parse::BinaryOp;
+use super::parse::Literal;
+use super::parse::Pattern;
+use super::parse::Piece;
+use super::parse::Call;
+use super::parse::Expr;
+use super::parse::Stmt;
+use super::parse::Arm;
+use super::parse::FnDecl;
+use super::parse::Inline;
+use super::parse::StructDecl;
+use super::parse::EnumDecl;
+use super::parse::Decl;
+use super::parse::Program;
+use super::tokenize::Comment;
+use super::tokenize::Token;
+use super::visit::Visitor;
+
+const WIDTH: usize = 80;
+const INDENT: &str = "  ";
+
+fn literal(lit: &Literal) -> String {
+    match *lit {
+        Literal::Int(imm) => imm.to_string(),
+        Literal::Char('\\') => "'\\\\'".to_string(),
+        Literal::Char('\'') => "'\\''".to_string(),
+        Literal::Char(c) => format!("'{}'", c)
+    }
+}
+
+pub fn string(text: &str) -> String {
+    let mut res = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\\' => res.push_str("\\\\"),
+            '"' => res.push_str("\\\""),
+            _ => res.push(c)
+        }
+    }
+    res.push('"');
+    res
+}
+
+pub fn format_string(pieces: &[Piece]) -> String {
+    let mut text = String::new();
+    for piece in pieces {
+        match *piece {
+            Piece::Text(ref literal) => text.push_str(&literal.replace('{', "{{").replace('}', "}}")),
+            Piece::Number{width: 0, fill: ' ', left: false} => text.push_str("{}"),
+            Piece::Number{width, fill, left} => {
+                text.push_str(&format!("{{:{}{}{}}}", if left { "<" } else { "" },
+                                       if fill == '0' { "0" } else { "" }, width));
+            }
+            Piece::Char{width, left} => {
+                text.push_str(&format!("{{:{}{}c}}", if left { "<" } else { "" },
+                                       if width > 0 { width.to_string() } else { String::new() }));
+            }
+        }
+    }
+    string(&text)
+}
+
+pub fn operator(op: &BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Multiply => "*",
+        BinaryOp::Division => "/",
+        BinaryOp::Modulo => "%",
+        BinaryOp::Equal => "==",
+        BinaryOp::LessThan => "<",
+        BinaryOp::Greater => ">"
+    }
+}
+
+fn precedence(op: &BinaryOp) -> usize {
+    match op {
+        BinaryOp::Equal => 1,
+        BinaryOp::LessThan | BinaryOp::Greater => 2,
+        BinaryOp::Add | BinaryOp::Sub => 3,
+        BinaryOp::Multiply | BinaryOp::Division | BinaryOp::Modulo => 4
+    }
+}
+
+// The parser does not chain operators of the same precedence, so `(a - b) - c`
+// keeps its parentheses.
+fn operand(expr: &Expr, prec: usize) -> String {
+    match expr {
+        Expr::Binary{ref op, ..} if precedence(op) <= prec => format!("({})", expression(expr)),
+        Expr::Conditional{..} => format!("({})", expression(expr)),
+        _ => expression(expr)
+    }
+}
+
+fn call(call: &Call) -> String {
+    let args: Vec<String> = call.args.iter().map(expression).collect();
+    format!("{}({})", call.name, args.join(", "))
+}
+
+fn expression(expr: &Expr) -> String {
+    match expr {
+        Expr::Constant(imm) if *imm < 0 => format!("(0 - {})", -(*imm as i64)),
+        Expr::Constant(imm) => imm.to_string(),
+        Expr::Char(c) => literal(&Literal::Char(*c)),
+        Expr::Identifier(name) => name.to_string(),
+        Expr::Function(name) => format!("&{}", name),
+        Expr::Binary{ref op, ref lhs, ref rhs} => {
+            let prec = precedence(op);
+            format!("{} {} {}", operand(lhs, prec), operator(op), operand(rhs, prec))
+        }
+        Expr::Call(ref c) => call(c),
+        Expr::StructLiteral{ref name, ref fields} => {
+            let fields: Vec<String> = fields.iter()
+                .map(|(field, value)| format!("{}: {}", field, expression(value)))
+                .collect();
+            format!("{} {{ {} }}", name, fields.join(", "))
+        }
+        Expr::Field{ref base, ref name} => format!("{}.{}", operand(base, usize::MAX), name),
+        Expr::Conditional{ref cond, ref then, ref otherwise} => {
+            let otherwise = match **otherwise {
+                Expr::Conditional{..} => expression(otherwise),
+                _ => format!("{{ {} }}", expression(otherwise))
+            };
+            format!("if {} {{ {} }} else {}", expression(cond), expression(then), otherwise)
+        }
+    }
+}
+
+fn pattern(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Range(lo, hi) if lo == hi => literal(lo),
+        Pattern::Range(lo, hi) => format!("{}..={}", literal(lo), literal(hi)),
+        Pattern::Variant(name) => name.to_string(),
+        Pattern::Wildcard => "_".to_string()
+    }
+}
+
+fn typed(names: &[String], types: &[Option<String>]) -> Vec<String> {
+    names.iter().zip(types.iter())
+        .map(|(name, ty)| match ty {
+            Some(ty) => format!("{}: {}", name, ty),
+            None => name.to_string()
+        })
+        .collect()
+}
+
+// `x = x + e` is written back as `x += e`, and `x = x + 1` as `x++`.
+fn substitute<'a>(name: &str, value: &'a Expr) -> (String, Option<&'a Call>) {
+    match value {
+        Expr::Call(c) => (format!("{} = {}", name, call(c)), Some(c)),
+        _ => (format!("{} = {}", name, expression(value)), None)
+    }
+}
+
+fn update(name: &str, op: &BinaryOp, value: &Expr) -> String {
+    match (op, value) {
+        (BinaryOp::Add, Expr::Constant(1)) => format!("{}++", name),
+        (BinaryOp::Sub, Expr::Constant(1)) => format!("{}--", name),
+        _ => format!("{} {}= {}", name, operator(op), expression(value))
+    }
+}
+
+struct Formatter {
+    lines: Vec<String>,
+    depth: usize
+}
+
+impl Formatter {
+    fn line(&mut self, text: &str) {
+        self.lines.push(format!("{}{}", INDENT.repeat(self.depth), text));
+    }
+
+    fn comment(&mut self, comment: &Comment) {
+        match self.lines.last_mut() {
+            Some(last) if comment.trailing => last.push_str(&format!("  //{}", comment.text)),
+            _ => self.line(&format!("//{}", comment.text))
+        }
+    }
+
+    // Puts a list that does not fit on one line one item per line.
+    fn list(&mut self, head: &str, items: &[String], tail: &str) {
+        let text = format!("{}{}{}", head, items.join(", "), tail);
+        if self.depth * INDENT.len() + text.len() <= WIDTH || items.is_empty() {
+            self.line(&text);
+            return;
+        }
+        self.line(head);
+        self.depth += 1;
+        for (i, item) in items.iter().enumerate() {
+            if i + 1 < items.len() {
+                self.line(&format!("{},", item));
+            } else {
+                self.line(item);
+            }
+        }
+        self.depth -= 1;
+        self.line(tail);
+    }
+
+    fn call(&mut self, head: &str, c: &Call, tail: &str) {
+        let args: Vec<String> = c.args.iter().map(expression).collect();
+        self.list(&format!("{}{}(", head, c.name), &args, &format!("){}", tail));
+    }
+
+    fn body(&mut self, body: &[Stmt]) {
+        self.depth += 1;
+        for stmt in body {
+            self.statement(stmt);
+        }
+        self.depth -= 1;
+    }
+
+    // The one line form of a statement, used for short match arms.
+    fn simple(stmt: &Stmt) -> Option<String> {
+        match stmt {
+            Stmt::Substitute{ref name, ref value} => Some(format!("{};", substitute(name, value).0)),
+            Stmt::Update{ref name, ref op, ref value} => Some(format!("{};", update(name, op, value))),
+            Stmt::Call(ref c) => Some(format!("{};", call(c))),
+            Stmt::Return(None) => Some("return;".to_string()),
+            Stmt::Return(Some(ref value)) => Some(format!("return {};", expression(value))),
+            Stmt::Assert{..} | Stmt::Print{..} => {
+                let mut formatter = Formatter { lines: Vec::new(), depth: 0 };
+                formatter.statement(stmt);
+                formatter.lines.pop()
+            }
+            _ => None
+        }
+    }
+
+    fn arm(&mut self, arm: &Arm) {
+        let patterns: Vec<String> = arm.patterns.iter().map(pattern).collect();
+        let head = format!("{} => {{", patterns.join(" | "));
+        if arm.body.is_empty() {
+            self.line(&format!("{}}}", head));
+            return;
+        }
+        if arm.body.len() == 1 {
+            if let Some(text) = Formatter::simple(&arm.body[0]) {
+                let text = format!("{} {} }}", head, text);
+                if self.depth * INDENT.len() + text.len() <= WIDTH {
+                    self.line(&text);
+                    return;
+                }
+            }
+        }
+        self.line(&head);
+        self.body(&arm.body);
+        self.line("}");
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Substitute{ref name, ref value} => {
+                match substitute(name, value) {
+                    (_, Some(c)) => self.call(&format!("{} = ", name), c, ";"),
+                    (text, None) => self.line(&format!("{};", text))
+                }
+            }
+            Stmt::Update{ref name, ref op, ref value} => {
+                self.line(&format!("{};", update(name, op, value)));
+            }
+            Stmt::Call(ref c) => self.call("", c, ";"),
+            Stmt::If{ref cond, ref then, ref otherwise} => {
+                self.line(&format!("if {} {{", expression(cond)));
+                self.body(then);
+                if let Some(otherwise) = otherwise {
+                    self.line("} else {");
+                    self.body(otherwise);
+                }
+                self.line("}");
+            }
+            Stmt::Match{ref scrutinee, ref arms} => {
+                self.line(&format!("match {} {{", expression(scrutinee)));
+                self.depth += 1;
+                for arm in arms {
+                    self.arm(arm);
+                }
+                self.depth -= 1;
+                self.line("}");
+            }
+            Stmt::Return(None) => self.line("return;"),
+            Stmt::Return(Some(Expr::Call(ref c))) => self.call("return ", c, ";"),
+            Stmt::Return(Some(ref value)) => self.line(&format!("return {};", expression(value))),
+            Stmt::Asm{ref inputs, ref instructions, ref outputs} => {
+                let mut head = String::from("asm");
+                if !inputs.is_empty() {
+                    let inputs: Vec<String> = inputs.iter().map(expression).collect();
+                    head.push_str(&format!("({})", inputs.join(", ")));
+                }
+                if !outputs.is_empty() {
+                    head.push_str(&format!(" -> ({})", outputs.join(", ")));
+                }
+                self.line(&format!("{} {{", head));
+                self.depth += 1;
+                for line in instructions {
+                    self.line(&line.join(" "));
+                }
+                self.depth -= 1;
+                self.line("}");
+            }
+            Stmt::Assert{ref cond, ref message, ..} => {
+                let mut args = vec![expression(cond)];
+                args.extend(message.iter().map(|message| string(message)));
+                self.list("assert(", &args, ");");
+            }
+            Stmt::Print{ref pieces, ref args} => {
+                let mut items = vec![format_string(pieces)];
+                items.extend(args.iter().map(expression));
+                self.list("print(", &items, ");");
+            }
+            Stmt::Fn(ref decl) => self.function(decl),
+            Stmt::Comment(ref comment) => self.comment(comment)
+        }
+    }
+
+    fn function(&mut self, decl: &FnDecl) {
+        let ret = match decl.rettype {
+            Some(ref ty) => ty.to_string(),
+            None => decl.retnum.to_string()
+        };
+        match decl.inline {
+            Inline::Always => self.line("#[inline]"),
+            Inline::Never => self.line("#[noinline]"),
+            Inline::Auto => {}
+        }
+        let head = format!("{}{}fn[{}] {}(",
+                           if decl.public { "pub " } else { "" },
+                           if decl.constant { "const " } else { "" },
+                           ret, decl.name);
+        self.list(&head, &typed(&decl.args, &decl.types), ") {");
+        self.body(&decl.body);
+        self.line("}");
+    }
+
+    fn structure(&mut self, decl: &StructDecl) {
+        let head = format!("{}struct {} {{ ", if decl.public { "pub " } else { "" }, decl.name);
+        let fields = typed(&decl.fields, &decl.types);
+        let text = format!("{}{} }}", head, fields.join(", "));
+        if text.len() <= WIDTH {
+            self.line(&text);
+        } else {
+            self.list(head.trim_end(), &fields, "}");
+        }
+    }
+
+    fn enumeration(&mut self, decl: &EnumDecl) {
+        self.line(&format!("{}enum {} {{", if decl.public { "pub " } else { "" }, decl.name));
+        self.depth += 1;
+        for (i, (name, value)) in decl.variants.iter().enumerate() {
+            let mut text = match value {
+                Some(value) => format!("{} = {}", name, literal(value)),
+                None => name.to_string()
+            };
+            if i + 1 < decl.variants.len() {
+                text.push(',');
+            }
+            self.line(&text);
+        }
+        self.depth -= 1;
+        self.line("}");
+    }
+}
+
+// Declarations are separated by a blank line, except that imports are kept
+// together and a comment stays attached to the declaration after it.
+pub fn format(program: &Program) -> String {
+    let mut formatter = Formatter { lines: Vec::new(), depth: 0 };
+    let mut prev: Option<&Decl> = None;
+    for decl in &program.decls {
+        let trailing = matches!(decl, Decl::Comment(Comment{trailing: true, ..}));
+        let attached = matches!((prev, decl),
+                                (None, _) | (Some(Decl::Comment(_)), _) |
+                                (Some(Decl::Import{..}), Decl::Import{..}));
+        if !trailing && !attached {
+            formatter.lines.push(String::new());
+        }
+        match decl {
+            Decl::Fn(ref decl) => formatter.function(decl),
+            Decl::Struct(ref decl) => formatter.structure(decl),
+            Decl::Enum(ref decl) => formatter.enumeration(decl),
+            Decl::Import{ref path} => formatter.line(&format!("import {};", string(path))),
+            Decl::Comment(ref comment) => formatter.comment(comment)
+        }
+        if !trailing {
+            prev = Some(decl);
+        }
+    }
+    let mut res = formatter.lines.join("\n");
+    res.push('\n');
+    res
+}
+
+struct KeptComments {
+    lines: Vec<usize>
+}
+
+impl Visitor for KeptComments {
+    fn visit_comment(&mut self, comment: &Comment) {
+        self.lines.push(comment.line);
+    }
+}
+
+// The lines of the comments that the parser dropped because they were inside
+// a statement or a declaration, where fmt has no place to put them.
+pub fn lost_comments(tokens: &[Token], program: &Program) -> Vec<usize> {
+    let mut kept = KeptComments { lines: Vec::new() };
+    kept.visit_program(program);
+    tokens.iter()
+        .flat_map(|token| token.comments.iter())
+        .map(|comment| comment.line)
+        .filter(|line| !kept.lines.contains(line))
+        .collect()
+}
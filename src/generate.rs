@@ -1,7 +1,22 @@
-use super::parse::AST;
-use super::parse::Node;
-use super::parse::Operator;
-use super::parse::Leaf;
+use super::parse;
+use super::parse::BinaryOp;
+use super::parse::Call;
+use super::parse::Expr;
+use super::parse::Stmt;
+use super::parse::Arm;
+use super::parse::FnDecl;
+use super::parse::Inline;
+use super::parse::StructDecl;
+use super::parse::EnumDecl;
+use super::parse::Decl;
+use super::parse::Pattern;
+use super::parse::Literal;
+use super::visit;
+use super::visit::Visitor;
+use super::visit::Folder;
+use super::parse::Piece;
+use super::runtime;
+use super::eval;
 
 use std::collections::HashMap;
 
@@ -12,6 +27,26 @@ pub enum Value {
     Label(usize),
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Multiply,
+    Division,
+    Modulo,
+    Substitute,
+    Equal,
+    LessThan,
+    Greater,
+    If,
+    Call{name: String},
+    CallIndirect{retnum: usize},
+    Return,
+    Jump,
+    Asm{instructions: Vec<String>, outputs: Vec<String>},
+    Print{pieces: Vec<Piece>}
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Statement {
     pub op: Operator,
@@ -19,6 +54,11 @@ pub struct Statement {
     pub args: Vec<Value>
 }
 
+// The register form that generate emits, and that the textual IR and trans
+// work on. It is not SSA: a register is a variable, which an update or a
+// self tail call writes again, and a block ends at its first if, jump or
+// return statement, going on to the blocks in `nexts`. The optimization
+// passes work on the SSA form in ssa.rs instead.
 #[derive(Debug, Clone, PartialEq)]
 pub struct BasicBlock {
     pub statements: Vec<Statement>,
@@ -29,185 +69,403 @@ pub struct BasicBlock {
 pub struct Function {
     pub name: String,
     pub args: Vec<String>,
+    pub types: Vec<Option<String>>,
     pub retnum: usize,
+    pub rettype: Option<String>,
+    pub constant: bool,
+    pub inline: Inline,
     pub basicblocks: Vec<BasicBlock>
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct Struct {
+    pub fields: Vec<String>,
+    pub types: Vec<Option<String>>
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
-    pub funcs: HashMap<String, Function>
+    pub funcs: HashMap<String, Function>,
+    pub structs: HashMap<String, Struct>,
+    pub enums: HashMap<String, Vec<(String, i32)>>,
+    pub labels: Vec<String>
 }
 
-fn expression(ast: &AST, program: &Program,
-              vars: &HashMap<String, usize>, statements: &mut Vec<Statement>,
-              regcount: usize)
-        -> Option<Value> {
-    match ast {
-        AST::Node(node) => {
-            match node.op {
-                Operator::Call{name:ref funcname} => {
-                    match program.funcs.get(funcname) {
-                        Some(func) => {
-                            if node.children.len() != func.args.len() {
-                                println!("Function {} expected {} args, but {} provided",
-                                         funcname, func.args.len(), node.children.len());
-                                return None;
-                            }
-                        }
-                        None => {
-                            println!("Undefined function {}", funcname);
-                        }
-                    }
-                }
-                Operator::Add => {
-                    if node.children.len() != 2 {
-                        println!("Add operation take 2 args, but {} provided",
-                                 node.children.len());
-                        return None;
-                    }
+impl Statement {
+    // The registers the statement writes.
+    pub fn defines(&self, program: &Program) -> Vec<usize> {
+        let count = match self.op {
+            Operator::Call{ref name} => program.funcs.get(name).map_or(0, |callee| callee.retnum),
+            Operator::CallIndirect{retnum} => retnum,
+            Operator::Asm{ref outputs, ..} => outputs.len(),
+            Operator::If | Operator::Jump | Operator::Return | Operator::Print{..} => 0,
+            _ => 1
+        };
+        match self.ret {
+            Some(ret) => (ret..ret + count).collect(),
+            None => Vec::new()
+        }
+    }
+}
+
+impl Function {
+    // The blocks trans continues to after a block: it stops at the first if,
+    // jump or return, and otherwise falls through to the next block.
+    pub fn successors(&self, index: usize) -> Vec<usize> {
+        let block = &self.basicblocks[index];
+        let mut next = Vec::new();
+        if index + 1 < self.basicblocks.len() {
+            next.push(index + 1);
+        }
+        for statement in &block.statements {
+            match statement.op {
+                Operator::If => {
+                    next.extend(block.nexts.get(1));
+                    return next;
                 }
-                Operator::Sub => {
-                    if node.children.len() != 2 {
-                        println!("Sub operation take 2 args, but {} provided",
-                                 node.children.len());
-                        return None;
+                Operator::Jump => return block.nexts.iter().take(1).cloned().collect(),
+                Operator::Return => return Vec::new(),
+                _ => {}
+            }
+        }
+        next
+    }
+}
+
+fn layout(ty: &Option<String>, program: &Program, depth: usize) -> Option<Vec<String>> {
+    match ty {
+        Some(name) => {
+            if depth > program.structs.len() {
+                println!("Struct {} contains itself", name);
+                return None;
+            }
+            match program.structs.get(name) {
+                Some(st) => {
+                    let mut paths = Vec::new();
+                    for (field, fty) in st.fields.iter().zip(st.types.iter()) {
+                        for path in layout(fty, program, depth + 1)? {
+                            paths.push(format!(".{}{}", field, path));
+                        }
                     }
+                    Some(paths)
                 }
-                Operator::Multiply => {
-                    if node.children.len() != 2 {
-                        println!("Multiply operation take 2 args, but {} provided",
-                                 node.children.len());
-                        return None;
-                    }
+                None => {
+                    println!("Undefined struct {}", name);
+                    None
                 }
-                Operator::Division => {
-                    if node.children.len() != 2 {
-                        println!("Division operation take 2 args, but {} provided",
-                                 node.children.len());
-                        return None;
-                    }
+            }
+        }
+        None => Some(vec![String::new()])
+    }
+}
+
+fn variant(name: &str, program: &Program) -> Option<i32> {
+    let pos = name.rfind("::")?;
+    program.enums.get(&name[..pos])?.iter()
+        .find(|(v, _)| *v == name[pos+2..])
+        .map(|(_, value)| *value)
+}
+
+fn operator(op: &BinaryOp) -> Operator {
+    match op {
+        BinaryOp::Add => Operator::Add,
+        BinaryOp::Sub => Operator::Sub,
+        BinaryOp::Multiply => Operator::Multiply,
+        BinaryOp::Division => Operator::Division,
+        BinaryOp::Modulo => Operator::Modulo,
+        BinaryOp::Equal => Operator::Equal,
+        BinaryOp::LessThan => Operator::LessThan,
+        BinaryOp::Greater => Operator::Greater
+    }
+}
+
+fn next_register(statements: &[Statement], program: &Program, regcount: usize) -> usize {
+    let mut id = regcount;
+    for statement in statements {
+        if let Some(ret) = statement.ret {
+            let width = match statement.op {
+                Operator::Call{ref name} => {
+                    program.funcs.get(name).map_or(1, |func| func.retnum.max(1))
                 }
-                Operator::Modulo => {
-                    if node.children.len() != 2 {
-                        println!("Modulo operation take 2 args, but {} provided",
-                                 node.children.len());
-                        return None;
-                    }
+                Operator::CallIndirect{retnum} => retnum.max(1),
+                Operator::Asm{ref outputs, ..} => outputs.len(),
+                _ => 1
+            };
+            id = id.max(ret + width);
+        }
+    }
+    id
+}
+
+fn block_registers(basicblocks: &[BasicBlock], program: &Program, regcount: usize) -> usize {
+    basicblocks.iter().fold(regcount, |id, block| next_register(&block.statements, program, id))
+}
+
+fn fields(expr: &Expr, program: &Program,
+          vars: &HashMap<String, usize>, statements: &mut Vec<Statement>,
+          regcount: usize)
+        -> Option<Vec<(String, Value)>> {
+    match expr {
+        Expr::StructLiteral{ref name, fields: ref given} => {
+            let st = match program.structs.get(name) {
+                Some(st) => st,
+                None => {
+                    println!("Undefined struct {}", name);
+                    return None;
                 }
-                Operator::LessThan => {
-                    if node.children.len() != 2 {
-                        println!("LessThan operation take 2 args, but {} provided",
-                                 node.children.len());
-                        return None;
-                    }
+            };
+            if given.len() != st.fields.len() {
+                println!("Struct {} has {} fields, but {} provided",
+                         name, st.fields.len(), given.len());
+                return None;
+            }
+            if let Some(field) = st.fields.iter().find(|field| given.iter().all(|(n, _)| n != *field)) {
+                println!("Missing field {} of struct {}", field, name);
+                return None;
+            }
+            // The fields are evaluated in the order they are written, and laid
+            // out in the order they are declared.
+            let mut values = vec![Vec::new(); st.fields.len()];
+            for (field, value) in given {
+                let index = st.fields.iter().position(|n| n == field)?;
+                values[index] = conform(value, &st.types[index], program, vars, statements, regcount)?;
+            }
+            let mut res = Vec::new();
+            for ((field, fty), values) in st.fields.iter().zip(st.types.iter()).zip(values) {
+                for (path, value) in layout(fty, program, 0)?.into_iter().zip(values) {
+                    res.push((format!(".{}{}", field, path), value));
                 }
-                Operator::Greater => {
-                    if node.children.len() != 2 {
-                        println!("Greater operation take 2 args, but {} provided",
-                                 node.children.len());
-                        return None;
-                    }
+            }
+            Some(res)
+        }
+        Expr::Field{ref base, ref name} => {
+            let prefix = format!(".{}", name);
+            let mut res = Vec::new();
+            for (path, value) in fields(base, program, vars, statements, regcount)? {
+                if path == prefix || path.starts_with(&format!("{}.", prefix)) {
+                    res.push((path[prefix.len()..].to_string(), value));
                 }
-                Operator::Equal => {
-                    if node.children.len() != 2 {
-                        println!("Equal operation take 2 args, but {} provided",
-                                 node.children.len());
-                        return None;
-                    }
+            }
+            if res.is_empty() {
+                println!("No field named {}", name);
+                return None;
+            }
+            Some(res)
+        }
+        Expr::Call(Call{name: ref funcname, ref args}) => {
+            match program.funcs.get(funcname) {
+                Some(Function{rettype: rettype @ Some(_), ..}) => {
+                    let id = match call(funcname, args, program, vars, statements, regcount)? {
+                        Value::Register(id) => id,
+                        _ => return None
+                    };
+                    let paths = layout(rettype, program, 0)?;
+                    Some(paths.into_iter().enumerate()
+                         .map(|(i, path)| (path, Value::Register(id + i)))
+                         .collect())
                 }
                 _ => {
-                    println!("Unsupported operation {:?}", node.op);
-                    return None
+                    let value = expression(expr, program, vars, statements, regcount)?;
+                    Some(vec![(String::new(), value)])
                 }
             }
-            let mut id_vec = Vec::new();
-            for child in &node.children {
-                let id = expression(&child, program, vars, statements, regcount)?;
-                id_vec.push(id);
+        }
+        Expr::Identifier(name) if !vars.contains_key(name) && variant(name, program).is_none() => {
+            let prefix = format!("{}.", name);
+            let mut res: Vec<(String, Value)> = vars.iter()
+                .filter(|(var, _)| var.starts_with(&prefix))
+                .map(|(var, id)| (var[name.len()..].to_string(), Value::Register(*id)))
+                .collect();
+            if res.is_empty() {
+                println!("Undefined variable {}", name);
+                return None;
             }
-            let id = statements.len() + regcount;
+            res.sort_by(|a, b| a.0.cmp(&b.0));
+            Some(res)
+        }
+        _ => {
+            let value = expression(expr, program, vars, statements, regcount)?;
+            Some(vec![(String::new(), value)])
+        }
+    }
+}
+
+fn conform(expr: &Expr, ty: &Option<String>, program: &Program,
+           vars: &HashMap<String, usize>, statements: &mut Vec<Statement>,
+           regcount: usize)
+        -> Option<Vec<Value>> {
+    let given = fields(expr, program, vars, statements, regcount)?;
+    let expected = layout(ty, program, 0)?;
+    if given.len() != expected.len() || given.iter().any(|(path, _)| !expected.contains(path)) {
+        match ty {
+            Some(name) => println!("Expected struct {}, but got different fields", name),
+            None => println!("Expected a value, but a struct provided")
+        }
+        return None;
+    }
+    let mut res = Vec::new();
+    for path in &expected {
+        let (_, value) = given.iter().find(|(p, _)| p == path).unwrap();
+        res.push(*value);
+    }
+    Some(res)
+}
+
+fn expression(expr: &Expr, program: &Program,
+              vars: &HashMap<String, usize>, statements: &mut Vec<Statement>,
+              regcount: usize)
+        -> Option<Value> {
+    match expr {
+        Expr::Call(Call{name: ref funcname, ref args}) => {
+            call(funcname, args, program, vars, statements, regcount)
+        }
+        Expr::Field{..} => {
+            let res = fields(expr, program, vars, statements, regcount)?;
+            if res.len() != 1 || !res[0].0.is_empty() {
+                println!("Expected a value, but a struct provided");
+                return None;
+            }
+            Some(res[0].1)
+        }
+        Expr::Binary{ref op, ref lhs, ref rhs} => {
+            let lhs = expression(lhs, program, vars, statements, regcount)?;
+            let rhs = expression(rhs, program, vars, statements, regcount)?;
+            let id = next_register(statements, program, regcount);
             statements.push(Statement {
-                op: node.op.clone(),
+                op: operator(op),
                 ret: Some(id),
-                args: id_vec
+                args: vec![lhs, rhs]
             });
             Some(Value::Register(id))
         }
-        AST::Leaf(leaf) => {
-            match leaf {
-                Leaf::Identifier(name) => {
-                    match vars.get(name) {
-                        Some(id) => {
-                            Some(Value::Register(*id))
-                        }
-                        None => {
-                            println!("Undefined variable {}", name);
-                            None
-                        }
-                    }
+        Expr::Identifier(name) => {
+            match vars.get(name) {
+                Some(id) => {
+                    Some(Value::Register(*id))
+                }
+                None if variant(name, program).is_some() => {
+                    Some(Value::Immediate(variant(name, program).unwrap()))
                 }
-                Leaf::Constant(imm) => {
-                    Some(Value::Immediate(*imm))
+                None => {
+                    println!("Undefined variable {}", name);
+                    None
                 }
             }
         }
-    }
-}
-
-fn substitute(children: &Vec<AST>, program: &Program,
-              vars: &mut HashMap<String, usize>,
-              statements: &mut Vec<Statement>, regcount: usize) 
-        -> Option<Value> {
-    match &children[0] {
-        AST::Leaf(leaf) => {
-            match leaf {
-                Leaf::Identifier(lhs) => {
-                    if vars.contains_key(lhs) {
-                        println!("Variable {} is already defined.", lhs);
-                        return None;
-                    }
-                    let exp_id = expression(&children[1], program, vars,
-                                            statements, regcount)?;
-                    let id = statements.len() + regcount;
-                    statements.push(Statement {
-                        op: Operator::Substitute,
-                        ret: Some(id),
-                        args: vec![exp_id; 1]
-                    });
-                    vars.insert(lhs.to_string(), id);
-                    Some(Value::Register(id))
-                }
-                Leaf::Constant(constant) => {
-                    println!("Unexpected constant {}, expected identifier", constant);
+        Expr::Constant(imm) => {
+            Some(Value::Immediate(*imm))
+        }
+        Expr::Char(c) => {
+            Some(Value::Immediate(*c as i32))
+        }
+        Expr::Function(name) => {
+            match program.labels.iter().position(|label| label == name) {
+                Some(label) => Some(Value::Label(label)),
+                None => {
+                    println!("Function {} is not defined.", name);
                     None
                 }
             }
         }
-        AST::Node(_node) => {
-            println!("Unexpected Node, expected identifier or constant");
+        Expr::StructLiteral{..} => {
+            println!("Expected a value, but a struct provided");
+            None
+        }
+        Expr::Conditional{..} => {
+            println!("Unsupported conditional expression");
             None
         }
     }
 }
 
-fn call(name: &str, children: &Vec<AST>, program: &Program,
+fn update(lhs: &str, op: &BinaryOp, rhs: &Expr, program: &Program,
+          vars: &mut HashMap<String, usize>,
+          statements: &mut Vec<Statement>, regcount: usize)
+        -> Option<Value> {
+    let id = match vars.get(lhs) {
+        Some(id) => *id,
+        None => {
+            println!("Undefined variable {}", lhs);
+            return None;
+        }
+    };
+    let value = Expr::Binary{
+        op: op.clone(),
+        lhs: Box::new(Expr::Identifier(lhs.to_string())),
+        rhs: Box::new(rhs.clone())
+    };
+    let len = statements.len();
+    let value = expression(&value, program, vars, statements, regcount)?;
+    // Retarget the statement that computed the new value, so
+    // trans overwrites the register instead of copying into it.
+    if statements.len() > len {
+        let last = statements.last_mut().unwrap();
+        let inplace = matches!(last.op, Operator::Add | Operator::Sub |
+                               Operator::Multiply | Operator::Division |
+                               Operator::Modulo);
+        if inplace && last.ret.map(Value::Register) == Some(value) {
+            last.ret = Some(id);
+            return Some(Value::Register(id));
+        }
+    }
+    statements.push(Statement {
+        op: Operator::Substitute,
+        ret: Some(id),
+        args: vec![value]
+    });
+    Some(Value::Register(id))
+}
+
+fn substitute(lhs: &str, rhs: &Expr, program: &Program,
+              vars: &mut HashMap<String, usize>,
+              statements: &mut Vec<Statement>, regcount: usize) 
+        -> Option<Value> {
+    let prefix = format!("{}.", lhs);
+    if vars.contains_key(lhs) || vars.keys().any(|var| var.starts_with(&prefix)) {
+        println!("Variable {} is already defined.", lhs);
+        return None;
+    }
+    let mut last = None;
+    for (path, exp_id) in fields(rhs, program, vars, statements, regcount)? {
+        let id = next_register(statements, program, regcount);
+        statements.push(Statement {
+            op: Operator::Substitute,
+            ret: Some(id),
+            args: vec![exp_id; 1]
+        });
+        vars.insert(format!("{}{}", lhs, path), id);
+        last = Some(Value::Register(id));
+    }
+    last
+}
+
+fn call(name: &str, args: &[Expr], program: &Program,
         vars: &HashMap<String, usize>, statements: &mut Vec<Statement>,
         regcount: usize)
         -> Option<Value> {
     match program.funcs.get(name) {
         Some(func) => {
-            if func.args.len() != children.len() {
+            if func.types.len() != args.len() {
                 println!("Function {}: expected {} args, but {} provided",
-                         name, func.args.len(), children.len());
+                         name, func.types.len(), args.len());
                 return None;
             }
             let mut id_vec = Vec::new();
-            for child in children {
-                let id = expression(child, program, vars, statements,
-                                    regcount)?;
-                id_vec.push(id);
+            for (arg, ty) in args.iter().zip(func.types.iter()) {
+                let ids = conform(arg, ty, program, vars, statements,
+                                  regcount)?;
+                id_vec.extend(ids);
             }
-            let id = statements.len();
+            if func.constant && func.retnum == 1 && func.rettype.is_none() && !func.basicblocks.is_empty() {
+                let args: Option<Vec<i32>> = id_vec.iter().map(|id| match id {
+                    Value::Immediate(imm) => Some(*imm),
+                    _ => None
+                }).collect();
+                if let Some(rets) = args.and_then(|args| eval::eval(program, name, &args)) {
+                    return Some(Value::Immediate(rets[0]));
+                }
+            }
+            let id = next_register(statements, program, regcount);
             statements.push(Statement {
                 op: Operator::Call{name:name.to_string()},
                 ret: Some(id),
@@ -215,6 +473,9 @@ fn call(name: &str, children: &Vec<AST>, program: &Program,
             });
             Some(Value::Register(id))
         }
+        None if vars.contains_key(name) => {
+            call_indirect(name, args, 1, program, vars, statements, regcount)
+        }
         None => {
             println!("Function {} is not defined.", name);
             None
@@ -222,14 +483,39 @@ fn call(name: &str, children: &Vec<AST>, program: &Program,
     }
 }
 
-fn if_op(children: &Vec<AST>, program: &Program,
-         vars: &mut HashMap<String, usize>, basicblocks: &mut Vec<BasicBlock>,
-         regcount: usize) -> bool {
-    if children.len() < 2 {
-        println!("If need 2 or 3 children");
-        return false;
+// A function value takes its result count from the call: a call statement
+// discards the results, so it calls a function returning none, and a call
+// in an expression calls one returning a value. Trans dispatches on both
+// counts and halts on a function of another signature.
+fn call_indirect(name: &str, args: &[Expr], retnum: usize, program: &Program,
+                 vars: &HashMap<String, usize>, statements: &mut Vec<Statement>,
+                 regcount: usize)
+        -> Option<Value> {
+    let referenced = program.labels.iter()
+        .map(|label| program.funcs.get(label).unwrap())
+        .any(|func| func.args.len() == args.len() && func.retnum == retnum);
+    if !referenced {
+        println!("No function taking {} args and returning {} values is referenced, cannot call {}",
+                 args.len(), retnum, name);
+        return None;
+    }
+    let mut id_vec = vec![Value::Register(*vars.get(name).unwrap())];
+    for arg in args {
+        id_vec.push(expression(arg, program, vars, statements, regcount)?);
     }
-    let id = match expression(&children[0], program, vars,
+    let id = next_register(statements, program, regcount);
+    statements.push(Statement {
+        op: Operator::CallIndirect{retnum},
+        ret: Some(id),
+        args: id_vec
+    });
+    Some(Value::Register(id))
+}
+
+fn if_op(cond: &Expr, (then, otherwise): (&[Stmt], &Option<Vec<Stmt>>), program: &Program,
+         func: &Function, vars: &mut HashMap<String, usize>,
+         basicblocks: &mut Vec<BasicBlock>, regcount: usize) -> bool {
+    let id = match expression(cond, program, vars,
                               &mut basicblocks.last_mut().unwrap().statements,
                               regcount) {
         Some(id) => id,
@@ -238,8 +524,8 @@ fn if_op(children: &Vec<AST>, program: &Program,
             return false
         }
     };
-    let newregcount = regcount + basicblocks.last().unwrap().statements.len();
-    let index = match statement(&children[1], program, vars, newregcount) {
+    let newregcount = block_registers(basicblocks, program, regcount);
+    match statement(then, program, func, &mut vars.clone(), newregcount) {
         Some(vb) => {
             basicblocks.last_mut().unwrap().statements.push(Statement {
                 op: Operator::If,
@@ -260,16 +546,15 @@ fn if_op(children: &Vec<AST>, program: &Program,
             }
             let jump_to = basicblocks.len();
             basicblocks.get_mut(offset-1).unwrap().nexts.push(jump_to);
-            basicblocks.len() - 1
         }
         None => {
             println!("Invalid statements");
-            println!("{:?}", children[1]);
             return false
         }
-    };
-    if children.len() == 3 {
-        match statement(&children[2], program, vars, newregcount) {
+    }
+    if let Some(otherwise) = otherwise {
+        let newregcount = block_registers(basicblocks, program, regcount);
+        match statement(otherwise, program, func, &mut vars.clone(), newregcount) {
             Some(vb) => {
                 basicblocks.last_mut().unwrap().statements.push(Statement {
                     op: Operator::Jump,
@@ -305,12 +590,639 @@ fn if_op(children: &Vec<AST>, program: &Program,
     true
 }
 
-fn return_op(children: &Vec<AST>, program: &Program,
+fn has_conditional(expr: &Expr) -> bool {
+    match expr {
+        Expr::Conditional{..} => true,
+        _ => expr.children().into_iter().any(has_conditional)
+    }
+}
+
+fn has_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call(_) => true,
+        _ => expr.children().into_iter().any(has_call)
+    }
+}
+
+fn hidden(id: usize, vars: &mut HashMap<String, usize>) -> Expr {
+    let name = format!("#{}", id);
+    vars.insert(name.clone(), id);
+    Expr::Identifier(name)
+}
+
+// Evaluates the conditional expressions in expr ahead of the rest of the
+// statement and replaces each of them by the register holding its value.
+fn lift(expr: &Expr, program: &Program, func: &Function,
+        vars: &mut HashMap<String, usize>, basicblocks: &mut Vec<BasicBlock>,
+        regcount: usize) -> Option<Expr> {
+    if !has_conditional(expr) {
+        return Some(expr.clone());
+    }
+    match expr {
+        Expr::Conditional{ref cond, ref then, ref otherwise} => {
+            let cond = lift(cond, program, func, vars, basicblocks, regcount)?;
+            let id = conditional(&cond, [then, otherwise], program, func, vars, basicblocks, regcount)?;
+            Some(hidden(id, vars))
+        }
+        Expr::Binary{ref op, ref lhs, ref rhs} => {
+            let operands = [(**lhs).clone(), (**rhs).clone()];
+            let mut operands = lift_all(&operands, program, func, vars, basicblocks, regcount)?;
+            let rhs = operands.pop().unwrap();
+            let lhs = operands.pop().unwrap();
+            Some(Expr::Binary{op: op.clone(), lhs: Box::new(lhs), rhs: Box::new(rhs)})
+        }
+        Expr::Call(Call{ref name, ref args}) => {
+            let args = lift_all(args, program, func, vars, basicblocks, regcount)?;
+            Some(Expr::Call(Call{name: name.to_string(), args}))
+        }
+        Expr::StructLiteral{ref name, ref fields} => {
+            let values: Vec<Expr> = fields.iter().map(|(_, value)| value.clone()).collect();
+            let values = lift_all(&values, program, func, vars, basicblocks, regcount)?;
+            Some(Expr::StructLiteral{
+                name: name.to_string(),
+                fields: fields.iter().map(|(field, _)| field.to_string()).zip(values).collect()
+            })
+        }
+        Expr::Field{ref base, ref name} => {
+            let base = lift(base, program, func, vars, basicblocks, regcount)?;
+            Some(Expr::Field{base: Box::new(base), name: name.to_string()})
+        }
+        _ => Some(expr.clone())
+    }
+}
+
+// Calls to the left of a conditional are evaluated first to keep their order.
+fn lift_all(exprs: &[Expr], program: &Program, func: &Function,
+            vars: &mut HashMap<String, usize>, basicblocks: &mut Vec<BasicBlock>,
+            regcount: usize) -> Option<Vec<Expr>> {
+    let mut res = Vec::new();
+    for (i, expr) in exprs.iter().enumerate() {
+        let expr = if has_conditional(expr) {
+            lift(expr, program, func, vars, basicblocks, regcount)?
+        } else if has_call(expr) && exprs[i+1..].iter().any(has_conditional) {
+            let regcount = block_registers(basicblocks, program, regcount);
+            let value = expression(expr, program, vars,
+                                   &mut basicblocks.last_mut().unwrap().statements,
+                                   regcount)?;
+            match value {
+                Value::Register(id) => hidden(id, vars),
+                Value::Immediate(imm) => Expr::Constant(imm),
+                Value::Label(_) => expr.clone()
+            }
+        } else {
+            expr.clone()
+        };
+        res.push(expr);
+    }
+    Some(res)
+}
+
+// Only the condition of if and match belongs to the statement itself; their
+// bodies are lifted statement by statement.
+fn lift_statement(stmt: &Stmt, program: &Program, func: &Function,
+                  vars: &mut HashMap<String, usize>, basicblocks: &mut Vec<BasicBlock>,
+                  regcount: usize) -> Option<Stmt> {
+    Some(match stmt {
+        Stmt::Substitute{ref name, ref value} => {
+            Stmt::Substitute{
+                name: name.to_string(),
+                value: lift(value, program, func, vars, basicblocks, regcount)?
+            }
+        }
+        Stmt::Update{ref name, ref op, ref value} => {
+            Stmt::Update{
+                name: name.to_string(),
+                op: op.clone(),
+                value: lift(value, program, func, vars, basicblocks, regcount)?
+            }
+        }
+        Stmt::Call(Call{ref name, ref args}) => {
+            Stmt::Call(Call{
+                name: name.to_string(),
+                args: lift_all(args, program, func, vars, basicblocks, regcount)?
+            })
+        }
+        Stmt::If{ref cond, ref then, ref otherwise} => {
+            Stmt::If{
+                cond: lift(cond, program, func, vars, basicblocks, regcount)?,
+                then: then.clone(),
+                otherwise: otherwise.clone()
+            }
+        }
+        Stmt::Match{ref scrutinee, ref arms} => {
+            Stmt::Match{
+                scrutinee: lift(scrutinee, program, func, vars, basicblocks, regcount)?,
+                arms: arms.clone()
+            }
+        }
+        Stmt::Return(Some(ref value)) => {
+            Stmt::Return(Some(lift(value, program, func, vars, basicblocks, regcount)?))
+        }
+        Stmt::Asm{ref inputs, ref instructions, ref outputs} => {
+            Stmt::Asm{
+                inputs: lift_all(inputs, program, func, vars, basicblocks, regcount)?,
+                instructions: instructions.to_vec(),
+                outputs: outputs.to_vec()
+            }
+        }
+        Stmt::Assert{ref cond, ref file, line, ref message} => {
+            Stmt::Assert{
+                cond: lift(cond, program, func, vars, basicblocks, regcount)?,
+                file: file.to_string(),
+                line: *line,
+                message: message.clone()
+            }
+        }
+        Stmt::Print{ref pieces, ref args} => {
+            Stmt::Print{
+                pieces: pieces.to_vec(),
+                args: lift_all(args, program, func, vars, basicblocks, regcount)?
+            }
+        }
+        Stmt::Return(None) | Stmt::Fn(_) | Stmt::Comment(_) => stmt.clone()
+    })
+}
+
+// Both arms leave their value in the same register, so the join block
+// receives it in one stack slot whichever way control went.
+fn conditional(cond: &Expr, branches: [&Expr; 2], program: &Program, func: &Function,
+               vars: &mut HashMap<String, usize>, basicblocks: &mut Vec<BasicBlock>,
+               regcount: usize) -> Option<usize> {
+    let regcount = block_registers(basicblocks, program, regcount);
+    let cond = expression(cond, program, vars,
+                          &mut basicblocks.last_mut().unwrap().statements,
+                          regcount)?;
+    let id = block_registers(basicblocks, program, regcount);
+    let mut newregcount = id + 1;
+    let mut arms = Vec::new();
+    for child in branches {
+        let mut vb = vec![BasicBlock {
+            statements: Vec::new(),
+            nexts: Vec::new()
+        }];
+        let mut vars = vars.clone();
+        let child = lift(child, program, func, &mut vars, &mut vb, newregcount)?;
+        let regcount = block_registers(&vb, program, newregcount);
+        let value = match fields(&child, program, &vars, &mut vb.last_mut().unwrap().statements, regcount)?[..] {
+            [(ref path, value)] if path.is_empty() => value,
+            _ => {
+                println!("if-expression arms must be integers");
+                return None;
+            }
+        };
+        vb.last_mut().unwrap().statements.push(Statement {
+            op: Operator::Substitute,
+            ret: Some(id),
+            args: vec![value]
+        });
+        newregcount = block_registers(&vb, program, newregcount);
+        arms.push(vb);
+    }
+    basicblocks.last_mut().unwrap().statements.push(Statement {
+        op: Operator::If,
+        ret: None,
+        args: vec![cond]
+    });
+    let then = basicblocks.len();
+    let otherwise = then + arms[0].len();
+    let join = otherwise + arms[1].len();
+    basicblocks.last_mut().unwrap().nexts = vec![then, otherwise];
+    for (vb, offset) in arms.into_iter().zip([then, otherwise]) {
+        for b in vb {
+            basicblocks.push(BasicBlock {
+                statements: b.statements,
+                nexts: b.nexts.iter().map(|bid| bid + offset).collect()
+            });
+        }
+        if offset == then {
+            basicblocks.last_mut().unwrap().statements.push(Statement {
+                op: Operator::Jump,
+                ret: None,
+                args: Vec::new()
+            });
+        }
+        basicblocks.last_mut().unwrap().nexts = vec![join];
+    }
+    basicblocks.push(BasicBlock {
+        statements: Vec::new(),
+        nexts: Vec::new()
+    });
+    Some(id)
+}
+
+// A failed assert prints `text`, which says where it is, and halts. The failure
+// block sits right after the check so that the code following the assert
+// stays in the last block.
+fn assert_op(text: &str, cond: &Expr, program: &Program, vars: &HashMap<String, usize>,
+             basicblocks: &mut Vec<BasicBlock>, regcount: usize) -> bool {
+    let statements = &mut basicblocks.last_mut().unwrap().statements;
+    let cond = match expression(cond, program, vars, statements, regcount) {
+        Some(cond) => cond,
+        None => {
+            println!("Invalid expression");
+            return false
+        }
+    };
+    let failed = next_register(statements, program, regcount);
+    statements.push(Statement {
+        op: Operator::Equal,
+        ret: Some(failed),
+        args: vec![cond, Value::Immediate(0)]
+    });
+    statements.push(Statement {
+        op: Operator::If,
+        ret: None,
+        args: vec![Value::Register(failed)]
+    });
+    // The message is pushed and printed in place, like print does, so an
+    // assert adds one call, to halt, however long its message is.
+    let statements = vec![
+        Statement {
+            op: Operator::Print{pieces: vec![Piece::Text(format!("{}\n", text))]},
+            ret: None,
+            args: Vec::new()
+        },
+        Statement {
+            op: Operator::Call{name: "halt".to_string()},
+            ret: Some(failed + 1),
+            args: Vec::new()
+        }
+    ];
+    let index = basicblocks.len();
+    basicblocks.last_mut().unwrap().nexts = vec![index, index + 1];
+    basicblocks.push(BasicBlock {
+        statements,
+        nexts: vec![index + 1]
+    });
+    basicblocks.push(BasicBlock {
+        statements: Vec::new(),
+        nexts: Vec::new()
+    });
+    true
+}
+
+struct StripAsserts;
+
+impl Folder for StripAsserts {
+    fn fold_stmt(&mut self, stmt: Stmt) -> Option<Vec<Stmt>> {
+        match stmt {
+            Stmt::Assert{..} => Some(Vec::new()),
+            _ => Some(vec![visit::fold_stmt(self, stmt)?])
+        }
+    }
+}
+
+// Splits the i32 range into disjoint segments, each taken by the first arm
+// covering it. Uncovered gaps go to `default` (None means the join block).
+// Over an enum, only its variants need to be covered.
+fn segments(arms: &[Vec<Pattern>], domain: Option<(&str, &Vec<(String, i32)>)>)
+        -> Vec<(i64, i64, Option<usize>)> {
+    let mut res: Vec<(i64, i64, Option<usize>)> = Vec::new();
+    let mut default = None;
+    for (i, patterns) in arms.iter().enumerate() {
+        let mut reachable = false;
+        for pattern in patterns {
+            let (lo, hi) = match pattern {
+                Pattern::Range(lo, hi) => (lo.value() as i64, hi.value() as i64),
+                Pattern::Wildcard => (i32::MIN as i64, i32::MAX as i64),
+                Pattern::Variant(_) => unreachable!()
+            };
+            let mut covered = res.iter()
+                .filter(|(l, h, _)| *h >= lo && *l <= hi)
+                .map(|(l, h, _)| (*l, *h))
+                .collect::<Vec<_>>();
+            covered.sort();
+            let mut cur = lo;
+            for (l, h) in covered {
+                if l > cur {
+                    res.push((cur, l - 1, Some(i)));
+                    reachable = true;
+                }
+                cur = cur.max(h + 1);
+            }
+            if cur <= hi {
+                res.push((cur, hi, Some(i)));
+                reachable = true;
+            }
+            if *pattern == Pattern::Wildcard && default.is_none() {
+                default = Some(i);
+            }
+        }
+        if !reachable {
+            eprintln!("Warning: match arm {} is unreachable", i);
+        }
+    }
+    res.sort();
+    let mut full = Vec::new();
+    let mut cur = i32::MIN as i64;
+    for (lo, hi, target) in res {
+        if lo > cur {
+            full.push((cur, lo - 1, None));
+        }
+        full.push((lo, hi, target));
+        cur = hi + 1;
+    }
+    if cur <= i32::MAX as i64 {
+        full.push((cur, i32::MAX as i64, None));
+    }
+    let uncovered = |value: i64| full.iter().any(|(lo, hi, target)| *lo <= value && value <= *hi && target.is_none());
+    match domain {
+        Some((name, variants)) => {
+            if let Some((variant, _)) = variants.iter().find(|(_, value)| uncovered(*value as i64)) {
+                eprintln!("Warning: match is not exhaustive, {}::{} is not covered", name, variant);
+            }
+        }
+        None => {
+            if let Some((lo, _, _)) = full.iter().find(|(_, _, target)| target.is_none()) {
+                eprintln!("Warning: match is not exhaustive, {} is not covered", lo);
+            }
+        }
+    }
+    let mut merged: Vec<(i64, i64, Option<usize>)> = Vec::new();
+    for (lo, hi, target) in full {
+        match merged.last_mut() {
+            Some(last) if last.2 == target => last.1 = hi,
+            _ => merged.push((lo, hi, target))
+        }
+    }
+    merged
+}
+
+// Emits a comparison tree over segs in preorder, starting in basicblocks[at].
+fn compare_tree(segs: &[(i64, i64, usize)], scrutinee: Value,
+                basicblocks: &mut Vec<BasicBlock>, at: usize, reg: &mut usize) {
+    if segs.len() == 1 {
+        basicblocks[at].statements.push(Statement {
+            op: Operator::Jump,
+            ret: None,
+            args: Vec::new()
+        });
+        basicblocks[at].nexts.push(segs[0].2);
+        return;
+    }
+    let mid = segs.len() / 2;
+    basicblocks[at].statements.push(Statement {
+        op: Operator::LessThan,
+        ret: Some(*reg),
+        args: vec![scrutinee, Value::Immediate(segs[mid].0 as i32)]
+    });
+    basicblocks[at].statements.push(Statement {
+        op: Operator::If,
+        ret: None,
+        args: vec![Value::Register(*reg)]
+    });
+    *reg += 1;
+    let then = basicblocks.len();
+    basicblocks.push(BasicBlock { statements: Vec::new(), nexts: Vec::new() });
+    compare_tree(&segs[..mid], scrutinee, basicblocks, then, reg);
+    let otherwise = basicblocks.len();
+    basicblocks.push(BasicBlock { statements: Vec::new(), nexts: Vec::new() });
+    basicblocks[at].nexts = vec![then, otherwise];
+    compare_tree(&segs[mid..], scrutinee, basicblocks, otherwise, reg);
+}
+
+fn match_op(scrutinee: &Expr, arms: &[Arm], program: &Program,
+            func: &Function, vars: &mut HashMap<String, usize>,
+            basicblocks: &mut Vec<BasicBlock>, regcount: usize) -> bool {
+    let scrutinee = match expression(scrutinee, program, vars,
+                                     &mut basicblocks.last_mut().unwrap().statements,
+                                     regcount) {
+        Some(id) => id,
+        None => {
+            println!("Invalid expression");
+            return false
+        }
+    };
+    let mut domain = None;
+    let mut resolved = Vec::new();
+    for arm in arms {
+        let mut values = Vec::new();
+        for pattern in &arm.patterns {
+            match pattern {
+                Pattern::Variant(name) => {
+                    let value = match variant(name, program) {
+                        Some(value) => value,
+                        None => {
+                            println!("Unknown enum variant {}", name);
+                            return false
+                        }
+                    };
+                    let owner = &name[..name.rfind("::").unwrap()];
+                    match domain {
+                        None => domain = Some(Some(owner)),
+                        Some(Some(other)) if other != owner => domain = Some(None),
+                        _ => {}
+                    }
+                    values.push(Pattern::Range(Literal::Int(value), Literal::Int(value)));
+                }
+                Pattern::Range(..) => {
+                    domain = Some(None);
+                    values.push(pattern.clone());
+                }
+                Pattern::Wildcard => values.push(pattern.clone())
+            }
+        }
+        resolved.push(values);
+    }
+    let domain = domain.flatten().map(|name| (name, program.enums.get(name).unwrap()));
+    let segs = segments(&resolved, domain);
+    let start = basicblocks.len() - 1;
+    let tree = 2 * (segs.len() - 1);
+    let mut reg = block_registers(basicblocks, program, regcount);
+    let mut newregcount = reg + segs.len() - 1;
+    let mut bodies = Vec::new();
+    let mut offset = start + 1 + tree;
+    let mut offsets = Vec::new();
+    for arm in arms {
+        match statement(&arm.body, program, func, &mut vars.clone(), newregcount) {
+            Some(vb) => {
+                newregcount = block_registers(&vb, program, newregcount);
+                offsets.push(offset);
+                offset += vb.len();
+                bodies.push(vb);
+            }
+            None => {
+                println!("Invalid statements");
+                return false
+            }
+        }
+    }
+    let join = offset;
+    let segs: Vec<(i64, i64, usize)> = segs.into_iter()
+        .map(|(lo, hi, target)| (lo, hi, target.map_or(join, |i| offsets[i])))
+        .collect();
+    compare_tree(&segs, scrutinee, basicblocks, start, &mut reg);
+    for (vb, offset) in bodies.into_iter().zip(offsets) {
+        let len = vb.len();
+        for (i, b) in vb.into_iter().enumerate() {
+            let mut statements = b.statements;
+            let mut nexts: Vec<usize> = b.nexts.iter().map(|bid| bid + offset).collect();
+            if i == len - 1 {
+                statements.push(Statement {
+                    op: Operator::Jump,
+                    ret: None,
+                    args: Vec::new()
+                });
+                nexts = vec![join];
+            }
+            basicblocks.push(BasicBlock { statements, nexts });
+        }
+    }
+    basicblocks.push(BasicBlock {
+        statements: Vec::new(),
+        nexts: Vec::new()
+    });
+    true
+}
+
+// Checks that straight-line Piet code turns `inputs` values on top of the
+// stack into `outputs` values without touching anything below them.
+fn stack_effect(instructions: &[String], inputs: usize, outputs: usize) -> bool {
+    let mut stack: Vec<Option<i32>> = vec![None; inputs];
+    for inst in instructions {
+        let mut words = inst.split_whitespace();
+        let (pops, pushes) = match words.next().unwrap() {
+            "PUSH" => {
+                stack.push(words.next().and_then(|imm| imm.parse().ok()));
+                continue;
+            }
+            "POP" | "OUTN" | "OUTC" => (1, 0),
+            "ADD" | "SUB" | "MUL" | "DIV" | "MOD" | "GREATER" => (2, 1),
+            "NOT" => (1, 1),
+            "DUP" => (1, 2),
+            "SWAP" => (2, 2),
+            "INN" | "INC" => (0, 1),
+            "ROLL" => {
+                if stack.len() < 2 {
+                    println!("asm: ROLL needs 2 values, but the stack has {}", stack.len());
+                    return false;
+                }
+                stack.pop();
+                match stack.pop().unwrap() {
+                    Some(depth) if depth >= 0 && depth as usize <= stack.len() => {}
+                    Some(depth) => {
+                        println!("asm: ROLL depth {} exceeds the {} values of the block", depth, stack.len());
+                        return false;
+                    }
+                    None => {
+                        println!("asm: ROLL depth must be pushed as a constant");
+                        return false;
+                    }
+                }
+                for slot in stack.iter_mut() {
+                    *slot = None;
+                }
+                continue;
+            }
+            "LABEL" | "JMP" | "JEZ" | "HALT" => {
+                println!("asm: control flow instruction {} is not allowed", inst);
+                return false;
+            }
+            other => {
+                println!("asm: unknown instruction {}", other);
+                return false;
+            }
+        };
+        if stack.len() < pops {
+            println!("asm: {} needs {} values, but the stack has {}", inst, pops, stack.len());
+            return false;
+        }
+        let len = stack.len() - pops;
+        stack.truncate(len);
+        for _ in 0..pushes {
+            stack.push(None);
+        }
+    }
+    if stack.len() != outputs {
+        println!("asm: block declares {} outputs, but leaves {} values", outputs, stack.len());
+        return false;
+    }
+    true
+}
+
+fn asm_op(lines: &[Vec<String>], outputs: &[String], inputs: &[Expr],
+          program: &Program, vars: &mut HashMap<String, usize>,
+          statements: &mut Vec<Statement>, regcount: usize) -> bool {
+    let instructions = lines.concat();
+    if !stack_effect(&instructions, inputs.len(), outputs.len()) {
+        return false;
+    }
+    let mut vec_id = Vec::new();
+    for input in inputs {
+        match expression(input, program, vars, statements, regcount) {
+            Some(id) => vec_id.push(id),
+            None => {
+                println!("Invalid expression");
+                return false
+            }
+        }
+    }
+    let id = next_register(statements, program, regcount);
+    for (i, output) in outputs.iter().enumerate() {
+        if vars.contains_key(output) {
+            println!("Variable {} is already defined.", output);
+            return false;
+        }
+        vars.insert(output.to_string(), id + i);
+    }
+    statements.push(Statement {
+        op: Operator::Asm{instructions, outputs: outputs.to_vec()},
+        ret: if outputs.is_empty() { None } else { Some(id) },
+        args: vec_id
+    });
+    true
+}
+
+fn print_op(pieces: &[Piece], args: &[Expr], program: &Program,
+            vars: &mut HashMap<String, usize>, statements: &mut Vec<Statement>,
+            regcount: usize) -> bool {
+    let mut vec_id = Vec::new();
+    for arg in args {
+        match expression(arg, program, vars, statements, regcount) {
+            Some(id) => vec_id.push(id),
+            None => {
+                println!("Invalid expression");
+                return false
+            }
+        }
+    }
+    statements.push(Statement {
+        op: Operator::Print{pieces: pieces.to_vec()},
+        ret: None,
+        args: vec_id
+    });
+    true
+}
+
+fn return_op(value: &Option<Expr>, program: &Program, func: &Function,
              vars: &mut HashMap<String, usize>, statements: &mut Vec<Statement>,
              regcount: usize) -> bool {
+    if let Some(ref rettype) = func.rettype {
+        let value = match value {
+            Some(value) => value,
+            None => {
+                println!("Function {} must return one {}", func.name, rettype);
+                return false;
+            }
+        };
+        let vec_id = match conform(value, &func.rettype, program, vars,
+                                   statements, regcount) {
+            Some(ids) => ids,
+            None => {
+                println!("Invalid expression");
+                return false
+            }
+        };
+        statements.push(Statement {
+            op: Operator::Return,
+            ret: None,
+            args: vec_id
+        });
+        return true;
+    }
     let mut vec_id = Vec::new();
-    for child in children {
-        let id = match expression(child, program, vars,
+    if let Some(value) = value {
+        let id = match expression(value, program, vars,
                                   statements, regcount) {
             Some(id) => id,
             None => {
@@ -328,157 +1240,296 @@ fn return_op(children: &Vec<AST>, program: &Program,
     true
 }
 
-
-fn statement_impl(ast: &AST, program: &Program,
+fn statement_impl(stmt: &Stmt, program: &Program, func: &Function,
                   vars: &mut HashMap<String, usize>,
                   basicblocks: &mut Vec<BasicBlock>, regcount: usize)
         -> bool {
-    match ast {
-        AST::Node(node) => {
-            match node.op {
-                Operator::Substitute => {
-                    substitute(&node.children, program, vars,
-                               &mut basicblocks.last_mut().unwrap().statements,
-                               regcount).is_some()
-                }
-                Operator::Call{ref name} => {
-                    call(&name, &node.children, program, vars,
-                         &mut basicblocks.last_mut().unwrap().statements,
-                         regcount).is_some()
-                }
-                Operator::If => {
-                    if_op(&node.children, program, vars, basicblocks, regcount)
-                }
-                Operator::Return => {
-                    return_op(&node.children, program, vars,
-                              &mut basicblocks.last_mut().unwrap().statements,
-                              regcount)
-                }
-                _ => {
-                    println!("Unknwon operator: {:?}", node.op);
-                    false
-                }
+    let lifted = if stmt.exprs().into_iter().any(has_conditional) {
+        match lift_statement(stmt, program, func, vars, basicblocks, regcount) {
+            Some(stmt) => Some(stmt),
+            None => return false
+        }
+    } else {
+        None
+    };
+    let stmt = lifted.as_ref().unwrap_or(stmt);
+    let regcount = block_registers(basicblocks, program, regcount);
+    match stmt {
+        Stmt::Substitute{ref name, ref value} => {
+            substitute(name, value, program, vars,
+                       &mut basicblocks.last_mut().unwrap().statements,
+                       regcount).is_some()
+        }
+        Stmt::Update{ref name, ref op, ref value} => {
+            update(name, op, value, program, vars,
+                   &mut basicblocks.last_mut().unwrap().statements,
+                   regcount).is_some()
+        }
+        Stmt::Call(Call{ref name, ref args}) if !program.funcs.contains_key(name) && vars.contains_key(name) => {
+            call_indirect(name, args, 0, program, vars,
+                          &mut basicblocks.last_mut().unwrap().statements,
+                          regcount).is_some()
+        }
+        Stmt::Call(Call{ref name, ref args}) => {
+            call(name, args, program, vars,
+                 &mut basicblocks.last_mut().unwrap().statements,
+                 regcount).is_some()
+        }
+        Stmt::If{ref cond, ref then, ref otherwise} => {
+            if_op(cond, (then, otherwise), program, func, vars, basicblocks, regcount)
+        }
+        Stmt::Match{ref scrutinee, ref arms} => {
+            match_op(scrutinee, arms, program, func, vars, basicblocks, regcount)
+        }
+        Stmt::Return(ref value) => {
+            return_op(value, program, func, vars,
+                      &mut basicblocks.last_mut().unwrap().statements,
+                      regcount)
+        }
+        Stmt::Asm{ref inputs, ref instructions, ref outputs} => {
+            asm_op(instructions, outputs, inputs, program, vars,
+                   &mut basicblocks.last_mut().unwrap().statements,
+                   regcount)
+        }
+        Stmt::Print{ref pieces, ref args} => {
+            print_op(pieces, args, program, vars,
+                     &mut basicblocks.last_mut().unwrap().statements,
+                     regcount)
+        }
+        Stmt::Assert{ref cond, ref file, line, ref message} => {
+            let mut text = format!("{}:{}: assertion failed", file, line);
+            if let Some(message) = message {
+                text = format!("{}: {}", text, message);
             }
+            assert_op(&text, cond, program, vars, basicblocks, regcount)
         }
-        AST::Leaf(_leaf) => {
-            println!("Invalid identifier or constant");
+        Stmt::Fn(ref decl) => {
+            println!("Nested function {} was not lifted", decl.name);
             false
         }
+        Stmt::Comment(_) => true
     }
 }
 
-fn statement(ast: &AST, program: &Program,
+fn statement(body: &[Stmt], program: &Program, func: &Function,
              vars: &mut HashMap<String, usize>, regcount: usize)
         -> Option<Vec<BasicBlock>> {
     let mut basicblocks = vec![BasicBlock {
         statements: Vec::new(),
         nexts: Vec::new()
     }];
-    match ast {
-        AST::Node(node) => {
-            match node.op {
-                Operator::Statement => {
-                    for child in &node.children {
-                        if !statement_impl(&child, program, vars, &mut basicblocks, regcount) {
-                            return None;
-                        }
-                    }
-                    Some(basicblocks)
-                }
-                _ => {
-                    println!("Unexpected operator: {:?}, expected Statement", node.op);
-                    None
+    for stmt in body {
+        if !statement_impl(stmt, program, func, vars, &mut basicblocks, regcount) {
+            return None;
+        }
+    }
+    Some(basicblocks)
+}
+
+fn pre_declare_function(decl: &FnDecl, program: &Program) -> Option<Function> {
+    let mut flat_args = Vec::new();
+    for (arg, ty) in decl.args.iter().zip(decl.types.iter()) {
+        for path in layout(ty, program, 0)? {
+            flat_args.push(format!("{}{}", arg, path));
+        }
+    }
+    let retnum = match decl.rettype {
+        Some(_) => layout(&decl.rettype, program, 0)?.len(),
+        None => decl.retnum
+    };
+    Some(Function {
+        name: decl.name.to_string(),
+        args: flat_args,
+        types: decl.types.to_vec(),
+        retnum,
+        rettype: decl.rettype.clone(),
+        constant: decl.constant,
+        inline: decl.inline,
+        basicblocks: Vec::new()
+    })
+}
+
+fn is_self_tail_call(func: &Function, statements: &[Statement]) -> bool {
+    match statements {
+        [.., Statement{op: Operator::Call{ref name}, ret: Some(ret), ..},
+             Statement{op: Operator::Return, args, ..}] if *name == func.name => {
+            args.len() == func.retnum &&
+                args.iter().enumerate().all(|(i, arg)| *arg == Value::Register(ret + i))
+        }
+        _ => false
+    }
+}
+
+// Turns `return f(...)` inside f into a loop: the new arguments overwrite the
+// argument registers and control jumps back to the entry block.
+fn self_tail_calls(func: &mut Function, program: &Program) {
+    let mut regcount = block_registers(&func.basicblocks, program, func.args.len());
+    for index in 0..func.basicblocks.len() {
+        if !is_self_tail_call(func, &func.basicblocks[index].statements) {
+            continue;
+        }
+        let block = &mut func.basicblocks[index];
+        block.statements.pop();
+        let args = block.statements.pop().unwrap().args;
+        let mut values = Vec::new();
+        for (i, arg) in args.into_iter().enumerate() {
+            match arg {
+                Value::Register(reg) if reg < func.args.len() && reg != i => {
+                    block.statements.push(Statement {
+                        op: Operator::Substitute,
+                        ret: Some(regcount),
+                        args: vec![arg]
+                    });
+                    values.push(Value::Register(regcount));
+                    regcount += 1;
                 }
+                _ => values.push(arg)
             }
         }
-        AST::Leaf(_leaf) => {
-            println!("Invalid identifier or constant");
-            None
+        for (i, value) in values.into_iter().enumerate() {
+            if value == Value::Register(i) {
+                continue;
+            }
+            block.statements.push(Statement {
+                op: Operator::Substitute,
+                ret: Some(i),
+                args: vec![value]
+            });
         }
+        block.statements.push(Statement {
+            op: Operator::Jump,
+            ret: None,
+            args: Vec::new()
+        });
+        block.nexts = vec![0];
     }
 }
 
-fn pre_declare_function(node: &Node, program: &Program) -> Option<Function> {
-    match node.op {
-        Operator::FunctionDeclare{ref name, ref args, retnum} => {
-            Some(Function { name: name.to_string(), args: args.to_vec(), retnum, basicblocks: Vec::new() })
+fn function(node: &FnDecl, program: &Program) -> Option<Function> {
+    let name = &node.name;
+    let decl = program.funcs.get(name).unwrap();
+    let mut vars = HashMap::<String, usize>::new();
+    for (i, arg) in decl.args.iter().enumerate() {
+        vars.insert(arg.to_string(), i);
+    }
+    let regcount = decl.args.len();
+    match statement(&node.body, program, decl, &mut vars, regcount) {
+        Some(mut basicblocks) => {
+            if decl.retnum == 0 && name != "main" {
+                basicblocks.last_mut().unwrap().statements.push(Statement {
+                    op: Operator::Return,
+                    ret: None,
+                    args: Vec::new()
+                });
+            }
+            let mut func = Function { basicblocks, ..decl.clone() };
+            self_tail_calls(&mut func, program);
+            Some(func)
         }
-        _ => {
-            println!("Unexpected operator: {:?}, expected FunctionDeclare", node.op);
+        None => {
+            println!("Invalid statements");
             None
         }
     }
 }
 
-fn function(node: &Node, program: &Program) -> Option<Function> {
-    match node.op {
-        Operator::FunctionDeclare{ref name, ref args, retnum} => {
-            let mut vars = HashMap::<String, usize>::new();
-            for (i, arg) in args.iter().enumerate() {
-                vars.insert(arg.to_string(), i);
+fn enum_declare(decl: &EnumDecl, program: &mut Program) -> bool {
+    if program.enums.contains_key(&decl.name) {
+        println!("Enum {} is already defined.", decl.name);
+        return false;
+    }
+    let mut variants = Vec::new();
+    let mut value: i32 = 0;
+    for (name, explicit) in &decl.variants {
+        if let Some(explicit) = explicit {
+            value = explicit.value();
+        }
+        variants.push((name.to_string(), value));
+        value = value.wrapping_add(1);
+    }
+    program.enums.insert(decl.name.to_string(), variants);
+    true
+}
+
+fn struct_declare(decl: &StructDecl, program: &mut Program) -> bool {
+    let StructDecl{ref name, ref fields, ref types, ..} = *decl;
+    if program.structs.contains_key(name) {
+        println!("Struct {} is already defined.", name);
+        return false;
+    }
+    for (i, field) in fields.iter().enumerate() {
+        if fields[..i].contains(field) {
+            println!("Field {} of struct {} is already defined.", field, name);
+            return false;
+        }
+    }
+    program.structs.insert(name.to_string(), Struct {
+        fields: fields.to_vec(),
+        types: types.to_vec()
+    });
+    true
+}
+
+// A const fn may only do what the evaluator can, so it calls const fns only.
+struct CheckConst<'a> {
+    name: &'a str,
+    program: &'a Program,
+    ok: bool
+}
+
+impl Visitor for CheckConst<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if !self.ok {
+            return;
+        }
+        match stmt {
+            Stmt::Asm{..} => {
+                println!("const fn {} cannot contain asm", self.name);
+                self.ok = false;
             }
-            let regcount = args.len();
-            match statement(&node.children[0], program, &mut vars, regcount) {
-                Some(mut basicblocks) => {
-                    if retnum == 0 && name != "main" {
-                        basicblocks.last_mut().unwrap().statements.push(Statement {
-                            op: Operator::Return,
-                            ret: None,
-                            args: Vec::new()
-                        });
-                    }
-                    Some(Function { name: name.to_string(), args: args.to_vec(), retnum, basicblocks })
-                }
-                None => {
-                    println!("Invalid statements");
-                    None
-                }
+            Stmt::Print{..} => {
+                println!("const fn {} cannot print", self.name);
+                self.ok = false;
             }
+            _ => visit::walk_stmt(self, stmt)
         }
-        _ => {
-            println!("Unexpected operator: {:?}, expected FunctionDeclare", node.op);
-            None
+    }
+
+    fn visit_call(&mut self, call: &Call) {
+        if !self.ok {
+            return;
+        }
+        match self.program.funcs.get(&call.name) {
+            Some(func) if !func.constant => {
+                println!("const fn {} calls {}, which is not const", self.name, call.name);
+                self.ok = false;
+            }
+            _ => visit::walk_call(self, call)
         }
     }
 }
 
-fn pre_declare(ast: &AST, program: &mut Program) -> bool {
-    match ast {
-        AST::Node(node) => {
-            match pre_declare_function(node, program) {
-                Some(func) => {
-                    program.funcs.insert(func.name.clone(), func);
-                    true
-                }
-                None => {
-                    println!("Invalid function declare");
-                    false
-                }
-            }
+fn pre_declare(decl: &FnDecl, program: &mut Program) -> bool {
+    match pre_declare_function(decl, program) {
+        Some(func) => {
+            program.funcs.insert(func.name.clone(), func);
+            true
         }
-        AST::Leaf(_leaf) => {
-            println!("Invalid identifier or constant");
+        None => {
+            println!("Invalid function declare");
             false
         }
     }
 }
 
-fn declare(ast: &AST, program: &mut Program) -> bool {
-    match ast {
-        AST::Node(node) => {
-            match function(node, program) {
-                Some(func) => {
-                    program.funcs.insert(func.name.clone(), func);
-                    true
-                }
-                None => {
-                    println!("Invalid function declare");
-                    false
-                }
-            }
+fn declare(decl: &FnDecl, program: &mut Program) -> bool {
+    match function(decl, program) {
+        Some(func) => {
+            program.funcs.insert(func.name.clone(), func);
+            true
         }
-        AST::Leaf(_leaf) => {
-            println!("Invalid identifier or constant");
+        None => {
+            println!("Invalid function declare");
             false
         }
     }
@@ -489,31 +1540,71 @@ impl Program {
         let getnum = Function {
             name: "getnum".to_string(),
             args: Vec::new(),
+            types: Vec::new(),
             retnum: 1,
+            rettype: None,
+            constant: false,
+            inline: Inline::Auto,
             basicblocks: Vec::new()
         };
         let getchar = Function {
             name: "getchar".to_string(),
             args: Vec::new(),
+            types: Vec::new(),
             retnum: 1,
+            rettype: None,
+            constant: false,
+            inline: Inline::Auto,
             basicblocks: Vec::new()
         };
         let putnum = Function {
             name: "putnum".to_string(),
             args: vec!["x".to_string()],
+            types: vec![None; 1],
             retnum: 0,
+            rettype: None,
+            constant: false,
+            inline: Inline::Auto,
             basicblocks: Vec::new()
         };
         let putchar = Function {
             name: "putchar".to_string(),
             args: vec!["x".to_string()],
+            types: vec![None; 1],
             retnum: 0,
+            rettype: None,
+            constant: false,
+            inline: Inline::Auto,
             basicblocks: Vec::new()
         };
         let halt = Function {
             name: "halt".to_string(),
             args: Vec::new(),
+            types: Vec::new(),
+            retnum: 0,
+            rettype: None,
+            constant: false,
+            inline: Inline::Auto,
+            basicblocks: Vec::new()
+        };
+        let load = Function {
+            name: "load".to_string(),
+            args: vec!["p".to_string()],
+            types: vec![None; 1],
+            retnum: 1,
+            rettype: None,
+            constant: false,
+            inline: Inline::Auto,
+            basicblocks: Vec::new()
+        };
+        let store = Function {
+            name: "store".to_string(),
+            args: vec!["p".to_string(), "x".to_string()],
+            types: vec![None; 2],
             retnum: 0,
+            rettype: None,
+            constant: false,
+            inline: Inline::Auto,
             basicblocks: Vec::new()
         };
         let mut funcs = HashMap::<String, Function>::new();
@@ -522,38 +1613,138 @@ impl Program {
         funcs.insert(putnum.name.clone(), putnum);
         funcs.insert(putchar.name.clone(), putchar);
         funcs.insert(halt.name.clone(), halt);
-        Program { funcs }
+        funcs.insert(load.name.clone(), load);
+        funcs.insert(store.name.clone(), store);
+        Program { funcs, structs: HashMap::new(), enums: HashMap::new(), labels: Vec::new() }
     }
 }
 
-pub fn generate(ast: &AST) -> Option<Program> {
+// Collects the functions whose address is taken with `&name`.
+struct References<'a> {
+    res: &'a mut Vec<String>
+}
+
+impl Visitor for References<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Function(name) = expr {
+            if !self.res.contains(name) {
+                self.res.push(name.to_string());
+            }
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+struct CallsAny<'a> {
+    names: &'a [&'a str],
+    found: bool
+}
+
+impl Visitor for CallsAny<'_> {
+    fn visit_call(&mut self, call: &Call) {
+        self.found |= self.names.contains(&&*call.name);
+        visit::walk_call(self, call);
+    }
+}
+
+fn calls_any(decl: &FnDecl, names: &[&str]) -> bool {
+    let mut visitor = CallsAny { names, found: false };
+    visitor.visit_fn(decl);
+    visitor.found
+}
+
+pub fn generate(ast: &parse::Program, asserts: bool) -> Option<Program> {
     let mut program = Program::new();
-    match ast {
-        AST::Node(node) => {
-            match node.op {
-                Operator::Declare => {
-                    for child in &node.children {
-                        if !pre_declare(child, &mut program) {
-                            return None;
-                        }
-                    }
-                    for child in &node.children {
-                        if !declare(child, &mut program) {
-                            return None;
-                        }
-                    }
-                    Some(program)
+    let builtins: Vec<String> = program.funcs.keys().cloned().collect();
+    let mut decls = ast.decls.clone();
+    let heap = match runtime::heap() {
+        Some(heap) => heap,
+        None => {
+            println!("Failed to load heap runtime");
+            return None;
+        }
+    };
+    // The runtime is merged in by name, so alloc and free cannot be redefined.
+    for decl in &decls {
+        if let Decl::Fn(func) = decl {
+            if runtime::FUNCTIONS.contains(&func.name.as_str()) {
+                println!("Function {} is defined by the heap runtime and cannot be redefined", func.name);
+                return None;
+            }
+        }
+    }
+    if decls.iter().any(|decl| matches!(decl, Decl::Fn(func) if calls_any(func, &runtime::FUNCTIONS))) {
+        decls.extend(heap.decls);
+    }
+    let mut funcs = Vec::new();
+    for decl in decls {
+        match decl {
+            Decl::Struct(decl) => {
+                if !struct_declare(&decl, &mut program) {
+                    return None;
                 }
-                _ => {
-                    println!("Unexpected operator {:?}, expected Declare", node.op);
-                    None
+            }
+            Decl::Enum(decl) => {
+                if !enum_declare(&decl, &mut program) {
+                    return None;
                 }
             }
+            Decl::Fn(decl) => {
+                if asserts {
+                    funcs.push(decl);
+                } else {
+                    funcs.push(StripAsserts.fold_fn(decl)?);
+                }
+            }
+            Decl::Import{path} => {
+                println!("Import {} was not resolved", path);
+                return None;
+            }
+            Decl::Comment(_) => {}
         }
-        AST::Leaf(_leaf) => {
-            println!("Invalid identifier or constant");
-            None
+    }
+    for name in program.structs.keys() {
+        layout(&Some(name.to_string()), &program, 0)?;
+    }
+    for decl in &funcs {
+        if !pre_declare(decl, &mut program) {
+            return None;
         }
     }
+    for decl in &funcs {
+        References { res: &mut program.labels }.visit_fn(decl);
+    }
+    program.labels.sort();
+    for label in &program.labels {
+        match program.funcs.get(label) {
+            Some(func) if builtins.contains(label) => {
+                println!("Cannot take the address of builtin {}", func.name);
+                return None;
+            }
+            Some(func) if func.rettype.is_some() || func.types.iter().any(|ty| ty.is_some()) => {
+                println!("Cannot take the address of {}, it takes or returns a struct", func.name);
+                return None;
+            }
+            Some(_func) => {}
+            None => {
+                println!("Function {} is not defined.", label);
+                return None;
+            }
+        }
+    }
+    // Const fns come first so that calls to them can be evaluated.
+    funcs.sort_by_key(|decl| !decl.constant);
+    for decl in &funcs {
+        if decl.constant {
+            let mut visitor = CheckConst { name: &decl.name, program: &program, ok: true };
+            visitor.visit_fn(decl);
+            if !visitor.ok {
+                return None;
+            }
+        }
+        if !declare(decl, &mut program) {
+            return None;
+        }
+    }
+    Some(program)
 }
-
@@ -0,0 +1,321 @@
+use super::tokenize;
+use super::parse;
+use super::parse::Call;
+use super::parse::Expr;
+use super::parse::Stmt;
+use super::parse::FnDecl;
+use super::parse::StructDecl;
+use super::parse::EnumDecl;
+use super::parse::Decl;
+use super::parse::Pattern;
+use super::lambda;
+use super::visit;
+use super::visit::Folder;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+struct Module {
+    name: Option<String>,
+    path: PathBuf,
+    funcs: Vec<(String, bool)>,
+    structs: Vec<(String, bool)>,
+    enums: Vec<(String, bool)>,
+    decls: Vec<Decl>
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Func,
+    Struct,
+    Enum
+}
+
+fn qualify(module: &Option<String>, name: &str) -> String {
+    match module {
+        Some(module) => format!("{}::{}", module, name),
+        None => name.to_string()
+    }
+}
+
+fn table(module: &Module, kind: Kind) -> &Vec<(String, bool)> {
+    match kind {
+        Kind::Func => &module.funcs,
+        Kind::Struct => &module.structs,
+        Kind::Enum => &module.enums
+    }
+}
+
+fn resolve(name: &str, module: &Module, imports: &[&Module], kind: Kind) -> Option<String> {
+    if let Some(pos) = name.find("::") {
+        let (prefix, base) = (&name[..pos], &name[pos+2..]);
+        if module.name.as_deref() == Some(prefix) {
+            return resolve(base, module, &[], kind);
+        }
+        match imports.iter().find(|m| m.name.as_deref() == Some(prefix)) {
+            Some(m) => {
+                match table(m, kind).iter().find(|(n, _)| n == base) {
+                    Some((_, true)) => Some(name.to_string()),
+                    Some((_, false)) => {
+                        println!("{} is private in module {}", base, prefix);
+                        None
+                    }
+                    None => {
+                        println!("Module {} has no {}", prefix, base);
+                        None
+                    }
+                }
+            }
+            None => {
+                println!("Module {} is not imported", prefix);
+                None
+            }
+        }
+    } else if table(module, kind).iter().any(|(n, _)| n == name) {
+        Some(qualify(&module.name, name))
+    } else {
+        let found: Vec<&&Module> = imports.iter()
+            .filter(|m| table(m, kind).iter().any(|(n, public)| n == name && *public))
+            .collect();
+        match found.len() {
+            0 => Some(name.to_string()),
+            1 => Some(qualify(&found[0].name, name)),
+            _ => {
+                println!("{} is exported by several modules, qualify it with the module name", name);
+                None
+            }
+        }
+    }
+}
+
+// `Enum::Variant` is renamed through the enum it names.
+fn resolve_variant(name: &str, module: &Module, imports: &[&Module]) -> Option<String> {
+    match name.rfind("::") {
+        Some(pos) => {
+            Some(format!("{}::{}", resolve(&name[..pos], module, imports, Kind::Enum)?, &name[pos+2..]))
+        }
+        None => Some(name.to_string())
+    }
+}
+
+fn resolve_type(ty: &Option<String>, module: &Module, imports: &[&Module]) -> Option<Option<String>> {
+    match ty {
+        Some(name) => Some(Some(resolve(name, module, imports, Kind::Struct)?)),
+        None => Some(None)
+    }
+}
+
+fn resolve_types(types: &[Option<String>], module: &Module, imports: &[&Module]) -> Option<Vec<Option<String>>> {
+    let mut res = Vec::new();
+    for ty in types {
+        res.push(resolve_type(ty, module, imports)?);
+    }
+    Some(res)
+}
+
+// Qualifies the declarations of a module with its name and resolves every
+// name it uses against the module itself and its imports.
+struct Renamer<'a> {
+    module: &'a Module,
+    imports: &'a [&'a Module]
+}
+
+impl Folder for Renamer<'_> {
+    fn fold_fn(&mut self, decl: FnDecl) -> Option<FnDecl> {
+        let decl = FnDecl {
+            name: qualify(&self.module.name, &decl.name),
+            types: resolve_types(&decl.types, self.module, self.imports)?,
+            rettype: resolve_type(&decl.rettype, self.module, self.imports)?,
+            ..decl
+        };
+        visit::fold_fn(self, decl)
+    }
+
+    fn fold_struct(&mut self, decl: StructDecl) -> Option<StructDecl> {
+        Some(StructDecl {
+            name: qualify(&self.module.name, &decl.name),
+            types: resolve_types(&decl.types, self.module, self.imports)?,
+            ..decl
+        })
+    }
+
+    fn fold_enum(&mut self, decl: EnumDecl) -> Option<EnumDecl> {
+        Some(EnumDecl { name: qualify(&self.module.name, &decl.name), ..decl })
+    }
+
+    fn fold_stmt(&mut self, stmt: Stmt) -> Option<Vec<Stmt>> {
+        let stmt = match stmt {
+            Stmt::Assert{cond, line, message, ..} => {
+                Stmt::Assert{
+                    cond,
+                    file: self.module.path.file_name().unwrap().to_string_lossy().to_string(),
+                    line,
+                    message
+                }
+            }
+            _ => stmt
+        };
+        Some(vec![visit::fold_stmt(self, stmt)?])
+    }
+
+    fn fold_pattern(&mut self, pattern: Pattern) -> Option<Pattern> {
+        match pattern {
+            Pattern::Variant(name) => Some(Pattern::Variant(resolve_variant(&name, self.module, self.imports)?)),
+            _ => Some(pattern)
+        }
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Option<Expr> {
+        let expr = match expr {
+            Expr::Identifier(name) if name.contains("::") => {
+                Expr::Identifier(resolve_variant(&name, self.module, self.imports)?)
+            }
+            Expr::Function(name) => Expr::Function(resolve(&name, self.module, self.imports, Kind::Func)?),
+            Expr::StructLiteral{name, fields} => {
+                Expr::StructLiteral{name: resolve(&name, self.module, self.imports, Kind::Struct)?, fields}
+            }
+            _ => expr
+        };
+        visit::fold_expr(self, expr)
+    }
+
+    fn fold_call(&mut self, call: Call) -> Option<Call> {
+        let call = Call { name: resolve(&call.name, self.module, self.imports, Kind::Func)?, ..call };
+        visit::fold_call(self, call)
+    }
+}
+
+fn read(path: &Path) -> Option<parse::Program> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => {
+            println!("Cannot open {}", path.display());
+            return None;
+        }
+    };
+    let mut code = String::new();
+    if file.read_to_string(&mut code).is_err() {
+        println!("Cannot read {}", path.display());
+        return None;
+    }
+    let tokens = tokenize::tokenize(&code)?;
+    parse::parse(&tokens)
+}
+
+fn find(import: &str, dir: &Path, search: &[PathBuf]) -> Option<PathBuf> {
+    for base in Some(dir).into_iter().chain(search.iter().map(|p| p.as_path())) {
+        let candidate = base.join(import);
+        if candidate.is_file() {
+            return candidate.canonicalize().ok();
+        }
+    }
+    println!("Cannot find {} in {} or the search path", import, dir.display());
+    None
+}
+
+// Adds the types of the functions and structs of an imported module, whose
+// declarations are already qualified, under the names the importing module
+// may call them.
+fn imported_types(module: &Module, types: &mut lambda::Types) {
+    let prefix = format!("{}::", module.name.as_ref().unwrap());
+    for decl in &module.decls {
+        match decl {
+            Decl::Fn(decl) => {
+                if decl.public {
+                    types.funcs.insert(decl.name[prefix.len()..].to_string(), decl.rettype.clone());
+                }
+                types.funcs.insert(decl.name.to_string(), decl.rettype.clone());
+            }
+            Decl::Struct(decl) => {
+                let fields: Vec<(String, Option<String>)> = decl.fields.iter().cloned()
+                    .zip(decl.types.iter().cloned())
+                    .collect();
+                if decl.public {
+                    types.structs.insert(decl.name[prefix.len()..].to_string(), fields.clone());
+                }
+                types.structs.insert(decl.name.to_string(), fields);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn module(ast: parse::Program, path: PathBuf, name: Option<String>, search: &[PathBuf],
+          loading: &mut Vec<PathBuf>, modules: &mut Vec<Module>) -> Option<usize> {
+    loading.push(path.clone());
+    let dir = path.parent().unwrap().to_path_buf();
+    let mut imports = Vec::new();
+    for decl in &ast.decls {
+        if let Decl::Import{path: ref import} = decl {
+            let found = find(import, &dir, search)?;
+            if let Some(i) = loading.iter().position(|p| *p == found) {
+                let cycle: Vec<String> = loading[i..].iter().chain(Some(&found))
+                    .map(|p| p.display().to_string())
+                    .collect();
+                println!("Import cycle: {}", cycle.join(" -> "));
+                return None;
+            }
+            let stem = found.file_stem().unwrap().to_string_lossy().to_string();
+            let index = match modules.iter().position(|m| m.path == found) {
+                Some(index) => index,
+                None => {
+                    if let Some(other) = modules.iter().find(|m| m.name.as_deref() == Some(&*stem)) {
+                        println!("Module {} is imported from both {} and {}",
+                                 stem, other.path.display(), found.display());
+                        return None;
+                    }
+                    let imported = read(&found)?;
+                    module(imported, found, Some(stem), search, loading, modules)?
+                }
+            };
+            imports.push(index);
+        }
+    }
+    loading.pop();
+    let mut types = lambda::Types::default();
+    for m in imports.iter().map(|i| &modules[*i]) {
+        imported_types(m, &mut types);
+    }
+    let decls = lambda::lift_functions(ast.decls, types)?;
+    let mut res = Module { name, path, funcs: Vec::new(), structs: Vec::new(), enums: Vec::new(), decls: Vec::new() };
+    for decl in &decls {
+        match decl {
+            Decl::Fn(decl) => res.funcs.push((decl.name.to_string(), decl.public)),
+            Decl::Struct(decl) => res.structs.push((decl.name.to_string(), decl.public)),
+            Decl::Enum(decl) => res.enums.push((decl.name.to_string(), decl.public)),
+            Decl::Import{..} | Decl::Comment(_) => {}
+        }
+    }
+    {
+        let imported: Vec<&Module> = imports.iter().map(|i| &modules[*i]).collect();
+        let mut renamer = Renamer { module: &res, imports: &imported };
+        let mut renamed = Vec::new();
+        for decl in decls {
+            match decl {
+                Decl::Import{..} => {}
+                _ => renamed.push(renamer.fold_decl(decl)?)
+            }
+        }
+        res.decls = renamed;
+    }
+    modules.push(res);
+    Some(modules.len() - 1)
+}
+
+pub fn resolve_imports(ast: parse::Program, path: &Path, search: &[PathBuf]) -> Option<parse::Program> {
+    let root = match path.canonicalize() {
+        Ok(root) => root,
+        Err(_) => {
+            println!("Cannot open {}", path.display());
+            return None;
+        }
+    };
+    let mut modules = Vec::new();
+    module(ast, root, None, search, &mut Vec::new(), &mut modules)?;
+    let mut decls = Vec::new();
+    for m in modules {
+        decls.extend(m.decls);
+    }
+    Some(parse::Program { decls })
+}
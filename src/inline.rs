@@ -0,0 +1,176 @@
+use super::ssa::Reg;
+use super::ssa::Value;
+use super::ssa::Op;
+use super::ssa::Statement;
+use super::ssa::Terminator;
+use super::ssa::Block;
+use super::ssa::Function;
+use super::ssa::Program;
+use super::parse::Inline;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// Function inlining over the SSA form. A direct call to a function that
+// cannot reach itself through calls is replaced by a copy of its body when
+// the function is small or marked #[inline], and never when it is marked
+// #[noinline]. The block holding the call jumps to the copy of the entry
+// block with the arguments, and each return jumps with its results to the
+// rest of the calling block, which takes the results of the call as its
+// parameters.
+
+// The most statements and terminators a function without #[inline] may have
+// to be inlined.
+pub const MAX_COST: usize = 12;
+
+fn cost(func: &Function) -> usize {
+    func.blocks.iter().map(|block| block.statements.len() + 1).sum()
+}
+
+fn callees(func: &Function) -> Vec<&str> {
+    let mut res = Vec::new();
+    for block in &func.blocks {
+        for statement in &block.statements {
+            if let Op::Call{ref name} = statement.op {
+                res.push(name.as_str());
+            }
+        }
+    }
+    res
+}
+
+fn reaches(program: &Program, from: &str, to: &str) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = vec![from];
+    while let Some(name) = stack.pop() {
+        if let Some(func) = program.funcs.get(name) {
+            for callee in callees(func) {
+                if callee == to {
+                    return true;
+                }
+                if seen.insert(callee) {
+                    stack.push(callee);
+                }
+            }
+        }
+    }
+    false
+}
+
+fn inlinable(program: &Program, name: &str, recursive: &HashSet<String>) -> bool {
+    let func = match program.funcs.get(name) {
+        Some(func) => func,
+        None => return false
+    };
+    if func.blocks.is_empty() || recursive.contains(name) {
+        return false;
+    }
+    match func.inline {
+        Inline::Always => true,
+        Inline::Never => false,
+        Inline::Auto => cost(func) <= MAX_COST
+    }
+}
+
+fn inline_call(func: &mut Function, block: usize, index: usize, callee: &Function) {
+    let mut regs: HashMap<Reg, Reg> = HashMap::new();
+    for b in &callee.blocks {
+        for reg in b.params.iter().chain(b.statements.iter().flat_map(|s| s.rets.iter())) {
+            regs.insert(*reg, func.regs.alloc());
+        }
+    }
+    let renumber = |value: &Value| match *value {
+        Value::Reg(reg) => Value::Reg(regs[&reg]),
+        value => value
+    };
+    let entry = func.blocks.len();
+    let tail = entry + callee.blocks.len();
+    let head = &mut func.blocks[block];
+    let rest = head.statements.split_off(index + 1);
+    let call = head.statements.pop().unwrap();
+    let terminator = std::mem::replace(&mut head.terminator, Terminator::Jump{target: entry, args: call.args});
+    for b in &callee.blocks {
+        let terminator = match b.terminator {
+            Terminator::Jump{target, ref args} => {
+                Terminator::Jump{target: target + entry, args: args.iter().map(renumber).collect()}
+            }
+            Terminator::Branch{ref cond, then, otherwise} => {
+                Terminator::Branch{cond: renumber(cond), then: then + entry, otherwise: otherwise + entry}
+            }
+            Terminator::Return(ref args) => {
+                Terminator::Jump{target: tail, args: args.iter().map(renumber).collect()}
+            }
+        };
+        func.blocks.push(Block {
+            params: b.params.iter().map(|reg| regs[reg]).collect(),
+            statements: b.statements.iter().map(|s| Statement {
+                op: s.op.clone(),
+                rets: s.rets.iter().map(|reg| regs[reg]).collect(),
+                args: s.args.iter().map(renumber).collect()
+            }).collect(),
+            terminator
+        });
+    }
+    func.blocks.push(Block{params: call.rets, statements: rest, terminator});
+}
+
+fn inline_function(program: &mut Program, name: &str, recursive: &HashSet<String>) {
+    loop {
+        let func = &program.funcs[name];
+        let mut site = None;
+        'search: for (i, block) in func.blocks.iter().enumerate() {
+            for (j, statement) in block.statements.iter().enumerate() {
+                if let Op::Call{ref name} = statement.op {
+                    if inlinable(program, name, recursive) {
+                        site = Some((i, j, name.to_string()));
+                        break 'search;
+                    }
+                }
+            }
+        }
+        let (block, index, callee) = match site {
+            Some(site) => site,
+            None => return
+        };
+        let mut func = func.clone();
+        inline_call(&mut func, block, index, &program.funcs[&callee]);
+        program.funcs.insert(name.to_string(), func);
+    }
+}
+
+// Inlines into callees before their callers, so that the size of a function
+// is its size after inlining.
+fn visit(program: &mut Program, name: &str, recursive: &HashSet<String>, seen: &mut HashSet<String>) {
+    if !seen.insert(name.to_string()) {
+        return;
+    }
+    let mut callees: Vec<String> = callees(&program.funcs[name]).into_iter()
+        .map(|callee| callee.to_string())
+        .collect();
+    callees.sort();
+    callees.dedup();
+    for callee in callees {
+        if program.funcs.contains_key(&callee) {
+            visit(program, &callee, recursive, seen);
+        }
+    }
+    inline_function(program, name, recursive);
+}
+
+pub fn inline(program: &mut Program) {
+    let mut names: Vec<String> = program.funcs.keys().cloned().collect();
+    names.sort();
+    let mut recursive = HashSet::new();
+    for name in &names {
+        if reaches(program, name, name) {
+            if program.funcs[name].inline == Inline::Always {
+                eprintln!("Warning: {} calls itself, so it is not inlined", name);
+            }
+            recursive.insert(name.to_string());
+        }
+    }
+    let mut seen = HashSet::new();
+    for name in &names {
+        visit(program, name, &recursive, &mut seen);
+    }
+}
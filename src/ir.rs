@@ -0,0 +1,540 @@
+use super::tokenize::Keyword;
+use super::tokenize::Punctuator;
+use super::tokenize::TokenType;
+use super::tokenize::Token;
+use super::parse;
+use super::parse::Inline;
+use super::format;
+use super::generate::Value;
+use super::generate::Operator;
+use super::generate::Statement;
+use super::generate::BasicBlock;
+use super::generate::Function;
+use super::generate::Struct;
+use super::generate::Program;
+
+use std::collections::HashMap;
+
+// A textual form of generate::Program that parses back to the same program,
+// so trans can be fed hand-written IR:
+//
+//   struct Point { x, y: Inner }
+//   enum Color { Red = 0, Green = 1 }
+//   labels { fib }
+//
+//   fn[1] getchar();
+//
+//   const fn[2: Point] name(a.x, a.y, b) types(Point, _) {
+//     0 -> 1, 2:
+//       %2 = less_than %0, 2;
+//       if %2;
+//     1:
+//       %3 = call fib %0, &fib;
+//       return %3;
+//   }
+//
+// Blocks are numbered in order and list the blocks they can continue to. A
+// statement is `[%ret =] op args;`, where an argument is a register `%0`, an
+// immediate or the label of a function. The ops with parameters are
+// `call name`, `call_indirect[retnum]`, `asm("INST", ...) -> (outputs)` and
+// `print "format"`. A function without blocks has no body. Struct arguments
+// take a register per field, so the types of the arguments as written are
+// listed apart, with `_` for a number. A function may be preceded by
+// `#[inline]` or `#[noinline]` as in the source.
+
+pub fn operator_name(op: &Operator) -> &'static str {
+    match op {
+        Operator::Add => "add",
+        Operator::Sub => "sub",
+        Operator::Multiply => "multiply",
+        Operator::Division => "division",
+        Operator::Modulo => "modulo",
+        Operator::Substitute => "substitute",
+        Operator::Equal => "equal",
+        Operator::LessThan => "less_than",
+        Operator::Greater => "greater",
+        Operator::If => "if",
+        Operator::Call{..} => "call",
+        Operator::CallIndirect{..} => "call_indirect",
+        Operator::Return => "return",
+        Operator::Jump => "jump",
+        Operator::Asm{..} => "asm",
+        Operator::Print{..} => "print"
+    }
+}
+
+fn simple_operator(name: &str) -> Option<Operator> {
+    match name {
+        "add" => Some(Operator::Add),
+        "sub" => Some(Operator::Sub),
+        "multiply" => Some(Operator::Multiply),
+        "division" => Some(Operator::Division),
+        "modulo" => Some(Operator::Modulo),
+        "substitute" => Some(Operator::Substitute),
+        "equal" => Some(Operator::Equal),
+        "less_than" => Some(Operator::LessThan),
+        "greater" => Some(Operator::Greater),
+        "if" => Some(Operator::If),
+        "return" => Some(Operator::Return),
+        "jump" => Some(Operator::Jump),
+        _ => None
+    }
+}
+
+fn typed(names: &[String], types: &[Option<String>]) -> String {
+    names.iter().zip(types.iter()).map(|(name, ty)| match ty {
+        Some(ty) => format!("{}: {}", name, ty),
+        None => name.to_string()
+    }).collect::<Vec<_>>().join(", ")
+}
+
+fn print_value(value: &Value, program: &Program) -> String {
+    match *value {
+        Value::Register(index) => format!("%{}", index),
+        Value::Immediate(imm) => imm.to_string(),
+        Value::Label(index) => match program.labels.get(index) {
+            Some(name) => format!("&{}", name),
+            None => format!("&{}", index)
+        }
+    }
+}
+
+pub fn print_statement(statement: &Statement, program: &Program) -> String {
+    let mut text = match statement.ret {
+        Some(ret) => format!("%{} = ", ret),
+        None => String::new()
+    };
+    text.push_str(operator_name(&statement.op));
+    match statement.op {
+        Operator::Call{ref name} => text.push_str(&format!(" {}", name)),
+        Operator::CallIndirect{retnum} => text.push_str(&format!("[{}]", retnum)),
+        Operator::Asm{ref instructions, ref outputs} => {
+            let instructions: Vec<_> = instructions.iter().map(|inst| format::string(inst)).collect();
+            text.push_str(&format!("({})", instructions.join(", ")));
+            if !outputs.is_empty() {
+                text.push_str(&format!(" -> ({})", outputs.join(", ")));
+            }
+        }
+        Operator::Print{ref pieces} => text.push_str(&format!(" {}", format::format_string(pieces))),
+        _ => {}
+    }
+    let args: Vec<_> = statement.args.iter().map(|arg| print_value(arg, program)).collect();
+    if !args.is_empty() {
+        text.push(' ');
+        text.push_str(&args.join(", "));
+    }
+    text.push(';');
+    text
+}
+
+fn print_function(func: &Function, program: &Program, out: &mut String) {
+    let ret = match func.rettype {
+        Some(ref ty) => format!("{}: {}", func.retnum, ty),
+        None => func.retnum.to_string()
+    };
+    match func.inline {
+        Inline::Always => out.push_str("#[inline]\n"),
+        Inline::Never => out.push_str("#[noinline]\n"),
+        Inline::Auto => {}
+    }
+    out.push_str(&format!("{}fn[{}] {}({})", if func.constant { "const " } else { "" },
+                          ret, func.name, func.args.join(", ")));
+    if func.types != vec![None; func.args.len()] {
+        let types: Vec<_> = func.types.iter().map(|ty| ty.as_deref().unwrap_or("_")).collect();
+        out.push_str(&format!(" types({})", types.join(", ")));
+    }
+    if func.basicblocks.is_empty() {
+        out.push_str(";\n");
+        return;
+    }
+    out.push_str(" {\n");
+    for (i, block) in func.basicblocks.iter().enumerate() {
+        let nexts: Vec<_> = block.nexts.iter().map(|next| next.to_string()).collect();
+        if nexts.is_empty() {
+            out.push_str(&format!("  {}:\n", i));
+        } else {
+            out.push_str(&format!("  {} -> {}:\n", i, nexts.join(", ")));
+        }
+        for statement in &block.statements {
+            out.push_str(&format!("    {}\n", print_statement(statement, program)));
+        }
+    }
+    out.push_str("}\n");
+}
+
+pub fn print(program: &Program) -> String {
+    let mut sections = Vec::new();
+    let mut structs: Vec<_> = program.structs.iter().collect();
+    structs.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, st) in structs {
+        sections.push(format!("struct {} {{ {} }}\n", name, typed(&st.fields, &st.types)));
+    }
+    let mut enums: Vec<_> = program.enums.iter().collect();
+    enums.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, variants) in enums {
+        let variants: Vec<_> = variants.iter()
+            .map(|(variant, value)| format!("{} = {}", variant, value))
+            .collect();
+        sections.push(format!("enum {} {{ {} }}\n", name, variants.join(", ")));
+    }
+    if !program.labels.is_empty() {
+        sections.push(format!("labels {{ {} }}\n", program.labels.join(", ")));
+    }
+    let mut funcs: Vec<_> = program.funcs.values().collect();
+    funcs.sort_by(|a, b| a.name.cmp(&b.name));
+    for func in funcs {
+        let mut text = String::new();
+        print_function(func, program, &mut text);
+        sections.push(text);
+    }
+    sections.join("\n")
+}
+
+// A parser returns what it read and how many tokens that took.
+type Parsed<T> = Option<(T, usize)>;
+
+// An enum as the program keeps it, its name and its variants with their values.
+type Enumeration = (String, Vec<(String, i32)>);
+
+fn unexpected<T>(tokens: &[Token], context: &str, expected: &str) -> Option<T> {
+    match tokens.first() {
+        Some(other) => println!("In {}, At {:?}: Unexpected {:?}, expected {}", context, other.pos, other.token, expected),
+        None => println!("In {}, Unexpected EOF, expected {}", context, expected)
+    }
+    None
+}
+
+fn punctuator(tokens: &[Token], punc: Punctuator, context: &str) -> Option<usize> {
+    match tokens.first() {
+        Some(Token{token:TokenType::Punctuator(other), ..}) if *other == punc => Some(1),
+        _ => unexpected(tokens, context, &format!("{:?}", punc))
+    }
+}
+
+fn is_punctuator(tokens: &[Token], punc: Punctuator) -> bool {
+    matches!(tokens.first(), Some(Token{token:TokenType::Punctuator(other), ..}) if *other == punc)
+}
+
+// Lifted functions are named `parent.name`, which the tokenizer splits at
+// the dots.
+fn name(tokens: &[Token]) -> Parsed<String> {
+    let mut res = match tokens.first() {
+        Some(Token{token:TokenType::Identifier(name), ..}) => name.to_string(),
+        _ => return unexpected(tokens, "name", "identifier")
+    };
+    let mut seek = 1;
+    while let (Some(Token{token:TokenType::Punctuator(Punctuator::Dot), ..}),
+               Some(Token{token:TokenType::Identifier(part), ..})) = (tokens.get(seek), tokens.get(seek+1)) {
+        res.push('.');
+        res.push_str(part);
+        seek += 2;
+    }
+    Some((res, seek))
+}
+
+fn name_list(tokens: &[Token], open: Punctuator, close: Punctuator) -> Parsed<Vec<String>> {
+    let mut seek = punctuator(tokens, open, "name_list")?;
+    let mut names = Vec::new();
+    if is_punctuator(&tokens[seek..], close.clone()) {
+        return Some((names, seek+1));
+    }
+    loop {
+        let (name, seek2) = name(&tokens[seek..])?;
+        names.push(name);
+        seek += seek2;
+        if is_punctuator(&tokens[seek..], close.clone()) {
+            return Some((names, seek+1));
+        }
+        seek += punctuator(&tokens[seek..], Punctuator::Comma, "name_list")?;
+    }
+}
+
+fn integer(tokens: &[Token]) -> Parsed<i32> {
+    match (tokens.first(), tokens.get(1)) {
+        (Some(Token{token:TokenType::Constant(imm), ..}), _) => Some((*imm, 1)),
+        (Some(Token{token:TokenType::Punctuator(Punctuator::Minus), ..}),
+         Some(Token{token:TokenType::Constant(imm), ..})) => Some((imm.wrapping_neg(), 2)),
+        _ => unexpected(tokens, "integer", "integer")
+    }
+}
+
+fn index(tokens: &[Token]) -> Parsed<usize> {
+    match tokens.first() {
+        Some(Token{token:TokenType::Constant(imm), ..}) if *imm >= 0 => Some((*imm as usize, 1)),
+        _ => unexpected(tokens, "index", "index")
+    }
+}
+
+fn value(tokens: &[Token], labels: &[String]) -> Parsed<Value> {
+    match tokens.first() {
+        Some(Token{token:TokenType::Punctuator(Punctuator::Percent), ..}) => {
+            let (index, seek) = index(&tokens[1..])?;
+            Some((Value::Register(index), 1+seek))
+        }
+        Some(Token{token:TokenType::Punctuator(Punctuator::Ampersand), ..}) => {
+            let (label, seek) = name(&tokens[1..])?;
+            match labels.iter().position(|other| *other == label) {
+                Some(index) => Some((Value::Label(index), 1+seek)),
+                None => {
+                    println!("Label {} is not in the labels", label);
+                    None
+                }
+            }
+        }
+        _ => {
+            let (imm, seek) = integer(tokens)?;
+            Some((Value::Immediate(imm), seek))
+        }
+    }
+}
+
+fn instructions(tokens: &[Token]) -> Parsed<Vec<String>> {
+    let mut seek = punctuator(tokens, Punctuator::ParenthesisLeft, "asm")?;
+    let mut res = Vec::new();
+    loop {
+        match tokens.get(seek) {
+            Some(Token{token:TokenType::String(inst), ..}) => res.push(inst.to_string()),
+            Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisRight), ..}) if res.is_empty() => {
+                return Some((res, seek+1));
+            }
+            _ => return unexpected(&tokens[seek..], "asm", "instruction")
+        }
+        seek += 1;
+        if is_punctuator(&tokens[seek..], Punctuator::ParenthesisRight) {
+            return Some((res, seek+1));
+        }
+        seek += punctuator(&tokens[seek..], Punctuator::Comma, "asm")?;
+    }
+}
+
+fn statement(tokens: &[Token], labels: &[String]) -> Parsed<Statement> {
+    let mut seek = 0;
+    let mut ret = None;
+    if is_punctuator(tokens, Punctuator::Percent) {
+        let (index, seek2) = index(&tokens[1..])?;
+        ret = Some(index);
+        seek += 1 + seek2;
+        seek += punctuator(&tokens[seek..], Punctuator::Equal, "statement")?;
+    }
+    let op_name = match tokens.get(seek) {
+        Some(Token{token:TokenType::Identifier(name), ..}) => name.as_str(),
+        Some(Token{token:TokenType::Keyword(Keyword::IF), ..}) => "if",
+        Some(Token{token:TokenType::Keyword(Keyword::RETURN), ..}) => "return",
+        Some(Token{token:TokenType::Keyword(Keyword::ASM), ..}) => "asm",
+        _ => return unexpected(&tokens[seek..], "statement", "operator")
+    };
+    seek += 1;
+    let op = match op_name {
+        "call" => {
+            let (name, seek2) = name(&tokens[seek..])?;
+            seek += seek2;
+            Operator::Call{name}
+        }
+        "call_indirect" => {
+            seek += punctuator(&tokens[seek..], Punctuator::BracketLeft, "call_indirect")?;
+            let (retnum, seek2) = index(&tokens[seek..])?;
+            seek += seek2;
+            seek += punctuator(&tokens[seek..], Punctuator::BracketRight, "call_indirect")?;
+            Operator::CallIndirect{retnum}
+        }
+        "asm" => {
+            let (instructions, seek2) = instructions(&tokens[seek..])?;
+            seek += seek2;
+            let mut outputs = Vec::new();
+            if let (Some(Token{token:TokenType::Punctuator(Punctuator::Minus), ..}),
+                    Some(Token{token:TokenType::Punctuator(Punctuator::Greater), ..})) = (tokens.get(seek), tokens.get(seek+1)) {
+                let (names, seek2) = name_list(&tokens[(seek+2)..], Punctuator::ParenthesisLeft, Punctuator::ParenthesisRight)?;
+                outputs = names;
+                seek += 2 + seek2;
+            }
+            Operator::Asm{instructions, outputs}
+        }
+        "print" => {
+            match tokens.get(seek) {
+                Some(Token{token:TokenType::String(text), pos, ..}) => {
+                    seek += 1;
+                    Operator::Print{pieces: parse::format_string(text, pos)?}
+                }
+                _ => return unexpected(&tokens[seek..], "print", "format string")
+            }
+        }
+        name => match simple_operator(name) {
+            Some(op) => op,
+            None => {
+                println!("Unknown operator {}", name);
+                return None
+            }
+        }
+    };
+    let mut args = Vec::new();
+    if !is_punctuator(&tokens[seek..], Punctuator::SemiColon) {
+        loop {
+            let (arg, seek2) = value(&tokens[seek..], labels)?;
+            args.push(arg);
+            seek += seek2;
+            if !is_punctuator(&tokens[seek..], Punctuator::Comma) {
+                break;
+            }
+            seek += 1;
+        }
+    }
+    seek += punctuator(&tokens[seek..], Punctuator::SemiColon, "statement")?;
+    Some((Statement{op, ret, args}, seek))
+}
+
+fn block(tokens: &[Token], number: usize, labels: &[String]) -> Parsed<BasicBlock> {
+    let (index, mut seek) = index(tokens)?;
+    if index != number {
+        println!("At {:?}: Block {} is numbered {}", tokens[0].pos, number, index);
+        return None
+    }
+    let mut nexts = Vec::new();
+    if let (Some(Token{token:TokenType::Punctuator(Punctuator::Minus), ..}),
+            Some(Token{token:TokenType::Punctuator(Punctuator::Greater), ..})) = (tokens.get(seek), tokens.get(seek+1)) {
+        seek += 2;
+        loop {
+            let (next, seek2) = self::index(&tokens[seek..])?;
+            nexts.push(next);
+            seek += seek2;
+            if !is_punctuator(&tokens[seek..], Punctuator::Comma) {
+                break;
+            }
+            seek += 1;
+        }
+    }
+    seek += punctuator(&tokens[seek..], Punctuator::Colon, "block")?;
+    let mut statements = Vec::new();
+    loop {
+        match tokens.get(seek) {
+            Some(Token{token:TokenType::Constant(_), ..}) |
+            Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), ..}) | None => break,
+            _ => {}
+        }
+        let (statement, seek2) = statement(&tokens[seek..], labels)?;
+        statements.push(statement);
+        seek += seek2;
+    }
+    Some((BasicBlock{statements, nexts}, seek))
+}
+
+fn function(tokens: &[Token], labels: &[String]) -> Parsed<Function> {
+    let mut seek = 0;
+    let mut inline = Inline::Auto;
+    if is_punctuator(tokens, Punctuator::Hash) {
+        let (attr, seek2) = parse::attribute(tokens)?;
+        inline = attr;
+        seek += seek2;
+    }
+    let constant = matches!(tokens.get(seek), Some(Token{token:TokenType::Keyword(Keyword::CONST), ..}));
+    if constant {
+        seek += 1;
+    }
+    match tokens.get(seek) {
+        Some(Token{token:TokenType::Keyword(Keyword::FN), ..}) => seek += 1,
+        _ => return unexpected(&tokens[seek..], "function", "fn")
+    }
+    seek += punctuator(&tokens[seek..], Punctuator::BracketLeft, "function")?;
+    let (retnum, seek2) = index(&tokens[seek..])?;
+    seek += seek2;
+    let mut rettype = None;
+    if is_punctuator(&tokens[seek..], Punctuator::Colon) {
+        match tokens.get(seek+1) {
+            Some(Token{token:TokenType::Identifier(ty), ..}) => rettype = Some(ty.to_string()),
+            _ => return unexpected(&tokens[(seek+1)..], "function", "type name")
+        }
+        seek += 2;
+    }
+    seek += punctuator(&tokens[seek..], Punctuator::BracketRight, "function")?;
+    let (name, seek2) = name(&tokens[seek..])?;
+    seek += seek2;
+    let (args, seek2) = name_list(&tokens[seek..], Punctuator::ParenthesisLeft, Punctuator::ParenthesisRight)?;
+    seek += seek2;
+    let mut types = vec![None; args.len()];
+    if let Some(Token{token:TokenType::Identifier(word), ..}) = tokens.get(seek) {
+        if word == "types" {
+            let (names, seek2) = name_list(&tokens[(seek+1)..], Punctuator::ParenthesisLeft, Punctuator::ParenthesisRight)?;
+            types = names.into_iter().map(|ty| if ty == "_" { None } else { Some(ty) }).collect();
+            seek += 1 + seek2;
+        }
+    }
+    let mut basicblocks = Vec::new();
+    if is_punctuator(&tokens[seek..], Punctuator::SemiColon) {
+        seek += 1;
+    } else {
+        seek += punctuator(&tokens[seek..], Punctuator::BraceLeft, "function")?;
+        while !is_punctuator(&tokens[seek..], Punctuator::BraceRight) {
+            let (block, seek2) = block(&tokens[seek..], basicblocks.len(), labels)?;
+            basicblocks.push(block);
+            seek += seek2;
+        }
+        seek += 1;
+    }
+    Some((Function{name, args, types, retnum, rettype, constant, inline, basicblocks}, seek))
+}
+
+fn structure(tokens: &[Token]) -> Parsed<(String, Struct)> {
+    let (name, seek) = name(&tokens[1..])?;
+    let (fields, types, seek2) = parse::typed_list(&tokens[(1+seek)..], Punctuator::BraceLeft, Punctuator::BraceRight)?;
+    Some(((name, Struct{fields, types}), 1+seek+seek2))
+}
+
+fn enumeration(tokens: &[Token]) -> Parsed<Enumeration> {
+    let (name, mut seek) = name(&tokens[1..])?;
+    seek += 1;
+    seek += punctuator(&tokens[seek..], Punctuator::BraceLeft, "enumeration")?;
+    let mut variants = Vec::new();
+    loop {
+        let variant = match tokens.get(seek) {
+            Some(Token{token:TokenType::Identifier(variant), ..}) => variant.to_string(),
+            _ => return unexpected(&tokens[seek..], "enumeration", "variant")
+        };
+        seek += 1;
+        seek += punctuator(&tokens[seek..], Punctuator::Equal, "enumeration")?;
+        let (value, seek2) = integer(&tokens[seek..])?;
+        variants.push((variant, value));
+        seek += seek2;
+        if is_punctuator(&tokens[seek..], Punctuator::BraceRight) {
+            return Some(((name, variants), seek+1));
+        }
+        seek += punctuator(&tokens[seek..], Punctuator::Comma, "enumeration")?;
+    }
+}
+
+pub fn parse(tokens: &[Token]) -> Option<Program> {
+    let mut program = Program {
+        funcs: HashMap::new(),
+        structs: HashMap::new(),
+        enums: HashMap::new(),
+        labels: Vec::new()
+    };
+    let mut seek = 0;
+    while seek < tokens.len() {
+        match tokens[seek].token {
+            TokenType::Keyword(Keyword::STRUCT) => {
+                let ((name, st), seek2) = structure(&tokens[seek..])?;
+                program.structs.insert(name, st);
+                seek += seek2;
+            }
+            TokenType::Keyword(Keyword::ENUM) => {
+                let ((name, variants), seek2) = enumeration(&tokens[seek..])?;
+                program.enums.insert(name, variants);
+                seek += seek2;
+            }
+            TokenType::Identifier(ref word) if word == "labels" => {
+                let (labels, seek2) = name_list(&tokens[(seek+1)..], Punctuator::BraceLeft, Punctuator::BraceRight)?;
+                program.labels = labels;
+                seek += 1 + seek2;
+            }
+            _ => {
+                let (func, seek2) = function(&tokens[seek..], &program.labels)?;
+                if program.funcs.contains_key(&func.name) {
+                    println!("Function {} is already defined", func.name);
+                    return None
+                }
+                program.funcs.insert(func.name.to_string(), func);
+                seek += seek2;
+            }
+        }
+    }
+    Some(program)
+}
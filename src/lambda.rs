@@ -0,0 +1,233 @@
+use super::parse::Call;
+use super::parse::Expr;
+use super::parse::Stmt;
+use super::parse::Arm;
+use super::parse::FnDecl;
+use super::parse::Decl;
+use super::visit;
+use super::visit::Visitor;
+use super::visit::Folder;
+
+use std::collections::HashMap;
+
+// A function declared inside another one is lifted to the top level as
+// `parent.name`, and one nested deeper as `parent.outer.inner`. No name
+// written in source contains a dot, so a lifted name never clashes with a
+// declared function, and the textual IR reads it back as one name. The
+// enclosing variables it reads become extra arguments, appended after its
+// own, and every call to it passes them along.
+#[derive(Clone)]
+struct Nested {
+    name: String,
+    lifted: String,
+    rettype: Option<String>,
+    captures: Vec<String>
+}
+
+// The return type of each function and the field types of each struct, by
+// the name the module calls them, for the types of captured variables.
+#[derive(Default)]
+pub struct Types {
+    pub funcs: HashMap<String, Option<String>>,
+    pub structs: HashMap<String, Vec<(String, Option<String>)>>
+}
+
+// The struct a variable assigned `expr` holds, if any.
+fn type_of(expr: &Expr, scope: &[(String, Option<String>)], nested: &[Nested], types: &Types) -> Option<String> {
+    match expr {
+        Expr::StructLiteral{name, ..} => Some(name.to_string()),
+        Expr::Identifier(name) => scope.iter().rev().find(|(var, _)| var == name)?.1.clone(),
+        Expr::Call(call) => match nested.iter().rev().find(|n| n.name == call.name) {
+            Some(n) => n.rettype.clone(),
+            None => types.funcs.get(&call.name)?.clone()
+        },
+        Expr::Field{base, name} => {
+            let base = type_of(base, scope, nested, types)?;
+            types.structs.get(&base)?.iter().find(|(field, _)| field == name)?.1.clone()
+        }
+        Expr::Conditional{then, ..} => type_of(then, scope, nested, types),
+        _ => None
+    }
+}
+
+// Collects the names a body reads or writes, with calls to nested functions
+// standing for the variables those capture.
+struct Used<'a> {
+    nested: &'a [Nested],
+    res: Vec<String>
+}
+
+impl Visitor for Used<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Substitute{name, ..} | Stmt::Update{name, ..} => self.res.push(name.to_string()),
+            Stmt::Asm{outputs, ..} => self.res.extend(outputs.iter().cloned()),
+            _ => {}
+        }
+        visit::walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Identifier(name) = expr {
+            self.res.push(name.to_string());
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_call(&mut self, call: &Call) {
+        match self.nested.iter().rev().find(|n| n.name == call.name) {
+            Some(n) => self.res.extend(n.captures.iter().cloned()),
+            None => self.res.push(call.name.to_string())
+        }
+        visit::walk_call(self, call);
+    }
+}
+
+// Points calls and references to nested functions at their lifted names, and
+// passes the captured variables along.
+struct Rename<'a> {
+    nested: &'a [Nested]
+}
+
+impl Folder for Rename<'_> {
+    fn fold_expr(&mut self, expr: Expr) -> Option<Expr> {
+        match expr {
+            Expr::Function(name) => {
+                match self.nested.iter().rev().find(|n| n.name == name) {
+                    Some(n) if !n.captures.is_empty() => {
+                        println!("Cannot take the address of {}, it reads variables of its parent", name);
+                        None
+                    }
+                    Some(n) => Some(Expr::Function(n.lifted.to_string())),
+                    None => Some(Expr::Function(name))
+                }
+            }
+            _ => visit::fold_expr(self, expr)
+        }
+    }
+
+    fn fold_call(&mut self, call: Call) -> Option<Call> {
+        let mut call = visit::fold_call(self, call)?;
+        if let Some(n) = self.nested.iter().rev().find(|n| n.name == call.name) {
+            for capture in &n.captures {
+                call.args.push(Expr::Identifier(capture.to_string()));
+            }
+            call.name = n.lifted.to_string();
+        }
+        Some(call)
+    }
+}
+
+fn bind(scope: &mut Vec<(String, Option<String>)>, name: &str, ty: Option<String>) {
+    if !scope.iter().any(|(var, _)| var == name) {
+        scope.push((name.to_string(), ty));
+    }
+}
+
+// Walks a statement list in order, so that a nested function only captures
+// variables defined before it.
+fn statements(body: &[Stmt], prefix: &str, scope: &mut Vec<(String, Option<String>)>,
+              nested: &mut Vec<Nested>, types: &Types, out: &mut Vec<Decl>) -> Option<Vec<Stmt>> {
+    let mut res = Vec::new();
+    for stmt in body {
+        let stmt = match stmt {
+            Stmt::Fn(decl) => {
+                let mut used = Used { nested, res: Vec::new() };
+                used.visit_body(&decl.body);
+                let names = used.res;
+                let captured: Vec<(String, Option<String>)> = scope.iter()
+                    .filter(|(var, _)| names.contains(var) && !decl.args.contains(var))
+                    .cloned()
+                    .collect();
+                let lifted = format!("{}.{}", prefix, decl.name);
+                nested.push(Nested {
+                    name: decl.name.to_string(),
+                    lifted: lifted.clone(),
+                    rettype: decl.rettype.clone(),
+                    captures: captured.iter().map(|(var, _)| var.to_string()).collect()
+                });
+                let mut inner_scope: Vec<(String, Option<String>)> = decl.args.iter().cloned()
+                    .zip(decl.types.iter().cloned())
+                    .chain(captured.iter().cloned())
+                    .collect();
+                let body = statements(&decl.body, &lifted, &mut inner_scope, &mut nested.clone(), types, out)?;
+                out.push(Decl::Fn(FnDecl {
+                    name: lifted,
+                    args: decl.args.iter().cloned().chain(captured.iter().map(|(var, _)| var.to_string())).collect(),
+                    types: decl.types.iter().cloned().chain(captured.iter().map(|(_, ty)| ty.clone())).collect(),
+                    public: false,
+                    constant: false,
+                    body,
+                    ..decl.clone()
+                }));
+                continue;
+            }
+            // Assignments and asm outputs are the statements that define
+            // variables; those of if and match arms stay inside the arm.
+            Stmt::Substitute{name, value} => {
+                let ty = type_of(value, scope, nested, types);
+                bind(scope, name, ty);
+                Rename { nested }.fold_stmt(stmt.clone())?
+            }
+            Stmt::Asm{outputs, ..} => {
+                for output in outputs {
+                    bind(scope, output, None);
+                }
+                Rename { nested }.fold_stmt(stmt.clone())?
+            }
+            Stmt::If{cond, then, otherwise} => {
+                vec![Stmt::If{
+                    cond: Rename { nested }.fold_expr(cond.clone())?,
+                    then: statements(then, prefix, &mut scope.clone(), &mut nested.clone(), types, out)?,
+                    otherwise: match otherwise {
+                        Some(otherwise) => {
+                            Some(statements(otherwise, prefix, &mut scope.clone(), &mut nested.clone(), types, out)?)
+                        }
+                        None => None
+                    }
+                }]
+            }
+            Stmt::Match{scrutinee, arms} => {
+                let mut new_arms = Vec::new();
+                for arm in arms {
+                    new_arms.push(Arm {
+                        patterns: arm.patterns.clone(),
+                        body: statements(&arm.body, prefix, &mut scope.clone(), &mut nested.clone(), types, out)?
+                    });
+                }
+                vec![Stmt::Match{scrutinee: Rename { nested }.fold_expr(scrutinee.clone())?, arms: new_arms}]
+            }
+            _ => Rename { nested }.fold_stmt(stmt.clone())?
+        };
+        res.extend(stmt);
+    }
+    Some(res)
+}
+
+// `types` holds what the module imports; its own declarations are added.
+pub fn lift_functions(decls: Vec<Decl>, mut types: Types) -> Option<Vec<Decl>> {
+    for decl in &decls {
+        match decl {
+            Decl::Fn(decl) => {
+                types.funcs.insert(decl.name.to_string(), decl.rettype.clone());
+            }
+            Decl::Struct(decl) => {
+                types.structs.insert(decl.name.to_string(),
+                                     decl.fields.iter().cloned().zip(decl.types.iter().cloned()).collect());
+            }
+            _ => {}
+        }
+    }
+    let mut out = Vec::new();
+    for decl in decls {
+        match decl {
+            Decl::Fn(decl) => {
+                let mut scope = decl.args.iter().cloned().zip(decl.types.iter().cloned()).collect();
+                let body = statements(&decl.body, &decl.name, &mut scope, &mut Vec::new(), &types, &mut out)?;
+                out.push(Decl::Fn(FnDecl { body, ..decl }));
+            }
+            other => out.push(other)
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,17 @@
+pub mod tokenize;
+pub mod parse;
+pub mod visit;
+pub mod generate;
+pub mod trans;
+pub mod runtime;
+pub mod import;
+pub mod eval;
+pub mod lambda;
+pub mod format;
+pub mod export;
+pub mod ir;
+pub mod verify;
+pub mod ssa;
+pub mod constant;
+pub mod dead;
+pub mod inline;
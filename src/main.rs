@@ -1,30 +1,194 @@
-pub mod tokenize;
-pub mod parse;
-pub mod generate;
-pub mod trans;
+use poyot::tokenize;
+use poyot::parse;
+use poyot::generate;
+use poyot::trans;
+use poyot::import;
+use poyot::format;
+use poyot::export;
+use poyot::ir;
+use poyot::verify;
+use poyot::ssa;
+use poyot::constant;
+use poyot::dead;
+use poyot::inline;
 
 use std::io;
 use std::io::Read;
+use std::io::Write;
 use std::fs::File;
 use std::env;
 use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process;
+
+// `poyot fmt [--check] files...` rewrites the files in the canonical layout.
+// With --check it only reports the files that are not formatted.
+fn fmt(args: impl Iterator<Item = String>) -> io::Result<bool> {
+    let mut check = false;
+    let mut ok = true;
+    for arg in args {
+        if arg == "--check" {
+            check = true;
+            continue;
+        }
+        let mut code = String::new();
+        File::open(&arg).expect("Cannot open file").read_to_string(&mut code)?;
+        let tokens = match tokenize::tokenize(&code) {
+            Some(tokens) => tokens,
+            None => {
+                println!("{}: Failed to tokenize", arg);
+                ok = false;
+                continue;
+            }
+        };
+        let ast = match parse::parse(&tokens) {
+            Some(ast) => ast,
+            None => {
+                println!("{}: Failed to parse", arg);
+                ok = false;
+                continue;
+            }
+        };
+        let lost = format::lost_comments(&tokens, &ast);
+        if !lost.is_empty() {
+            for line in lost {
+                println!("{}:{}: Cannot keep a comment inside a statement or declaration", arg, line + 1);
+            }
+            ok = false;
+            continue;
+        }
+        let formatted = format::format(&ast);
+        if formatted == code {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", arg);
+            ok = false;
+        } else {
+            File::create(&arg)?.write_all(formatted.as_bytes())?;
+        }
+    }
+    Ok(ok)
+}
+
+#[derive(PartialEq)]
+enum Emit {
+    Tokens,
+    Ast,
+    Ssa,
+    Ir,
+    IrText
+}
+
+// Prints a stage for other tools instead of compiling further.
+fn emit(node: export::Node, sexp: bool) {
+    if sexp {
+        print!("{}", export::sexp(&node));
+    } else {
+        print!("{}", export::json(&node));
+    }
+}
 
 fn main() -> io::Result<()> {
     let mut args = env::args();
     let _program = args.next().unwrap();
-    let in_filename = args.next().expect("Please specify input filename");
-    let out_filename = args.next().expect("Please specify output filename");
-    let mut in_file = File::open(in_filename).expect("Cannot open file");
+    let mut args = args.peekable();
+    if args.peek().map(|arg| arg.as_str()) == Some("fmt") {
+        args.next();
+        if !fmt(args)? {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    let mut search = Vec::<PathBuf>::new();
+    let mut files = Vec::<String>::new();
+    let mut asserts = true;
+    let mut stage = None;
+    let mut sexp = false;
+    while let Some(arg) = args.next() {
+        if arg == "--release" {
+            asserts = false;
+        } else if arg == "--emit" {
+            stage = match args.next().as_deref() {
+                Some("tokens") => Some(Emit::Tokens),
+                Some("ast") => Some(Emit::Ast),
+                Some("ssa") => Some(Emit::Ssa),
+                Some("ir") => Some(Emit::Ir),
+                Some("ir-text") => Some(Emit::IrText),
+                _ => panic!("Please specify tokens, ast, ssa, ir or ir-text after --emit")
+            };
+        } else if arg == "--sexp" {
+            sexp = true;
+        } else if arg == "-I" {
+            search.push(PathBuf::from(args.next().expect("Please specify a directory after -I")));
+        } else {
+            files.push(arg);
+        }
+    }
+    let mut files = files.into_iter();
+    let in_filename = files.next().expect("Please specify input filename");
+    let mut in_file = File::open(&in_filename).expect("Cannot open file");
     let mut code = String::new();
     in_file.read_to_string(&mut code)?;
     let tokens = tokenize::tokenize(&code).expect("Failde to tokenize");
-    println!("Tokens = {:?}", tokens);
-    let ast = parse::parse(&tokens).expect("Failed to parse");
-    println!("AST = {:?}", ast);
-    let prog = generate::generate(&ast).expect("Failed to generate program");
-    println!("Program = {:?}", prog);
+    if stage == Some(Emit::Tokens) {
+        emit(export::tokens(&tokens), sexp);
+        return Ok(());
+    }
+    // A .ir file is the textual IR, which goes straight to trans.
+    let prog = if in_filename.ends_with(".ir") {
+        if stage == Some(Emit::Ast) || stage == Some(Emit::Ssa) {
+            panic!("An IR file goes straight to trans");
+        }
+        let prog = ir::parse(&tokens).expect("Failed to parse IR");
+        if !verify::verify(&prog) {
+            panic!("Invalid IR");
+        }
+        prog
+    } else {
+        let ast = parse::parse(&tokens).expect("Failed to parse");
+        let ast = import::resolve_imports(ast, Path::new(&in_filename), &search).expect("Failed to import");
+        if stage == Some(Emit::Ast) {
+            emit(export::ast(&ast), sexp);
+            return Ok(());
+        }
+        let prog = generate::generate(&ast, asserts).expect("Failed to generate program");
+        if cfg!(debug_assertions) && !verify::verify(&prog) {
+            panic!("generate produced invalid IR");
+        }
+        // The passes work on the SSA form, which is lowered back for trans.
+        let mut ssa = ssa::build(&prog);
+        inline::inline(&mut ssa);
+        if cfg!(debug_assertions) && !verify::verify(&ssa::lower(&ssa)) {
+            panic!("Inlining produced invalid IR");
+        }
+        constant::fold(&mut ssa);
+        if cfg!(debug_assertions) && !verify::verify(&ssa::lower(&ssa)) {
+            panic!("Constant folding produced invalid IR");
+        }
+        dead::eliminate(&mut ssa);
+        if stage == Some(Emit::Ssa) {
+            print!("{}", ssa::print(&ssa));
+            return Ok(());
+        }
+        let prog = ssa::lower(&ssa);
+        if cfg!(debug_assertions) && !verify::verify(&prog) {
+            panic!("Dead code elimination produced invalid IR");
+        }
+        prog
+    };
+    if stage == Some(Emit::Ir) {
+        emit(export::ir(&prog), sexp);
+        return Ok(());
+    }
+    if stage == Some(Emit::IrText) {
+        print!("{}", ir::print(&prog));
+        return Ok(());
+    }
+    let out_filename = files.next().expect("Please specify output filename");
     let mut out_file_buf = BufWriter::new(File::create(out_filename).expect("Cannot create file"));
-    trans::trans(&prog, &mut out_file_buf);
+    trans::trans(&prog, &mut out_file_buf)?;
+    out_file_buf.flush()?;
 
     Ok(())
 }
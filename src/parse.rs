@@ -2,68 +2,241 @@ use super::tokenize::Token;
 use super::tokenize::TokenType;
 use super::tokenize::Punctuator;
 use super::tokenize::Keyword;
+use super::tokenize::Pos;
+use super::tokenize::Comment;
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Operator {
+pub enum BinaryOp {
     Add,
     Sub,
     Multiply,
     Division,
     Modulo,
-    Substitute,
     Equal,
     LessThan,
-    Greater,
-    If,
-    Call{name: String},
-    Return,
-    Jump,
-    Do,
-    Expression,
-    Statement,
-    Declare,
-    FunctionDeclare{name: String, args: Vec<String>, retnum: usize}
+    Greater
 }
 
+// Characters are kept apart from numbers so that fmt prints them as written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Literal {
+    Int(i32),
+    Char(char)
+}
+
+impl Literal {
+    pub fn value(&self) -> i32 {
+        match *self {
+            Literal::Int(imm) => imm,
+            Literal::Char(c) => c as i32
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Range(Literal, Literal),
+    Variant(String),
+    Wildcard
+}
+
+// A print format string split at its placeholders. `{}` prints a number,
+// `{:c}` a char; `{:5}`, `{:05}` and `{:<5}` pad to a width.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Piece {
+    Text(String),
+    Number{width: usize, fill: char, left: bool},
+    Char{width: usize, left: bool}
+}
+
+// The widest a number can print, -2147483648; no width may exceed it.
+pub const MAX_WIDTH: usize = 11;
+
 #[derive(Debug, Clone, PartialEq)]
-pub struct Node {
-    pub op: Operator,
-    pub children: Vec<AST>
+pub struct Call {
+    pub name: String,
+    pub args: Vec<Expr>
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Leaf {
+pub enum Expr {
+    Constant(i32),
+    Char(char),
     Identifier(String),
-    Constant(i32)
+    Function(String),
+    Binary{op: BinaryOp, lhs: Box<Expr>, rhs: Box<Expr>},
+    Call(Call),
+    StructLiteral{name: String, fields: Vec<(String, Expr)>},
+    Field{base: Box<Expr>, name: String},
+    Conditional{cond: Box<Expr>, then: Box<Expr>, otherwise: Box<Expr>}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arm {
+    pub patterns: Vec<Pattern>,
+    pub body: Vec<Stmt>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Substitute{name: String, value: Expr},
+    // A compound assignment to an existing variable, `name op= value`; `x++`
+    // and `x--` are an update by 1.
+    Update{name: String, op: BinaryOp, value: Expr},
+    Call(Call),
+    If{cond: Expr, then: Vec<Stmt>, otherwise: Option<Vec<Stmt>>},
+    Match{scrutinee: Expr, arms: Vec<Arm>},
+    Return(Option<Expr>),
+    // The instructions are grouped by the source line they are written on.
+    Asm{inputs: Vec<Expr>, instructions: Vec<Vec<String>>, outputs: Vec<String>},
+    // The file is filled in by import, which knows where each module came from.
+    Assert{cond: Expr, file: String, line: usize, message: Option<String>},
+    Print{pieces: Vec<Piece>, args: Vec<Expr>},
+    Fn(FnDecl),
+    Comment(Comment)
+}
+
+// What the #[inline] or #[noinline] attribute of a function asks of the
+// inliner; without one, the inliner decides by size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Inline {
+    Auto,
+    Always,
+    Never
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnDecl {
+    pub name: String,
+    pub args: Vec<String>,
+    pub types: Vec<Option<String>>,
+    pub retnum: usize,
+    pub rettype: Option<String>,
+    pub public: bool,
+    pub constant: bool,
+    pub inline: Inline,
+    pub body: Vec<Stmt>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructDecl {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub types: Vec<Option<String>>,
+    pub public: bool
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumDecl {
+    pub name: String,
+    // The explicit values; the others count up from the previous variant.
+    pub variants: Vec<(String, Option<Literal>)>,
+    pub public: bool
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decl {
+    Fn(FnDecl),
+    Struct(StructDecl),
+    Enum(EnumDecl),
+    Import{path: String},
+    Comment(Comment)
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum AST {
-    Node(Node),
-    Leaf(Leaf)
+pub struct Program {
+    pub decls: Vec<Decl>
+}
+
+impl Expr {
+    // The direct subexpressions, in the order they are evaluated.
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Binary{lhs, rhs, ..} => vec![lhs, rhs],
+            Expr::Call(call) => call.args.iter().collect(),
+            Expr::StructLiteral{fields, ..} => fields.iter().map(|(_, value)| value).collect(),
+            Expr::Field{base, ..} => vec![base],
+            Expr::Conditional{cond, then, otherwise} => vec![cond, then, otherwise],
+            Expr::Constant(_) | Expr::Char(_) | Expr::Identifier(_) | Expr::Function(_) => Vec::new()
+        }
+    }
+}
+
+impl Stmt {
+    // The expressions evaluated by the statement itself, not by its bodies.
+    pub fn exprs(&self) -> Vec<&Expr> {
+        match self {
+            Stmt::Substitute{value, ..} | Stmt::Update{value, ..} => vec![value],
+            Stmt::Call(call) => call.args.iter().collect(),
+            Stmt::If{cond, ..} => vec![cond],
+            Stmt::Match{scrutinee, ..} => vec![scrutinee],
+            Stmt::Return(value) => value.iter().collect(),
+            Stmt::Asm{inputs, ..} => inputs.iter().collect(),
+            Stmt::Assert{cond, ..} => vec![cond],
+            Stmt::Print{args, ..} => args.iter().collect(),
+            Stmt::Fn(_) | Stmt::Comment(_) => Vec::new()
+        }
+    }
+
+    pub fn bodies(&self) -> Vec<&Vec<Stmt>> {
+        match self {
+            Stmt::If{then, otherwise, ..} => {
+                let mut bodies = vec![then];
+                bodies.extend(otherwise.iter());
+                bodies
+            }
+            Stmt::Match{arms, ..} => arms.iter().map(|arm| &arm.body).collect(),
+            Stmt::Fn(decl) => vec![&decl.body],
+            _ => Vec::new()
+        }
+    }
 }
 
-fn expression_call(tokens: &[Token]) -> Option<(AST, usize)> {
+fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    Expr::Binary{op, lhs: Box::new(lhs), rhs: Box::new(rhs)}
+}
+
+fn expression_call(tokens: &[Token]) -> Option<(Expr, usize)> {
     let mut itr = tokens.iter();
     match itr.next() {
-        Some(Token{token:TokenType::Constant(constant), pos:_}) => Some((AST::Leaf(Leaf::Constant(*constant)), 1)),
-        Some(Token{token:TokenType::Identifier(identifier), pos:_}) => {
+        Some(Token{token:TokenType::Constant(constant), ..}) => Some((Expr::Constant(*constant), 1)),
+        Some(Token{token:TokenType::Char(c), ..}) => Some((Expr::Char(*c), 1)),
+        Some(Token{token:TokenType::Identifier(identifier), ..}) => {
             match itr.next() {
-                Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisLeft), pos:_}) => {
+                Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisLeft), ..}) => {
                     match call(tokens, identifier.to_string()) {
-                        Some((ast, seek)) => Some((ast, 2+seek)),
+                        Some((call, seek)) => Some((Expr::Call(call), 2+seek)),
                         None => None
                     }
                 }
-                _ => Some((AST::Leaf(Leaf::Identifier(identifier.to_string())), 1))
+                Some(Token{token:TokenType::Punctuator(Punctuator::BraceLeft), ..}) if is_struct_literal(tokens) => {
+                    struct_literal(tokens, identifier.to_string())
+                }
+                _ => Some((Expr::Identifier(identifier.to_string()), 1))
             }
         }
-        Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisLeft), pos:_}) => {
+        Some(Token{token:TokenType::Punctuator(Punctuator::Ampersand), ..}) => {
+            match itr.next() {
+                Some(Token{token:TokenType::Identifier(identifier), ..}) => {
+                    Some((Expr::Function(identifier.to_string()), 2))
+                }
+                Some(other) => {
+                    println!("In expression_call, At {:?}: Unexpected {:?}, expected function name", other.pos, other.token);
+                    None
+                }
+                None => {
+                    println!("In expression_call, Unexpected EOF, expected function name");
+                    None
+                }
+            }
+        }
+        Some(Token{token:TokenType::Keyword(Keyword::IF), ..}) => if_expression(tokens),
+        Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisLeft), ..}) => {
             match expression(tokens.get(1..).unwrap()) {
                 Some((ast, seek)) => {
                     let mut itr2 = itr.skip(seek);
                     match itr2.next() {
-                        Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisRight), pos:_}) => {}
+                        Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisRight), ..}) => {}
                         _ => return None
                     }
                     Some((ast, 1+seek+1))
@@ -79,33 +252,100 @@ fn expression_call(tokens: &[Token]) -> Option<(AST, usize)> {
     }
 }
 
-fn expression_mul(tokens: &[Token]) -> Option<(AST, usize)> {
+fn is_struct_literal(tokens: &[Token]) -> bool {
+    matches!((tokens.get(2), tokens.get(3)),
+             (Some(Token{token:TokenType::Identifier(_), ..}),
+              Some(Token{token:TokenType::Punctuator(Punctuator::Colon), ..})))
+}
+
+fn struct_literal(tokens: &[Token], name: String) -> Option<(Expr, usize)> {
+    let mut fields = Vec::new();
+    let mut seek = 2;
+    loop {
+        let field = match tokens.get(seek) {
+            Some(Token{token:TokenType::Identifier(field), ..}) => field.to_string(),
+            Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), ..}) if !fields.is_empty() => {
+                break;
+            }
+            Some(other) => {
+                println!("In struct_literal, At {:?}: Unexpected {:?}, expected field name", other.pos, other.token);
+                return None
+            }
+            None => {
+                println!("In struct_literal, Unexpected EOF, expected field name");
+                return None
+            }
+        };
+        match tokens.get(seek+1) {
+            Some(Token{token:TokenType::Punctuator(Punctuator::Colon), ..}) => {}
+            Some(other) => {
+                println!("In struct_literal, At {:?}: Unexpected {:?}, expected :", other.pos, other.token);
+                return None
+            }
+            None => {
+                println!("In struct_literal, Unexpected EOF, expected :");
+                return None
+            }
+        }
+        let (exp, seek2) = expression(tokens.get((seek+2)..).unwrap())?;
+        fields.push((field, exp));
+        seek += 2 + seek2;
+        match tokens.get(seek) {
+            Some(Token{token:TokenType::Punctuator(Punctuator::Comma), ..}) => {
+                seek += 1;
+            }
+            Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), ..}) => {
+                break;
+            }
+            Some(other) => {
+                println!("In struct_literal, At {:?}: Unexpected {:?}, expected , or }}", other.pos, other.token);
+                return None
+            }
+            None => {
+                println!("In struct_literal, Unexpected EOF, expected , or }}");
+                return None
+            }
+        }
+    }
+    Some((Expr::StructLiteral{name, fields}, seek+1))
+}
+
+fn expression_field(tokens: &[Token]) -> Option<(Expr, usize)> {
     let (mut lhs, mut seek) = expression_call(tokens)?;
+    loop {
+        match (tokens.get(seek), tokens.get(seek+1)) {
+            (Some(Token{token:TokenType::Punctuator(Punctuator::Dot), ..}),
+             Some(Token{token:TokenType::Identifier(field), ..})) => {
+                lhs = Expr::Field{base: Box::new(lhs), name: field.to_string()};
+                seek += 2;
+            }
+            (Some(Token{token:TokenType::Punctuator(Punctuator::Dot), ..}), Some(other)) => {
+                println!("In expression_field, At {:?}: Unexpected {:?}, expected field name", other.pos, other.token);
+                return None
+            }
+            _ => return Some((lhs, seek))
+        }
+    }
+}
+
+fn expression_mul(tokens: &[Token]) -> Option<(Expr, usize)> {
+    let (mut lhs, mut seek) = expression_field(tokens)?;
     let mut itr = tokens.iter().skip(seek);
     loop {
         match itr.next() {
-            Some(Token{token:TokenType::Punctuator(Punctuator::Star), pos:_}) => {
-                let (rhs, seek2) = expression_call(tokens.get((seek+1)..).unwrap())?;
-                lhs = AST::Node(Node {
-                    op: Operator::Multiply,
-                    children: vec![lhs, rhs]
-                });
+            Some(Token{token:TokenType::Punctuator(Punctuator::Star), ..}) => {
+                let (rhs, seek2) = expression_field(tokens.get((seek+1)..).unwrap())?;
+                lhs = binary(BinaryOp::Multiply, lhs, rhs);
                 seek += 1 + seek2;
             }
-            Some(Token{token:TokenType::Punctuator(Punctuator::Slash), pos:_}) => {
-                let (rhs, seek2) = expression_call(tokens.get((seek+1)..).unwrap())?;
-                lhs = AST::Node(Node {
-                    op: Operator::Division,
-                    children: vec![lhs, rhs]
-                });
+            Some(Token{token:TokenType::Punctuator(Punctuator::Slash), ..}) => {
+                let (rhs, seek2) = expression_field(tokens.get((seek+1)..).unwrap())?;
+                lhs = binary(BinaryOp::Division, lhs, rhs);
                 seek += 1 + seek2;
             }
-            Some(Token{token:TokenType::Punctuator(Punctuator::Percent), pos:_}) => {
-                let (rhs, seek2) = expression_call(tokens.get((seek+1)..).unwrap())?;
-                lhs = AST::Node(Node {
-                    op: Operator::Modulo,
-                    children: vec![lhs, rhs]
-                });
+            Some(Token{token:TokenType::Punctuator(Punctuator::Percent), ..}) => {
+                let (rhs, seek2) = expression_field(tokens.get((seek+1)..).unwrap())?;
+                lhs = binary(BinaryOp::Modulo, lhs, rhs);
                 seek += 1 + seek2;
             }
             _ => return Some((lhs, seek))
@@ -113,25 +353,19 @@ fn expression_mul(tokens: &[Token]) -> Option<(AST, usize)> {
     }
 }
 
-fn expression_add(tokens: &[Token]) -> Option<(AST, usize)> {
+fn expression_add(tokens: &[Token]) -> Option<(Expr, usize)> {
     let (mut lhs, mut seek) = expression_mul(tokens)?;
     let mut itr = tokens.iter().skip(seek);
     loop {
         match itr.next() {
-            Some(Token{token:TokenType::Punctuator(Punctuator::Plus), pos:_}) => {
+            Some(Token{token:TokenType::Punctuator(Punctuator::Plus), ..}) => {
                 let (rhs, seek2) = expression_mul(tokens.get((seek+1)..).unwrap())?;
-                lhs = AST::Node(Node {
-                    op: Operator::Add,
-                    children: vec![lhs, rhs]
-                });
+                lhs = binary(BinaryOp::Add, lhs, rhs);
                 seek += 1 + seek2;
             }
-            Some(Token{token:TokenType::Punctuator(Punctuator::Minus), pos:_}) => {
+            Some(Token{token:TokenType::Punctuator(Punctuator::Minus), ..}) => {
                 let (rhs, seek2) = expression_mul(tokens.get((seek+1)..).unwrap())?;
-                lhs = AST::Node(Node {
-                    op: Operator::Sub,
-                    children: vec![lhs, rhs]
-                });
+                lhs = binary(BinaryOp::Sub, lhs, rhs);
                 seek += 1 + seek2;
             }
             _ => return Some((lhs, seek))
@@ -139,25 +373,19 @@ fn expression_add(tokens: &[Token]) -> Option<(AST, usize)> {
     }
 }
 
-fn expression_greater(tokens: &[Token]) -> Option<(AST, usize)> {
+fn expression_greater(tokens: &[Token]) -> Option<(Expr, usize)> {
     let (mut lhs, mut seek) = expression_add(tokens)?;
     let mut itr = tokens.iter().skip(seek);
     loop {
         match itr.next() {
-            Some(Token{token:TokenType::Punctuator(Punctuator::LessThan), pos:_}) => {
+            Some(Token{token:TokenType::Punctuator(Punctuator::LessThan), ..}) => {
                 let (rhs, seek2) = expression_add(tokens.get((seek+1)..).unwrap())?;
-                lhs = AST::Node(Node {
-                    op: Operator::LessThan,
-                    children: vec![lhs, rhs]
-                });
+                lhs = binary(BinaryOp::LessThan, lhs, rhs);
                 seek += 1 + seek2;
             }
-            Some(Token{token:TokenType::Punctuator(Punctuator::Greater), pos:_}) => {
+            Some(Token{token:TokenType::Punctuator(Punctuator::Greater), ..}) => {
                 let (rhs, seek2) = expression_add(tokens.get((seek+1)..).unwrap())?;
-                lhs = AST::Node(Node {
-                    op: Operator::Greater,
-                    children: vec![lhs, rhs]
-                });
+                lhs = binary(BinaryOp::Greater, lhs, rhs);
                 seek += 1 + seek2;
             }
             _ => return Some((lhs, seek))
@@ -165,17 +393,14 @@ fn expression_greater(tokens: &[Token]) -> Option<(AST, usize)> {
     }
 }
 
-fn expression(tokens: &[Token]) -> Option<(AST, usize)> {
+fn expression(tokens: &[Token]) -> Option<(Expr, usize)> {
     let (mut lhs, mut seek) = expression_greater(tokens)?;
     let mut itr = tokens.iter().skip(seek);
     loop {
         match itr.next() {
-            Some(Token{token:TokenType::Punctuator(Punctuator::DoubleEqual), pos:_}) => {
+            Some(Token{token:TokenType::Punctuator(Punctuator::DoubleEqual), ..}) => {
                 let (rhs, seek2) = expression_greater(tokens.get((seek+1)..).unwrap())?;
-                lhs = AST::Node(Node {
-                    op: Operator::Equal,
-                    children: vec![lhs, rhs]
-                });
+                lhs = binary(BinaryOp::Equal, lhs, rhs);
                 seek += 1 + seek2;
             }
             _ => return Some((lhs, seek))
@@ -183,7 +408,7 @@ fn expression(tokens: &[Token]) -> Option<(AST, usize)> {
     }
 }
 
-fn expression_loop(tokens: &[Token], res: &mut Vec<AST>) -> Option<usize> {
+fn expression_loop(tokens: &[Token], res: &mut Vec<Expr>) -> Option<usize> {
     if tokens.len() >= 1 {
         if tokens[0].token == TokenType::Punctuator(Punctuator::ParenthesisRight) {
             return Some(0);
@@ -204,39 +429,36 @@ fn expression_loop(tokens: &[Token], res: &mut Vec<AST>) -> Option<usize> {
     res.push(expression_);
     let mut itr2 = itr.skip(seek);
     match itr2.next() {
-        Some(Token{token:TokenType::Punctuator(Punctuator::Comma), pos:_}) => {
+        Some(Token{token:TokenType::Punctuator(Punctuator::Comma), ..}) => {
             offset += 1;
             match expression_loop(tokens.get(offset..).unwrap(), res) {
                 Some(seek) => Some(offset+seek),
                 None => None
             }
         }
-        Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisRight), pos:_}) => {
+        Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisRight), ..}) => {
             Some(offset)
         }
         _ => return None
     }
 }
 
-fn expression_list(tokens: &[Token]) -> Option<(Vec<AST>, usize)> {
-    let mut res = Vec::<AST>::new();
+fn expression_list(tokens: &[Token]) -> Option<(Vec<Expr>, usize)> {
+    let mut res = Vec::<Expr>::new();
     match expression_loop(tokens, &mut res) {
         Some(seek) => Some((res, seek)),
         None => None
     }
 }
 
-fn call(tokens: &[Token], funcname: String) -> Option<(AST, usize)> {
+fn call(tokens: &[Token], funcname: String) -> Option<(Call, usize)> {
     let itr = tokens.iter().skip(2);
     match expression_list(tokens.get(2..).unwrap()) {
         Some((expressions, seek)) => {
             let mut itr2 = itr.skip(seek);
             match itr2.next() {
-                Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisRight), pos:_}) => {
-                    Some((AST::Node(Node {
-                        op: Operator::Call{name: funcname},
-                        children: expressions
-                    }), seek+1))
+                Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisRight), ..}) => {
+                    Some((Call{name: funcname, args: expressions}, seek+1))
                 }
                 Some(other) => {
                     println!("In call, At {:?}: Unexpected {:?}, expected )", other.pos, other.token);
@@ -252,10 +474,65 @@ fn call(tokens: &[Token], funcname: String) -> Option<(AST, usize)> {
     }
 }
 
-fn if_statement(tokens: &[Token]) -> Option<(AST, usize)> {
+fn braced_expression(tokens: &[Token]) -> Option<(Expr, usize)> {
+    match tokens.first() {
+        Some(Token{token:TokenType::Punctuator(Punctuator::BraceLeft), ..}) => {}
+        Some(other) => {
+            println!("In if_expression, At {:?}: Unexpected {:?}, expected {{", other.pos, other.token);
+            return None
+        }
+        None => {
+            println!("In if_expression, Unexpected EOF, expected {{");
+            return None
+        }
+    }
+    let (ast, seek) = expression(tokens.get(1..).unwrap())?;
+    match tokens.get(1+seek) {
+        Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), ..}) => {}
+        Some(other) => {
+            println!("In if_expression, At {:?}: Unexpected {:?}, expected }}", other.pos, other.token);
+            return None
+        }
+        None => {
+            println!("In if_expression, Unexpected EOF, expected }}");
+            return None
+        }
+    }
+    Some((ast, 1+seek+1))
+}
+
+fn if_expression(tokens: &[Token]) -> Option<(Expr, usize)> {
+    let (cond, seek) = expression(tokens.get(1..).unwrap())?;
+    let (then, seek2) = braced_expression(tokens.get((1+seek)..).unwrap())?;
+    let seek = 1 + seek + seek2;
+    match tokens.get(seek) {
+        Some(Token{token:TokenType::Keyword(Keyword::ELSE), ..}) => {}
+        Some(other) => {
+            println!("In if_expression, At {:?}: Unexpected {:?}, expected else", other.pos, other.token);
+            return None
+        }
+        None => {
+            println!("In if_expression, Unexpected EOF, expected else");
+            return None
+        }
+    }
+    let (otherwise, seek2) = match tokens.get(seek+1) {
+        Some(Token{token:TokenType::Keyword(Keyword::IF), ..}) => {
+            if_expression(tokens.get((seek+1)..).unwrap())?
+        }
+        _ => braced_expression(tokens.get((seek+1)..).unwrap())?
+    };
+    Some((Expr::Conditional{
+        cond: Box::new(cond),
+        then: Box::new(then),
+        otherwise: Box::new(otherwise)
+    }, seek+1+seek2))
+}
+
+fn if_statement(tokens: &[Token]) -> Option<(Stmt, usize)> {
     let mut itr = tokens.iter();
     match itr.next() {
-        Some(Token{token:TokenType::Keyword(Keyword::IF), pos:_}) => {}
+        Some(Token{token:TokenType::Keyword(Keyword::IF), ..}) => {}
         Some(other) => {
             println!("In if_statement, At {:?}: Unexpected {:?}, expected if", other.pos, other.token);
             return None
@@ -268,7 +545,7 @@ fn if_statement(tokens: &[Token]) -> Option<(AST, usize)> {
     let (cond, seek) = expression(tokens.get(1..).unwrap())?;
     let mut itr2 = itr.skip(seek);
     match itr2.next() {
-        Some(Token{token:TokenType::Punctuator(Punctuator::BraceLeft), pos:_}) => {}
+        Some(Token{token:TokenType::Punctuator(Punctuator::BraceLeft), ..}) => {}
         Some(other) => {
             println!("In if_statement, At {:?}: Unexpected {:?}, expected {{", other.pos, other.token);
             return None
@@ -281,7 +558,7 @@ fn if_statement(tokens: &[Token]) -> Option<(AST, usize)> {
     let (statements, seek2) = statement_list(tokens.get((1+seek+1)..).unwrap())?;
     let mut itr3 = itr2.skip(seek2);
     match itr3.next() {
-        Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), pos:_}) => {}
+        Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), ..}) => {}
         Some(other) => {
             println!("In if_statement, At {:?}: Unexpected {:?}, expected }}", other.pos, other.token);
             return None
@@ -292,13 +569,13 @@ fn if_statement(tokens: &[Token]) -> Option<(AST, usize)> {
         }
     }
     match itr3.next() {
-        Some(Token{token:TokenType::Keyword(Keyword::ELSIF), pos:_}) => {
+        Some(Token{token:TokenType::Keyword(Keyword::ELSIF), ..}) => {
             println!("Unsopported keyword: elsif");
             return None;
         }
-        Some(Token{token:TokenType::Keyword(Keyword::ELSE), pos:_}) => {
+        Some(Token{token:TokenType::Keyword(Keyword::ELSE), ..}) => {
             match itr3.next() {
-                Some(Token{token:TokenType::Punctuator(Punctuator::BraceLeft), pos:_}) => {}
+                Some(Token{token:TokenType::Punctuator(Punctuator::BraceLeft), ..}) => {}
                 Some(other) => {
                     println!("In if_statement, At {:?}: Unexpected {:?}, expected {{", other.pos, other.token);
                     return None;
@@ -311,7 +588,7 @@ fn if_statement(tokens: &[Token]) -> Option<(AST, usize)> {
             let (else_statements, seek3) = statement_list(tokens.get((1+seek+1+seek2+3)..).unwrap())?;
             let mut itr4 = itr3.skip(seek3);
             match itr4.next() {
-                Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), pos:_}) => {}
+                Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), ..}) => {}
                 Some(other) => {
                     println!("In if_statement, At {:?}: Unexpected {:?}, expected }}", other.pos, other.token);
                     return None
@@ -321,21 +598,16 @@ fn if_statement(tokens: &[Token]) -> Option<(AST, usize)> {
                     return None
                 }
             }
-            Some((AST::Node(Node {
-                op: Operator::If,
-                children: vec![cond, statements, else_statements]
-            }), 1+seek+1+seek2+3+seek3+1))
+            Some((Stmt::If{cond, then: statements, otherwise: Some(else_statements)},
+                  1+seek+1+seek2+3+seek3+1))
         }
         _ => {
-            Some((AST::Node(Node {
-                op: Operator::If,
-                children: vec![cond, statements]
-            }), 1+seek+1+seek2+1))
+            Some((Stmt::If{cond, then: statements, otherwise: None}, 1+seek+1+seek2+1))
         }
     }
 }
 
-fn return_statement(tokens: &[Token]) -> Option<(AST, usize)> {
+fn return_statement(tokens: &[Token]) -> Option<(Stmt, usize)> {
     if tokens.len() < 1 {
         println!("In return_statement, Unexpected EOF, expected return");
         return None;
@@ -343,11 +615,14 @@ fn return_statement(tokens: &[Token]) -> Option<(AST, usize)> {
     if tokens[0].token != TokenType::Keyword(Keyword::RETURN) {
         println!("In return_statement, At {:?}: Unexpected {:?}, expected return", tokens[0].pos, tokens[0].token);
     }
+    if let Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), ..}) = tokens.get(1) {
+        return Some((Stmt::Return(None), 2));
+    }
     match expression(tokens.get(1..).unwrap()) {
         Some((ast, seek)) => {
             let mut itr = tokens.iter().skip(1+seek);
             match itr.next() {
-                Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), pos:_}) => {}
+                Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), ..}) => {}
                 Some(other) => {
                     println!("In return_statement, At {:?}: Unexpected {:?}, expected ;", other.pos, other.token);
                     return None;
@@ -357,15 +632,12 @@ fn return_statement(tokens: &[Token]) -> Option<(AST, usize)> {
                     return None;
                 }
             }
-            Some((AST::Node(Node {
-                op: Operator::Return,
-                children: vec![ast]
-            }), 1+seek+1))
+            Some((Stmt::Return(Some(ast)), 1+seek+1))
         }
         None => {
             let mut itr = tokens.iter().skip(1);
             match itr.next() {
-                Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), pos:_}) => {}
+                Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), ..}) => {}
                 Some(other) => {
                     println!("In return_statement, At {:?}: Unexpected {:?}, expected ;", other.pos, other.token);
                     return None;
@@ -375,27 +647,419 @@ fn return_statement(tokens: &[Token]) -> Option<(AST, usize)> {
                     return None;
                 }
             }
-            Some((AST::Node(Node {
-                op: Operator::Return,
-                children: Vec::new()
-            }), 2))
+            Some((Stmt::Return(None), 2))
+        }
+    }
+}
+
+fn pattern_value(tokens: &[Token]) -> Option<(Literal, usize)> {
+    match (tokens.first(), tokens.get(1)) {
+        (Some(Token{token:TokenType::Constant(imm), ..}), _) => Some((Literal::Int(*imm), 1)),
+        (Some(Token{token:TokenType::Char(c), ..}), _) => Some((Literal::Char(*c), 1)),
+        (Some(Token{token:TokenType::Punctuator(Punctuator::Minus), ..}),
+         Some(Token{token:TokenType::Constant(imm), ..})) => Some((Literal::Int(-*imm), 2)),
+        (Some(other), _) => {
+            println!("In pattern, At {:?}: Unexpected {:?}, expected constant", other.pos, other.token);
+            None
+        }
+        (None, _) => {
+            println!("In pattern, Unexpected EOF, expected constant");
+            None
+        }
+    }
+}
+
+fn pattern(tokens: &[Token]) -> Option<(Pattern, usize)> {
+    if let Some(Token{token:TokenType::Identifier(name), ..}) = tokens.first() {
+        if name == "_" {
+            return Some((Pattern::Wildcard, 1));
+        }
+        return Some((Pattern::Variant(name.to_string()), 1));
+    }
+    let (lo, seek) = pattern_value(tokens)?;
+    match tokens.get(seek) {
+        Some(Token{token:TokenType::Punctuator(Punctuator::DotDotEqual), pos, ..}) => {
+            let (hi, seek2) = pattern_value(tokens.get((seek+1)..).unwrap())?;
+            if hi.value() < lo.value() {
+                println!("In pattern, At {:?}: empty range {}..={}", pos, lo.value(), hi.value());
+                return None
+            }
+            Some((Pattern::Range(lo, hi), seek+1+seek2))
+        }
+        _ => Some((Pattern::Range(lo, lo), seek))
+    }
+}
+
+fn match_statement(tokens: &[Token]) -> Option<(Stmt, usize)> {
+    let (scrutinee, seek) = expression(tokens.get(1..).unwrap())?;
+    let mut seek = 1 + seek;
+    match tokens.get(seek) {
+        Some(Token{token:TokenType::Punctuator(Punctuator::BraceLeft), ..}) => {}
+        Some(other) => {
+            println!("In match_statement, At {:?}: Unexpected {:?}, expected {{", other.pos, other.token);
+            return None
+        }
+        None => {
+            println!("In match_statement, Unexpected EOF, expected {{");
+            return None
+        }
+    }
+    seek += 1;
+    let mut arms = Vec::new();
+    loop {
+        if let Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), ..}) = tokens.get(seek) {
+            break;
+        }
+        let mut body: Vec<Stmt> = tokens[seek].comments.iter().cloned().map(Stmt::Comment).collect();
+        let mut patterns = Vec::new();
+        loop {
+            let (pat, seek2) = pattern(tokens.get(seek..).unwrap())?;
+            patterns.push(pat);
+            seek += seek2;
+            match tokens.get(seek) {
+                Some(Token{token:TokenType::Punctuator(Punctuator::Pipe), ..}) => seek += 1,
+                _ => break
+            }
+        }
+        match (tokens.get(seek), tokens.get(seek+1)) {
+            (Some(Token{token:TokenType::Punctuator(Punctuator::FatArrow), ..}),
+             Some(Token{token:TokenType::Punctuator(Punctuator::BraceLeft), ..})) => {}
+            (Some(other), _) => {
+                println!("In match_statement, At {:?}: Unexpected {:?}, expected => {{", other.pos, other.token);
+                return None
+            }
+            (None, _) => {
+                println!("In match_statement, Unexpected EOF, expected => {{");
+                return None
+            }
+        }
+        let (stmts, seek2) = statement_list(tokens.get((seek+2)..).unwrap())?;
+        body.extend(stmts);
+        seek += 2 + seek2;
+        match tokens.get(seek) {
+            Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), ..}) => seek += 1,
+            Some(other) => {
+                println!("In match_statement, At {:?}: Unexpected {:?}, expected }}", other.pos, other.token);
+                return None
+            }
+            None => {
+                println!("In match_statement, Unexpected EOF, expected }}");
+                return None
+            }
+        }
+        if let Some(Token{token:TokenType::Punctuator(Punctuator::Comma), ..}) = tokens.get(seek) {
+            seek += 1;
+        }
+        arms.push(Arm{patterns, body});
+    }
+    Some((Stmt::Match{scrutinee, arms}, seek+1))
+}
+
+fn assert_statement(tokens: &[Token]) -> Option<(Stmt, usize)> {
+    let line = tokens[0].pos.line + 1;
+    match tokens.get(1) {
+        Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisLeft), ..}) => {}
+        Some(other) => {
+            println!("In assert_statement, At {:?}: Unexpected {:?}, expected (", other.pos, other.token);
+            return None
+        }
+        None => {
+            println!("In assert_statement, Unexpected EOF, expected (");
+            return None
+        }
+    }
+    let (cond, seek) = expression(tokens.get(2..).unwrap())?;
+    let mut seek = 2 + seek;
+    let mut message = None;
+    if let Some(Token{token:TokenType::Punctuator(Punctuator::Comma), ..}) = tokens.get(seek) {
+        match tokens.get(seek+1) {
+            Some(Token{token:TokenType::String(text), ..}) => message = Some(text.to_string()),
+            Some(other) => {
+                println!("In assert_statement, At {:?}: Unexpected {:?}, expected string", other.pos, other.token);
+                return None
+            }
+            None => {
+                println!("In assert_statement, Unexpected EOF, expected string");
+                return None
+            }
+        }
+        seek += 2;
+    }
+    match (tokens.get(seek), tokens.get(seek+1)) {
+        (Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisRight), ..}),
+         Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), ..})) => {}
+        (Some(other), _) => {
+            println!("In assert_statement, At {:?}: Unexpected {:?}, expected );", other.pos, other.token);
+            return None
+        }
+        (None, _) => {
+            println!("In assert_statement, Unexpected EOF, expected );");
+            return None
+        }
+    }
+    Some((Stmt::Assert{cond, file: String::new(), line, message}, seek+2))
+}
+
+fn format_spec(spec: &str, pos: &Pos) -> Option<Piece> {
+    if spec.is_empty() {
+        return Some(Piece::Number{width: 0, fill: ' ', left: false});
+    }
+    let mut rest = match spec.strip_prefix(':') {
+        Some(rest) => rest,
+        None => {
+            println!("At {:?}: Invalid format {{{}}}, expected {{}} or {{:...}}", pos, spec);
+            return None
+        }
+    };
+    let left = rest.starts_with('<');
+    if left {
+        rest = &rest[1..];
+    }
+    let zero = rest.starts_with('0');
+    if zero {
+        rest = &rest[1..];
+    }
+    let character = rest.ends_with('c');
+    if character {
+        rest = &rest[..rest.len()-1];
+    }
+    let width = if rest.is_empty() {
+        0
+    } else {
+        match rest.parse::<usize>() {
+            Ok(width) => width,
+            Err(_) => {
+                println!("At {:?}: Invalid width in format {{{}}}", pos, spec);
+                return None
+            }
+        }
+    };
+    if width > MAX_WIDTH {
+        println!("At {:?}: Width in format {{{}}} exceeds {}, the widest a number prints", pos, spec, MAX_WIDTH);
+        return None
+    }
+    if zero && (left || character) {
+        println!("At {:?}: Zero padding in format {{{}}} applies to right-aligned numbers only", pos, spec);
+        return None
+    }
+    if character {
+        Some(Piece::Char{width, left})
+    } else {
+        Some(Piece::Number{width, fill: if zero { '0' } else { ' ' }, left})
+    }
+}
+
+pub fn format_string(text: &str, pos: &Pos) -> Option<Vec<Piece>> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(d) => spec.push(d),
+                        None => {
+                            println!("At {:?}: Unterminated {{ in format string", pos);
+                            return None
+                        }
+                    }
+                }
+                if !literal.is_empty() {
+                    pieces.push(Piece::Text(literal));
+                    literal = String::new();
+                }
+                pieces.push(format_spec(&spec, pos)?);
+            }
+            '}' => {
+                println!("At {:?}: Unmatched }} in format string, use }}}} to print it", pos);
+                return None
+            }
+            _ => literal.push(c)
         }
     }
+    if !literal.is_empty() {
+        pieces.push(Piece::Text(literal));
+    }
+    Some(pieces)
+}
+
+fn print_statement(tokens: &[Token]) -> Option<(Stmt, usize)> {
+    let (text, pos) = match tokens.get(2) {
+        Some(Token{token:TokenType::String(text), pos, ..}) => (text, pos),
+        _ => unreachable!()
+    };
+    let pieces = format_string(text, pos)?;
+    let mut seek = 3;
+    let mut args = Vec::new();
+    if let Some(Token{token:TokenType::Punctuator(Punctuator::Comma), ..}) = tokens.get(seek) {
+        let (exps, seek2) = expression_list(tokens.get((seek+1)..).unwrap())?;
+        args = exps;
+        seek += 1 + seek2;
+    }
+    match (tokens.get(seek), tokens.get(seek+1)) {
+        (Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisRight), ..}),
+         Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), ..})) => {}
+        (Some(other), _) => {
+            println!("In print_statement, At {:?}: Unexpected {:?}, expected );", other.pos, other.token);
+            return None
+        }
+        (None, _) => {
+            println!("In print_statement, Unexpected EOF, expected );");
+            return None
+        }
+    }
+    let expected = pieces.iter().filter(|piece| !matches!(piece, Piece::Text(_))).count();
+    if expected != args.len() {
+        println!("In print_statement, At {:?}: Format string takes {} arguments, but {} were given",
+                 pos, expected, args.len());
+        return None
+    }
+    Some((Stmt::Print{pieces, args}, seek+2))
 }
 
-fn statement(tokens: &[Token]) -> Option<(AST, usize)> {
+fn asm_statement(tokens: &[Token]) -> Option<(Stmt, usize)> {
+    let mut seek = 1;
+    let mut inputs = Vec::new();
+    if let Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisLeft), ..}) = tokens.get(seek) {
+        let (exps, seek2) = expression_list(tokens.get((seek+1)..).unwrap())?;
+        match tokens.get(seek+1+seek2) {
+            Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisRight), ..}) => {}
+            _ => {
+                println!("In asm_statement, Unexpected {:?}, expected )", tokens.get(seek+1+seek2));
+                return None
+            }
+        }
+        inputs = exps;
+        seek += 1 + seek2 + 1;
+    }
+    let mut outputs = Vec::new();
+    if let (Some(Token{token:TokenType::Punctuator(Punctuator::Minus), ..}),
+            Some(Token{token:TokenType::Punctuator(Punctuator::Greater), ..})) = (tokens.get(seek), tokens.get(seek+1)) {
+        let (names, types, seek2) = argument_list(tokens.get((seek+2)..).unwrap())?;
+        if types.iter().any(|ty| ty.is_some()) {
+            println!("In asm_statement, asm outputs cannot have types");
+            return None
+        }
+        outputs = names;
+        seek += 2 + seek2;
+    }
+    match tokens.get(seek) {
+        Some(Token{token:TokenType::Punctuator(Punctuator::BraceLeft), ..}) => {}
+        Some(other) => {
+            println!("In asm_statement, At {:?}: Unexpected {:?}, expected {{", other.pos, other.token);
+            return None
+        }
+        None => {
+            println!("In asm_statement, Unexpected EOF, expected {{");
+            return None
+        }
+    }
+    seek += 1;
+    let mut instructions: Vec<Vec<String>> = vec![Vec::new()];
+    let mut line = tokens.get(seek).map(|token| token.pos.line);
+    loop {
+        if let Some(token) = tokens.get(seek) {
+            if line != Some(token.pos.line) {
+                instructions.push(Vec::new());
+                line = Some(token.pos.line);
+            }
+        }
+        let current = instructions.last_mut().unwrap();
+        match tokens.get(seek) {
+            Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), ..}) => break,
+            Some(Token{token:TokenType::Identifier(mnemonic), ..}) if mnemonic == "PUSH" => {
+                match (tokens.get(seek+1), tokens.get(seek+2)) {
+                    (Some(Token{token:TokenType::Constant(imm), ..}), _) => {
+                        current.push(format!("PUSH {}", imm));
+                        seek += 2;
+                    }
+                    (Some(Token{token:TokenType::Char(c), ..}), _) => {
+                        current.push(format!("PUSH {}", *c as i32));
+                        seek += 2;
+                    }
+                    (Some(Token{token:TokenType::Punctuator(Punctuator::Minus), ..}),
+                     Some(Token{token:TokenType::Constant(imm), ..})) => {
+                        current.push(format!("PUSH -{}", imm));
+                        seek += 3;
+                    }
+                    _ => {
+                        println!("In asm_statement, At {:?}: PUSH needs a constant", tokens[seek].pos);
+                        return None
+                    }
+                }
+            }
+            Some(Token{token:TokenType::Identifier(mnemonic), ..}) => {
+                current.push(mnemonic.to_string());
+                seek += 1;
+            }
+            Some(other) => {
+                println!("In asm_statement, At {:?}: Unexpected {:?}, expected instruction", other.pos, other.token);
+                return None
+            }
+            None => {
+                println!("In asm_statement, Unexpected EOF, expected }}");
+                return None
+            }
+        }
+    }
+    instructions.retain(|line| !line.is_empty());
+    Some((Stmt::Asm{inputs, instructions, outputs}, seek+1))
+}
+
+fn compound_operator(punc: &Punctuator) -> Option<BinaryOp> {
+    match punc {
+        Punctuator::PlusEqual => Some(BinaryOp::Add),
+        Punctuator::MinusEqual => Some(BinaryOp::Sub),
+        Punctuator::StarEqual => Some(BinaryOp::Multiply),
+        Punctuator::SlashEqual => Some(BinaryOp::Division),
+        Punctuator::PercentEqual => Some(BinaryOp::Modulo),
+        _ => None
+    }
+}
+
+fn statement(tokens: &[Token]) -> Option<(Stmt, usize)> {
     let mut itr = tokens.iter();
-    let left: Leaf;
+    let left: String;
     match itr.next() {
-        Some(Token{token:TokenType::Identifier(identifier), pos:_}) => {
-            left = Leaf::Identifier(identifier.to_string());
+        Some(Token{token:TokenType::Identifier(identifier), ..}) if identifier == "assert" => {
+            return assert_statement(tokens);
+        }
+        Some(Token{token:TokenType::Identifier(identifier), ..})
+            if identifier == "print" &&
+               matches!(tokens.get(1), Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisLeft), ..})) &&
+               matches!(tokens.get(2), Some(Token{token:TokenType::String(_), ..})) => {
+            return print_statement(tokens);
+        }
+        Some(Token{token:TokenType::Identifier(identifier), ..}) => {
+            left = identifier.to_string();
         }
-        Some(Token{token:TokenType::Keyword(Keyword::IF), pos:_}) => {
+        Some(Token{token:TokenType::Keyword(Keyword::IF), ..}) => {
             return if_statement(tokens);
         }
-        Some(Token{token:TokenType::Keyword(Keyword::RETURN), pos:_}) => {
+        Some(Token{token:TokenType::Keyword(Keyword::RETURN), ..}) => {
             return return_statement(tokens);
         }
+        Some(Token{token:TokenType::Keyword(Keyword::ASM), ..}) => {
+            return asm_statement(tokens);
+        }
+        Some(Token{token:TokenType::Keyword(Keyword::MATCH), ..}) => {
+            return match_statement(tokens);
+        }
+        Some(Token{token:TokenType::Keyword(Keyword::FN), ..}) => {
+            let (decl, seek) = function_declaration(tokens)?;
+            return Some((Stmt::Fn(decl), seek));
+        }
         Some(other) => {
             println!("In statement, At {:?}: Unexpected {:?}, expected identifier", other.pos, other.token);
             return None
@@ -406,38 +1070,54 @@ fn statement(tokens: &[Token]) -> Option<(AST, usize)> {
         }
     }
     match itr.next() {
-        Some(Token{token:TokenType::Punctuator(Punctuator::Equal), pos:_}) => {
+        Some(Token{token:TokenType::Punctuator(Punctuator::Equal), ..}) => {
             let (exp, seek) = expression(tokens.get(2..).unwrap())?;
             let mut itr2 = itr.skip(seek);
             match itr2.next() {
-                Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), pos:_}) => {}
+                Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), ..}) => {}
                 Some(other) => {
                     println!("In statement, At {:?}: Unexpected {:?}, expected ;", other.pos, other.token);
                     return None
                 }
                 _ => return None
             }
-            Some((AST::Node(Node {
-                op: Operator::Substitute,
-                children: vec![
-                    AST::Leaf(left),
-                    exp
-                ]
-            }), 2+seek+1))
+            Some((Stmt::Substitute{name: left, value: exp}, 2+seek+1))
         }
-        Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisLeft), pos:_}) => {
-            let funcname = match left {
-                Leaf::Identifier(name) => {
-                    name
+        Some(Token{token:TokenType::Punctuator(punc), ..}) if compound_operator(punc).is_some() => {
+            let (exp, seek) = expression(tokens.get(2..).unwrap())?;
+            match tokens.get(2+seek) {
+                Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), ..}) => {}
+                Some(other) => {
+                    println!("In statement, At {:?}: Unexpected {:?}, expected ;", other.pos, other.token);
+                    return None
+                }
+                _ => return None
+            }
+            Some((Stmt::Update{name: left, op: compound_operator(punc).unwrap(), value: exp}, 2+seek+1))
+        }
+        Some(Token{token:TokenType::Punctuator(punc @ (Punctuator::Plus | Punctuator::Minus)), ..})
+                if tokens.get(2).map(|t| &t.token) == Some(&TokenType::Punctuator(punc.clone())) => {
+            match tokens.get(3) {
+                Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), ..}) => {}
+                Some(other) => {
+                    println!("In statement, At {:?}: Unexpected {:?}, expected ;", other.pos, other.token);
+                    return None
                 }
                 _ => return None
+            }
+            let op = match punc {
+                Punctuator::Plus => BinaryOp::Add,
+                _ => BinaryOp::Sub
             };
-            match call(tokens, funcname) {
-                Some((node, seek)) => {
+            Some((Stmt::Update{name: left, op, value: Expr::Constant(1)}, 4))
+        }
+        Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisLeft), ..}) => {
+            match call(tokens, left) {
+                Some((call, seek)) => {
                     let mut itr2 = itr.skip(seek);
                     match itr2.next() {
-                        Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), pos:_}) => {
-                            Some((node, 2+seek+1))
+                        Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), ..}) => {
+                            Some((Stmt::Call(call), 2+seek+1))
                         }
                         Some(other) => {
                             println!("In statement, At {:?}, Unexpected {:?}, expected ;", other.pos, other.token);
@@ -460,7 +1140,15 @@ fn statement(tokens: &[Token]) -> Option<(AST, usize)> {
     }
 }
 
-fn statements_loop(tokens: &[Token], node: &mut Node) -> Option<usize> {
+// Comments before a statement or before the closing brace stay in the body.
+fn leading_comments(token: &Token) -> impl Iterator<Item = &Comment> {
+    token.comments.iter().filter(move |comment| comment.line < token.pos.line)
+}
+
+fn statements_loop(tokens: &[Token], res: &mut Vec<Stmt>) -> Option<usize> {
+    if let Some(token) = tokens.first() {
+        res.extend(leading_comments(token).cloned().map(Stmt::Comment));
+    }
     if tokens.len() >= 1 {
         if tokens[0].token == TokenType::Punctuator(Punctuator::BraceRight) {
             return Some(0);
@@ -468,8 +1156,8 @@ fn statements_loop(tokens: &[Token], node: &mut Node) -> Option<usize> {
     }
     match statement(tokens) {
         Some((stm, seek)) => {
-            node.children.push(stm);
-            match statements_loop(tokens.get(seek..).unwrap(), node) {
+            res.push(stm);
+            match statements_loop(tokens.get(seek..).unwrap(), res) {
                 Some(len) => Some(seek+len),
                 None => None
             }
@@ -478,126 +1166,346 @@ fn statements_loop(tokens: &[Token], node: &mut Node) -> Option<usize> {
     }
 }
 
-fn statement_list(tokens: &[Token]) -> Option<(AST, usize)> {
-    let mut res = Node {
-        op: Operator::Statement,
-        children: Vec::new()
-    };
+fn statement_list(tokens: &[Token]) -> Option<(Vec<Stmt>, usize)> {
+    let mut res = Vec::new();
     match statements_loop(tokens, &mut res) {
         Some(seek) => {
-            Some((AST::Node(res), seek))
+            Some((res, seek))
         }
         None => None
     }
 }
 
-fn argument_list(tokens: &[Token]) -> Option<(Vec<String>, usize)> {
-    let mut tokens_itr = tokens.iter();
-    match tokens_itr.next() {
-        Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisLeft), pos:_}) => {
-            let mut res = Vec::<String>::new();
-            let mut len = 1;
-            loop {
-                match tokens_itr.next() {
-                    Some(Token{token:TokenType::Identifier(identifier), pos:_}) => {
-                        res.push(identifier.to_string());
-                    }
-                    Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisRight), pos:_}) => {
-                        return Some((res, len+1))
-                    }
-                    Some(other) => {
-                        println!("In argument_list, At {:?}: Unexpected {:?}, expected identifier or )", other.pos, other.token);
-                        return None
-                    }
-                    _ => {
-                        println!("In argument_list, Unexpected EOF, expected identifier or )");
-                        return None
-                    }
+pub fn typed_list(tokens: &[Token], open: Punctuator, close: Punctuator)
+        -> Option<(Vec<String>, Vec<Option<String>>, usize)> {
+    match tokens.first() {
+        Some(Token{token:TokenType::Punctuator(punc), ..}) if *punc == open => {}
+        Some(other) => {
+            println!("In typed_list, At {:?}: Unexpected {:?}, expected {:?}", other.pos, other.token, open);
+            return None
+        }
+        None => {
+            println!("In typed_list, Unexpected EOF, expected {:?}", open);
+            return None
+        }
+    }
+    let mut names = Vec::<String>::new();
+    let mut types = Vec::<Option<String>>::new();
+    let mut len = 1;
+    loop {
+        match tokens.get(len) {
+            Some(Token{token:TokenType::Identifier(identifier), ..}) => {
+                names.push(identifier.to_string());
+            }
+            Some(Token{token:TokenType::Punctuator(punc), ..}) if *punc == close => {
+                return Some((names, types, len+1))
+            }
+            Some(other) => {
+                println!("In typed_list, At {:?}: Unexpected {:?}, expected identifier or {:?}", other.pos, other.token, close);
+                return None
+            }
+            None => {
+                println!("In typed_list, Unexpected EOF, expected identifier or {:?}", close);
+                return None
+            }
+        }
+        len += 1;
+        match (tokens.get(len), tokens.get(len+1)) {
+            (Some(Token{token:TokenType::Punctuator(Punctuator::Colon), ..}),
+             Some(Token{token:TokenType::Identifier(ty), ..})) => {
+                types.push(Some(ty.to_string()));
+                len += 2;
+            }
+            (Some(Token{token:TokenType::Punctuator(Punctuator::Colon), ..}), _) => {
+                println!("In typed_list, Unexpected {:?}, expected type name", tokens.get(len+1));
+                return None
+            }
+            _ => types.push(None)
+        }
+        match tokens.get(len) {
+            Some(Token{token:TokenType::Punctuator(Punctuator::Comma), ..}) => {}
+            Some(Token{token:TokenType::Punctuator(punc), ..}) if *punc == close => {
+                return Some((names, types, len+1))
+            }
+            Some(other) => {
+                println!("In typed_list, At {:?}: Unexpected {:?}, expected , or {:?}", other.pos, other.token, close);
+                return None
+            }
+            None => {
+                println!("In typed_list, Unexpected EOF, expected , or {:?}", close);
+                return None
+            }
+        }
+        len += 1;
+    }
+}
+
+fn argument_list(tokens: &[Token]) -> Option<(Vec<String>, Vec<Option<String>>, usize)> {
+    typed_list(tokens, Punctuator::ParenthesisLeft, Punctuator::ParenthesisRight)
+}
+
+fn struct_declaration(tokens: &[Token]) -> Option<(Decl, usize)> {
+    let name = match tokens.get(1) {
+        Some(Token{token:TokenType::Identifier(name), ..}) => name,
+        Some(other) => {
+            println!("In struct_declaration, At {:?}: Unexpected {:?}, expected identifier", other.pos, other.token);
+            return None
+        }
+        None => {
+            println!("In struct_declaration, Unexpected EOF, expected identifier");
+            return None
+        }
+    };
+    let (fields, types, seek) = typed_list(tokens.get(2..).unwrap(), Punctuator::BraceLeft, Punctuator::BraceRight)?;
+    if fields.is_empty() {
+        println!("Struct {} has no fields", name);
+        return None
+    }
+    Some((Decl::Struct(StructDecl{name: name.to_string(), fields, types, public: false}), 2+seek))
+}
+
+fn enum_declaration(tokens: &[Token]) -> Option<(Decl, usize)> {
+    let name = match tokens.get(1) {
+        Some(Token{token:TokenType::Identifier(name), ..}) => name,
+        Some(other) => {
+            println!("In enum_declaration, At {:?}: Unexpected {:?}, expected identifier", other.pos, other.token);
+            return None
+        }
+        None => {
+            println!("In enum_declaration, Unexpected EOF, expected identifier");
+            return None
+        }
+    };
+    match tokens.get(2) {
+        Some(Token{token:TokenType::Punctuator(Punctuator::BraceLeft), ..}) => {}
+        Some(other) => {
+            println!("In enum_declaration, At {:?}: Unexpected {:?}, expected {{", other.pos, other.token);
+            return None
+        }
+        None => {
+            println!("In enum_declaration, Unexpected EOF, expected {{");
+            return None
+        }
+    }
+    let mut variants: Vec<(String, Option<Literal>)> = Vec::new();
+    let mut len = 3;
+    loop {
+        match tokens.get(len) {
+            Some(Token{token:TokenType::Identifier(variant), ..}) => {
+                if variants.iter().any(|(v, _)| v == variant) {
+                    println!("Variant {} of enum {} is already defined", variant, name);
+                    return None
                 }
                 len += 1;
-                match tokens_itr.next() {
-                    Some(Token{token:TokenType::Punctuator(Punctuator::Comma), pos:_}) => {}
-                    Some(Token{token:TokenType::Punctuator(Punctuator::ParenthesisRight), pos:_}) => {
-                        return Some((res, len+1))
-                    }
-                    Some(other) => {
-                        println!("In argument_list, At {:?}: Unexpected {:?}, expected , or )", other.pos, other.token);
-                        return None
-                    }
-                    _ => {
-                        println!("In argument_list, Unexpected EOF, expected , or )");
-                        return None
-                    }
+                let mut value = None;
+                if let Some(Token{token:TokenType::Punctuator(Punctuator::Equal), ..}) = tokens.get(len) {
+                    let (imm, seek) = pattern_value(tokens.get((len+1)..).unwrap())?;
+                    value = Some(imm);
+                    len += 1 + seek;
                 }
-                len += 1;
+                variants.push((variant.to_string(), value));
+            }
+            Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), ..}) => break,
+            Some(other) => {
+                println!("In enum_declaration, At {:?}: Unexpected {:?}, expected identifier or }}", other.pos, other.token);
+                return None
+            }
+            None => {
+                println!("In enum_declaration, Unexpected EOF, expected identifier or }}");
+                return None
+            }
+        }
+        match tokens.get(len) {
+            Some(Token{token:TokenType::Punctuator(Punctuator::Comma), ..}) => len += 1,
+            Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), ..}) => break,
+            Some(other) => {
+                println!("In enum_declaration, At {:?}: Unexpected {:?}, expected , or }}", other.pos, other.token);
+                return None
+            }
+            None => {
+                println!("In enum_declaration, Unexpected EOF, expected , or }}");
+                return None
             }
         }
+    }
+    if variants.is_empty() {
+        println!("Enum {} has no variants", name);
+        return None
+    }
+    Some((Decl::Enum(EnumDecl{name: name.to_string(), variants, public: false}), len+1))
+}
+
+fn function_declaration(tokens: &[Token]) -> Option<(FnDecl, usize)> {
+    let mut tokens_itr = tokens.iter().skip(1);
+    match tokens_itr.next() {
+        Some(Token{token:TokenType::Punctuator(Punctuator::BracketLeft), ..}) => (),
+        Some(other) => {
+            println!("In function_declaration, At {:?}:Unexpected {:?}, expected {{", other.pos, other.token);
+            return None
+        }
+        _ => {
+            println!("In function_declaration, Unexpected EOF, expected {{");
+            return None
+        }
+    }
+    let retnum;
+    let rettype;
+    match tokens_itr.next() {
+        Some(Token{token:TokenType::Constant(num), ..}) => {
+            retnum = *num as usize;
+            rettype = None;
+        }
+        Some(Token{token:TokenType::Identifier(ty), ..}) => {
+            retnum = 0;
+            rettype = Some(ty.to_string());
+        }
+        _ => return None
+    }
+    match tokens_itr.next() {
+        Some(Token{token:TokenType::Punctuator(Punctuator::BracketRight), ..}) => (),
+        _ => return None
+    }
+    let name;
+    match tokens_itr.next() {
+        Some(Token{token:TokenType::Identifier(s), ..}) => name = s,
+        _ => return None
+    }
+    let (args, types, seek) = argument_list(tokens.get(5..).unwrap())?;
+    let mut tokens_itr_2 = tokens_itr.skip(seek);
+    match tokens_itr_2.next() {
+        Some(Token{token:TokenType::Punctuator(Punctuator::BraceLeft), ..}) => (),
+        _ => return None
+    }
+    let (statements, seek2) = statement_list(tokens.get((6+seek)..).unwrap())?;
+    let mut tokens_itr_3 = tokens_itr_2.skip(seek2);
+    match tokens_itr_3.next() {
+        Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), ..}) => (),
         _ => return None
     }
+    let res = FnDecl {
+        name: name.to_string(), args, types, retnum, rettype, public: false,
+        constant: false, inline: Inline::Auto, body: statements
+    };
+    Some((res, 5+seek+2+seek2))
 }
 
-fn declaration(tokens: &[Token]) -> Option<(AST, usize)> {
+// Parses `#[inline]` or `#[noinline]`.
+pub fn attribute(tokens: &[Token]) -> Option<(Inline, usize)> {
+    let name = match (tokens.get(1), tokens.get(2), tokens.get(3)) {
+        (Some(Token{token:TokenType::Punctuator(Punctuator::BracketLeft), ..}),
+         Some(Token{token:TokenType::Identifier(name), ..}),
+         Some(Token{token:TokenType::Punctuator(Punctuator::BracketRight), ..})) => name,
+        _ => {
+            println!("In attribute, At {:?}: expected #[inline] or #[noinline]", tokens[0].pos);
+            return None
+        }
+    };
+    match name.as_str() {
+        "inline" => Some((Inline::Always, 4)),
+        "noinline" => Some((Inline::Never, 4)),
+        _ => {
+            println!("In attribute, At {:?}: Unknown attribute {}", tokens[2].pos, name);
+            None
+        }
+    }
+}
+
+fn declaration(tokens: &[Token]) -> Option<(Decl, usize)> {
     let mut tokens_itr = tokens.iter();
     match tokens_itr.next() {
-        Some(Token{token:TokenType::Keyword(Keyword::FN), pos:_}) => {
-            match tokens_itr.next() {
-                Some(Token{token:TokenType::Punctuator(Punctuator::BracketLeft), pos:_}) => (),
-                Some(other) => {
-                    println!("In declaration, At {:?}:Unexpected {:?}, expected {{", other.pos, other.token);
+        Some(Token{token:TokenType::Keyword(Keyword::FN), ..}) => {
+            let (decl, seek) = function_declaration(tokens)?;
+            Some((Decl::Fn(decl), seek))
+        }
+        Some(Token{token:TokenType::Keyword(Keyword::STRUCT), ..}) => {
+            struct_declaration(tokens)
+        }
+        Some(Token{token:TokenType::Keyword(Keyword::ENUM), ..}) => {
+            enum_declaration(tokens)
+        }
+        Some(Token{token:TokenType::Keyword(Keyword::IMPORT), ..}) => {
+            import(tokens)
+        }
+        Some(Token{token:TokenType::Keyword(Keyword::PUB), ..}) => {
+            let (mut ast, seek) = declaration(tokens.get(1..).unwrap())?;
+            match ast {
+                Decl::Fn(FnDecl{ref mut public, ..}) |
+                Decl::Struct(StructDecl{ref mut public, ..}) |
+                Decl::Enum(EnumDecl{ref mut public, ..}) => {
+                    *public = true;
+                }
+                Decl::Import{..} | Decl::Comment(_) => {
+                    println!("In declaration, At {:?}: pub must be followed by fn, struct or enum", tokens[0].pos);
                     return None
                 }
+            }
+            Some((ast, 1+seek))
+        }
+        Some(Token{token:TokenType::Punctuator(Punctuator::Hash), ..}) => {
+            let (inline, len) = attribute(tokens)?;
+            let (mut ast, seek) = declaration(tokens.get(len..).unwrap())?;
+            match ast {
+                Decl::Fn(FnDecl{inline: ref mut attr, ..}) => {
+                    *attr = inline;
+                }
                 _ => {
-                    println!("In declaration, Unexpected EOF, expected {{");
+                    println!("In declaration, At {:?}: an attribute must be followed by fn", tokens[0].pos);
                     return None
                 }
             }
-            let retnum;
-            match tokens_itr.next() {
-                Some(Token{token:TokenType::Constant(num), pos:_}) => {
-                    retnum = num;
+            Some((ast, len+seek))
+        }
+        Some(Token{token:TokenType::Keyword(Keyword::CONST), ..}) => {
+            let (mut ast, seek) = declaration(tokens.get(1..).unwrap())?;
+            match ast {
+                Decl::Fn(FnDecl{ref mut constant, ..}) => {
+                    *constant = true;
+                }
+                _ => {
+                    println!("In declaration, At {:?}: const must be followed by fn", tokens[0].pos);
+                    return None
                 }
-                _ => return None
-            }
-            match tokens_itr.next() {
-                Some(Token{token:TokenType::Punctuator(Punctuator::BracketRight), pos:_}) => (),
-                _ => return None
-            }
-            let name;
-            match tokens_itr.next() {
-                Some(Token{token:TokenType::Identifier(s), pos:_}) => name = s,
-                _ => return None
-            }
-            let (args, seek) = argument_list(tokens.get(5..).unwrap())?;
-            let mut tokens_itr_2 = tokens_itr.skip(seek);
-            match tokens_itr_2.next() {
-                Some(Token{token:TokenType::Punctuator(Punctuator::BraceLeft), pos:_}) => (),
-                _ => return None
-            }
-            let (statements, seek2) = statement_list(tokens.get((6+seek)..).unwrap())?;
-            let mut tokens_itr_3 = tokens_itr_2.skip(seek2);
-            match tokens_itr_3.next() {
-                Some(Token{token:TokenType::Punctuator(Punctuator::BraceRight), pos:_}) => (),
-                _ => return None
             }
-            let res = AST::Node(Node {
-                op: Operator::FunctionDeclare{
-                    name: name.to_string(), args: args, retnum: *retnum as usize
-                },
-                children: vec![statements; 1]
-            });
-            Some((res, 5+seek+2+seek2))
+            Some((ast, 1+seek))
         }
         _ => None
     }
 }
 
-fn declarations_loop(tokens: &[Token], res: &mut Node, count: u32) -> bool {
+fn import(tokens: &[Token]) -> Option<(Decl, usize)> {
+    let path = match tokens.get(1) {
+        Some(Token{token:TokenType::String(path), ..}) => path,
+        Some(other) => {
+            println!("In import, At {:?}: Unexpected {:?}, expected string", other.pos, other.token);
+            return None
+        }
+        None => {
+            println!("In import, Unexpected EOF, expected string");
+            return None
+        }
+    };
+    match tokens.get(2) {
+        Some(Token{token:TokenType::Punctuator(Punctuator::SemiColon), ..}) => {}
+        Some(other) => {
+            println!("In import, At {:?}: Unexpected {:?}, expected ;", other.pos, other.token);
+            return None
+        }
+        None => {
+            println!("In import, Unexpected EOF, expected ;");
+            return None
+        }
+    }
+    Some((Decl::Import{path: path.to_string()}, 3))
+}
+
+fn declarations_loop(tokens: &[Token], res: &mut Vec<Decl>, count: u32) -> bool {
     if tokens.len() == 0 {
         return true;
     }
+    res.extend(leading_comments(&tokens[0]).cloned().map(Decl::Comment));
     match declaration(tokens) {
         Some((ast, seek)) => {
-            res.children.push(ast);
+            res.push(ast);
             declarations_loop(tokens.get(seek..).unwrap(), res, count+1)
         }
         None => {
@@ -607,13 +1515,16 @@ fn declarations_loop(tokens: &[Token], res: &mut Node, count: u32) -> bool {
     }
 }
 
-pub fn parse(tokens: &[Token]) -> Option<AST> {
-    let mut res = Node {
-        op: Operator::Declare,
-        children: Vec::new()
-    };
-    if declarations_loop(tokens, &mut res, 0) {
-        Some(AST::Node(res))
+pub fn parse(tokens: &[Token]) -> Option<Program> {
+    let mut decls = Vec::new();
+    if declarations_loop(tokens, &mut decls, 0) {
+        if let Some(last) = tokens.last() {
+            decls.extend(last.comments.iter()
+                         .filter(|comment| comment.line >= last.pos.line)
+                         .cloned()
+                         .map(Decl::Comment));
+        }
+        Some(Program { decls })
     } else {
         None
     }
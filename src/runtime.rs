@@ -0,0 +1,32 @@
+use super::tokenize;
+use super::tokenize::TokenType;
+use super::parse;
+
+// Heap layout (cells are counted from the bottom of the Piet stack):
+//   cell 0: break, the first never-allocated cell
+//   cell 1: head of the free list (0 = empty)
+//   cell 2..HEAP_SIZE: blocks, each one a size header followed by its cells
+// A pointer points just past the header. A freed block keeps its header and
+// stores the next free block in its first cell.
+pub const HEAP_SIZE: usize = 256;
+pub const HEAP_START: usize = 2;
+
+// The functions of the runtime a program can call.
+pub const FUNCTIONS: [&str; 2] = ["alloc", "free"];
+const HELPERS: [&str; 2] = ["alloc_bump", "alloc_fit"];
+
+pub fn heap() -> Option<parse::Program> {
+    let code = include_str!("runtime/heap.pyt")
+        .replace("HEAP_SIZE", &HEAP_SIZE.to_string());
+    let mut tokens = tokenize::tokenize(&code)?;
+    // An identifier in source never contains a dot, so user functions cannot
+    // clash with the helpers.
+    for token in tokens.iter_mut() {
+        if let TokenType::Identifier(ref mut name) = token.token {
+            if HELPERS.contains(&name.as_str()) {
+                *name = format!("heap.{}", name);
+            }
+        }
+    }
+    parse::parse(&tokens)
+}
@@ -0,0 +1,700 @@
+use super::generate;
+use super::generate::Operator;
+use super::parse::Piece;
+use super::parse::Inline;
+use super::format;
+use super::ir;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// An SSA form of generate::Program for the optimization passes. Every
+// register is written exactly once, control flow lives in a block's
+// terminator, and values flowing into a block are passed as its parameters.
+// The parameters of the entry block are the arguments of the function.
+//
+// `build` converts the output of generate right away; inlining, constant
+// folding and dead code elimination all work on this form, and `lower`
+// converts the result back once for trans. The register form of generate
+// stays as it is: the textual IR and trans are written against it.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Reg(pub usize);
+
+// Hands out the registers of a function, so that passes adding statements
+// never reuse an id.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RegAlloc {
+    next: usize
+}
+
+impl RegAlloc {
+    pub fn new() -> RegAlloc {
+        RegAlloc { next: 0 }
+    }
+
+    pub fn alloc(&mut self) -> Reg {
+        self.next += 1;
+        Reg(self.next - 1)
+    }
+
+    // Registers with consecutive ids, for the results of one statement.
+    pub fn alloc_many(&mut self, count: usize) -> Vec<Reg> {
+        (0..count).map(|_| self.alloc()).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Value {
+    Reg(Reg),
+    Immediate(i32),
+    Label(usize)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Multiply,
+    Division,
+    Modulo,
+    Equal,
+    LessThan,
+    Greater,
+    Call{name: String},
+    CallIndirect{retnum: usize},
+    Asm{instructions: Vec<String>, outputs: Vec<String>},
+    Print{pieces: Vec<Piece>}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub op: Op,
+    pub rets: Vec<Reg>,
+    pub args: Vec<Value>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump{target: usize, args: Vec<Value>},
+    // Branch targets take no parameters; an edge into a block with
+    // parameters goes through a block that only jumps.
+    Branch{cond: Value, then: usize, otherwise: usize},
+    Return(Vec<Value>)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub params: Vec<Reg>,
+    pub statements: Vec<Statement>,
+    pub terminator: Terminator
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub args: Vec<String>,
+    pub types: Vec<Option<String>>,
+    pub retnum: usize,
+    pub rettype: Option<String>,
+    pub constant: bool,
+    pub inline: Inline,
+    // Empty for builtins.
+    pub blocks: Vec<Block>,
+    pub regs: RegAlloc
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub funcs: HashMap<String, Function>,
+    pub structs: HashMap<String, generate::Struct>,
+    pub enums: HashMap<String, Vec<(String, i32)>>,
+    pub labels: Vec<String>
+}
+
+impl Terminator {
+    pub fn targets(&self) -> Vec<usize> {
+        match *self {
+            Terminator::Jump{target, ..} => vec![target],
+            Terminator::Branch{then, otherwise, ..} => vec![then, otherwise],
+            Terminator::Return(_) => Vec::new()
+        }
+    }
+
+    pub fn values_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Terminator::Jump{args, ..} | Terminator::Return(args) => args.iter_mut().collect(),
+            Terminator::Branch{cond, ..} => vec![cond]
+        }
+    }
+}
+
+impl Function {
+    // The blocks reachable from the entry, each after its first visit in a
+    // depth-first walk, so that every block but a loop head comes after one
+    // of its predecessors.
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((index, next)) = stack.pop() {
+            // The then block is visited last so that it comes right after
+            // its branch.
+            let mut targets = self.blocks[index].terminator.targets();
+            targets.reverse();
+            match targets.get(next) {
+                Some(&target) => {
+                    stack.push((index, next + 1));
+                    if !visited[target] {
+                        visited[target] = true;
+                        stack.push((target, 0));
+                    }
+                }
+                None => order.push(index)
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    // Replaces every read of a register.
+    pub fn replace(&mut self, from: Reg, to: Value) {
+        for block in &mut self.blocks {
+            let values = block.statements.iter_mut()
+                .flat_map(|statement| statement.args.iter_mut())
+                .chain(block.terminator.values_mut());
+            for value in values {
+                if *value == Value::Reg(from) {
+                    *value = to;
+                }
+            }
+        }
+    }
+
+    // Drops the blocks the entry cannot reach and renumbers the others.
+    pub fn remove_unreachable(&mut self) {
+        if self.blocks.is_empty() {
+            return;
+        }
+        let mut order = self.reverse_postorder();
+        order.sort();
+        let mut position = vec![None; self.blocks.len()];
+        for (i, index) in order.iter().enumerate() {
+            position[*index] = Some(i);
+        }
+        let mut blocks = Vec::new();
+        for index in order {
+            let mut block = self.blocks[index].clone();
+            match block.terminator {
+                Terminator::Jump{ref mut target, ..} => *target = position[*target].unwrap(),
+                Terminator::Branch{ref mut then, ref mut otherwise, ..} => {
+                    *then = position[*then].unwrap();
+                    *otherwise = position[*otherwise].unwrap();
+                }
+                Terminator::Return(_) => {}
+            }
+            blocks.push(block);
+        }
+        self.blocks = blocks;
+    }
+
+    // Removes the parameters that receive the same value from every jump,
+    // not counting the jumps that pass the parameter back to itself.
+    pub fn simplify_params(&mut self) {
+        'restart: loop {
+            for target in 1..self.blocks.len() {
+                for (i, param) in self.blocks[target].params.clone().into_iter().enumerate() {
+                    let mut incoming = Vec::new();
+                    for block in &self.blocks {
+                        if let Terminator::Jump{target: t, ref args} = block.terminator {
+                            if t == target && args[i] != Value::Reg(param) && !incoming.contains(&args[i]) {
+                                incoming.push(args[i]);
+                            }
+                        }
+                    }
+                    if incoming.len() > 1 {
+                        continue;
+                    }
+                    let value = incoming.pop().unwrap_or(Value::Immediate(0));
+                    self.blocks[target].params.remove(i);
+                    for block in &mut self.blocks {
+                        if let Terminator::Jump{target: t, ref mut args} = block.terminator {
+                            if t == target {
+                                args.remove(i);
+                            }
+                        }
+                    }
+                    self.replace(param, value);
+                    continue 'restart;
+                }
+            }
+            break;
+        }
+    }
+
+    // Points branches past the blocks that only jump on without arguments.
+    pub fn thread_jumps(&mut self) {
+        let forward = |blocks: &[Block], index: usize| match blocks[index] {
+            Block{ref params, ref statements, terminator: Terminator::Jump{target, ref args}}
+                if params.is_empty() && statements.is_empty() && args.is_empty()
+                    && blocks[target].params.is_empty() => target,
+            _ => index
+        };
+        for i in 0..self.blocks.len() {
+            if let Terminator::Branch{cond, then, otherwise} = self.blocks[i].terminator {
+                self.blocks[i].terminator = Terminator::Branch{
+                    cond,
+                    then: forward(&self.blocks, then),
+                    otherwise: forward(&self.blocks, otherwise)
+                };
+            }
+        }
+    }
+}
+
+fn op(op: &Operator) -> Op {
+    match op {
+        Operator::Add => Op::Add,
+        Operator::Sub => Op::Sub,
+        Operator::Multiply => Op::Multiply,
+        Operator::Division => Op::Division,
+        Operator::Modulo => Op::Modulo,
+        Operator::Equal => Op::Equal,
+        Operator::LessThan => Op::LessThan,
+        Operator::Greater => Op::Greater,
+        Operator::Call{name} => Op::Call{name: name.to_string()},
+        Operator::CallIndirect{retnum} => Op::CallIndirect{retnum: *retnum},
+        Operator::Asm{instructions, outputs} => {
+            Op::Asm{instructions: instructions.clone(), outputs: outputs.clone()}
+        }
+        Operator::Print{pieces} => Op::Print{pieces: pieces.clone()},
+        Operator::Substitute | Operator::If | Operator::Jump | Operator::Return => {
+            unreachable!("{:?} is not a statement in SSA", op)
+        }
+    }
+}
+
+// The registers of generate read before they are written in each block, so
+// that a block takes a parameter for each register live into it.
+fn live_in(func: &generate::Function, program: &generate::Program) -> Vec<HashSet<usize>> {
+    let count = func.basicblocks.len();
+    let mut uses = vec![HashSet::new(); count];
+    let mut defs = vec![HashSet::new(); count];
+    for (i, block) in func.basicblocks.iter().enumerate() {
+        for statement in &block.statements {
+            for arg in &statement.args {
+                if let generate::Value::Register(reg) = arg {
+                    if !defs[i].contains(reg) {
+                        uses[i].insert(*reg);
+                    }
+                }
+            }
+            if matches!(statement.op, Operator::If | Operator::Jump | Operator::Return) {
+                break;
+            }
+            defs[i].extend(statement.defines(program));
+        }
+    }
+    let mut live = uses.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..count).rev() {
+            for next in func.successors(i) {
+                for reg in live[next].clone() {
+                    if !defs[i].contains(&reg) && live[i].insert(reg) {
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    live
+}
+
+fn build_function(func: &generate::Function, program: &generate::Program) -> Function {
+    let mut res = Function {
+        name: func.name.to_string(),
+        args: func.args.clone(),
+        types: func.types.clone(),
+        retnum: func.retnum,
+        rettype: func.rettype.clone(),
+        constant: func.constant,
+        inline: func.inline,
+        blocks: Vec::new(),
+        regs: RegAlloc::new()
+    };
+    if func.basicblocks.is_empty() {
+        return res;
+    }
+    let live = live_in(func, program);
+    let mut reachable = vec![false; func.basicblocks.len()];
+    let mut stack = vec![0];
+    reachable[0] = true;
+    while let Some(i) = stack.pop() {
+        for next in func.successors(i) {
+            if !reachable[next] {
+                reachable[next] = true;
+                stack.push(next);
+            }
+        }
+    }
+    let mut position = vec![0; func.basicblocks.len()];
+    let mut olds = Vec::new();
+    for i in 0..func.basicblocks.len() {
+        if reachable[i] {
+            position[i] = olds.len();
+            olds.push(i);
+        }
+    }
+    // The registers of generate each block takes, in the order of its params.
+    let mut params: Vec<Vec<usize>> = Vec::new();
+    for &i in &olds {
+        let mut regs: Vec<usize> = if i == 0 {
+            (0..func.args.len()).collect()
+        } else {
+            live[i].iter().cloned().collect()
+        };
+        regs.sort();
+        res.blocks.push(Block {
+            params: res.regs.alloc_many(regs.len()),
+            statements: Vec::new(),
+            terminator: Terminator::Return(Vec::new())
+        });
+        params.push(regs);
+    }
+    let mut edges = Vec::new();
+    for (index, &i) in olds.iter().enumerate() {
+        let mut env = HashMap::new();
+        for (reg, param) in params[index].iter().zip(res.blocks[index].params.iter()) {
+            env.insert(*reg, Value::Reg(*param));
+        }
+        let resolve = |env: &HashMap<usize, Value>, value: &generate::Value| match *value {
+            generate::Value::Register(reg) => env[&reg],
+            generate::Value::Immediate(imm) => Value::Immediate(imm),
+            generate::Value::Label(label) => Value::Label(label)
+        };
+        // A register not live into a block is not used there, so any value
+        // will do; only the entry block takes parameters that may be dead.
+        let jump = |env: &HashMap<usize, Value>, target: usize| Terminator::Jump {
+            target: position[target],
+            args: params[position[target]].iter()
+                .map(|reg| env.get(reg).cloned().unwrap_or(Value::Immediate(0)))
+                .collect()
+        };
+        let block = &func.basicblocks[i];
+        let mut statements = Vec::new();
+        let mut terminator = None;
+        for statement in &block.statements {
+            let args: Vec<Value> = statement.args.iter().map(|arg| resolve(&env, arg)).collect();
+            match statement.op {
+                Operator::Substitute => {
+                    env.insert(statement.ret.unwrap(), args[0]);
+                }
+                Operator::If => {
+                    let then = position[i + 1];
+                    let otherwise = position[block.nexts[1]];
+                    let mut edge = |target: usize, old: usize| {
+                        if params[target].is_empty() {
+                            return target;
+                        }
+                        edges.push(Block {
+                            params: Vec::new(),
+                            statements: Vec::new(),
+                            terminator: jump(&env, old)
+                        });
+                        olds.len() + edges.len() - 1
+                    };
+                    let then = edge(then, i + 1);
+                    let otherwise = edge(otherwise, block.nexts[1]);
+                    terminator = Some(Terminator::Branch{cond: args[0], then, otherwise});
+                    break;
+                }
+                Operator::Jump => {
+                    terminator = Some(jump(&env, block.nexts[0]));
+                    break;
+                }
+                Operator::Return => {
+                    terminator = Some(Terminator::Return(args));
+                    break;
+                }
+                _ => {
+                    let defs = statement.defines(program);
+                    let rets = res.regs.alloc_many(defs.len());
+                    for (reg, ret) in defs.iter().zip(rets.iter()) {
+                        env.insert(*reg, Value::Reg(*ret));
+                    }
+                    statements.push(Statement{op: op(&statement.op), rets, args});
+                }
+            }
+        }
+        let terminator = match terminator {
+            Some(terminator) => terminator,
+            None if i + 1 < func.basicblocks.len() => jump(&env, i + 1),
+            None => Terminator::Return(Vec::new())
+        };
+        res.blocks[index].statements = statements;
+        res.blocks[index].terminator = terminator;
+    }
+    res.blocks.extend(edges);
+    res.simplify_params();
+    res.thread_jumps();
+    res.remove_unreachable();
+    res
+}
+
+// Converts a program that verify accepts.
+pub fn build(program: &generate::Program) -> Program {
+    Program {
+        funcs: program.funcs.iter()
+            .map(|(name, func)| (name.to_string(), build_function(func, program)))
+            .collect(),
+        structs: program.structs.clone(),
+        enums: program.enums.clone(),
+        labels: program.labels.clone()
+    }
+}
+
+pub fn operator(op: &Op) -> Operator {
+    match op {
+        Op::Add => Operator::Add,
+        Op::Sub => Operator::Sub,
+        Op::Multiply => Operator::Multiply,
+        Op::Division => Operator::Division,
+        Op::Modulo => Operator::Modulo,
+        Op::Equal => Operator::Equal,
+        Op::LessThan => Operator::LessThan,
+        Op::Greater => Operator::Greater,
+        Op::Call{name} => Operator::Call{name: name.to_string()},
+        Op::CallIndirect{retnum} => Operator::CallIndirect{retnum: *retnum},
+        Op::Asm{instructions, outputs} => {
+            Operator::Asm{instructions: instructions.clone(), outputs: outputs.clone()}
+        }
+        Op::Print{pieces} => Operator::Print{pieces: pieces.clone()}
+    }
+}
+
+fn statement(op: Operator, ret: Option<usize>, args: Vec<generate::Value>) -> generate::Statement {
+    generate::Statement{op, ret, args}
+}
+
+// trans keeps registers on the stack and expects every block to be entered
+// with the registers of its first predecessor in the same order. Blocks are
+// laid out in reverse postorder, a jump copies its arguments into the
+// parameters just before it leaves, and a branch falls through to its then
+// block, through an extra jump when that block is not next.
+fn lower_function(func: &Function) -> generate::Function {
+    let mut res = generate::Function {
+        name: func.name.to_string(),
+        args: func.args.clone(),
+        types: func.types.clone(),
+        retnum: func.retnum,
+        rettype: func.rettype.clone(),
+        constant: func.constant,
+        inline: func.inline,
+        basicblocks: Vec::new()
+    };
+    if func.blocks.is_empty() {
+        return res;
+    }
+    let order = func.reverse_postorder();
+    let mut regs = HashMap::new();
+    let mut next = 0;
+    for &index in &order {
+        let block = &func.blocks[index];
+        for reg in block.params.iter().chain(block.statements.iter().flat_map(|s| s.rets.iter())) {
+            regs.insert(*reg, next);
+            next += 1;
+        }
+    }
+    let value = |value: &Value| match *value {
+        Value::Reg(reg) => generate::Value::Register(regs[&reg]),
+        Value::Immediate(imm) => generate::Value::Immediate(imm),
+        Value::Label(label) => generate::Value::Label(label)
+    };
+    let mut preds = vec![0; func.blocks.len()];
+    preds[0] = 1;
+    for &index in &order {
+        for target in func.blocks[index].terminator.targets() {
+            preds[target] += 1;
+        }
+    }
+    // A branch enters both of its targets with the same stack, so each
+    // target must have no other predecessor. Otherwise the branch goes through
+    // blocks placed right after it that jump on, where trans drops what the
+    // target lacks; they come before the target, as trans only carries stack
+    // layouts forward.
+    let trampolines = |k: usize, index: usize| match func.blocks[index].terminator {
+        Terminator::Branch{otherwise, ..} if preds[otherwise] > 1 => 2,
+        Terminator::Branch{then, ..} if order.get(k + 1) != Some(&then) || preds[then] > 1 => 1,
+        _ => 0
+    };
+    let mut position = vec![0; func.blocks.len()];
+    let mut count = 0;
+    for (k, &index) in order.iter().enumerate() {
+        position[index] = count;
+        count += 1 + trampolines(k, index);
+    }
+    // main does not return; trans ends it with a halt after its last block.
+    let exit = count;
+    for (k, &index) in order.iter().enumerate() {
+        let block = &func.blocks[index];
+        let mut statements = Vec::new();
+        for s in &block.statements {
+            let ret = match s.op {
+                Op::Call{..} | Op::CallIndirect{..} => Some(s.rets.first().map_or(next, |ret| regs[ret])),
+                _ => s.rets.first().map(|ret| regs[ret])
+            };
+            statements.push(statement(operator(&s.op), ret, s.args.iter().map(&value).collect()));
+        }
+        let mut nexts = Vec::new();
+        let mut jumps = Vec::new();
+        match block.terminator {
+            Terminator::Jump{target, ref args} => {
+                let params: Vec<usize> = func.blocks[target].params.iter().map(|reg| regs[reg]).collect();
+                let copies: Vec<(usize, generate::Value)> = params.into_iter()
+                    .zip(args.iter().map(&value))
+                    .filter(|(param, arg)| *arg != generate::Value::Register(*param))
+                    .collect();
+                let overlaps = copies.iter().any(|(param, _)| {
+                    copies.iter().any(|(_, arg)| *arg == generate::Value::Register(*param))
+                });
+                if overlaps {
+                    let temps: Vec<usize> = (next..next + copies.len()).collect();
+                    next += copies.len();
+                    for ((_, arg), temp) in copies.iter().zip(temps.iter()) {
+                        statements.push(statement(Operator::Substitute, Some(*temp), vec![*arg]));
+                    }
+                    for ((param, _), temp) in copies.iter().zip(temps.iter()) {
+                        statements.push(statement(Operator::Substitute, Some(*param),
+                                                  vec![generate::Value::Register(*temp)]));
+                    }
+                } else {
+                    for (param, arg) in copies {
+                        statements.push(statement(Operator::Substitute, Some(param), vec![arg]));
+                    }
+                }
+                if order.get(k + 1) != Some(&target) {
+                    statements.push(statement(Operator::Jump, None, Vec::new()));
+                }
+                nexts.push(position[target]);
+            }
+            Terminator::Branch{cond, then, otherwise} => {
+                statements.push(statement(Operator::If, None, vec![value(&cond)]));
+                let targets = match trampolines(k, index) {
+                    2 => vec![then, otherwise],
+                    1 => vec![then],
+                    _ => Vec::new()
+                };
+                nexts = vec![position[index] + 1, position[otherwise]];
+                if targets.len() == 2 {
+                    nexts[1] = position[index] + 2;
+                }
+                for target in targets {
+                    jumps.push(generate::BasicBlock {
+                        statements: vec![statement(Operator::Jump, None, Vec::new())],
+                        nexts: vec![position[target]]
+                    });
+                }
+            }
+            Terminator::Return(_) if func.name == "main" => {
+                if position[index] + 1 != exit {
+                    statements.push(statement(Operator::Jump, None, Vec::new()));
+                    nexts.push(exit);
+                }
+            }
+            Terminator::Return(ref args) => {
+                statements.push(statement(Operator::Return, None, args.iter().map(&value).collect()));
+            }
+        }
+        res.basicblocks.push(generate::BasicBlock{statements, nexts});
+        res.basicblocks.extend(jumps);
+    }
+    if res.basicblocks.iter().any(|block| block.nexts.contains(&exit)) {
+        res.basicblocks.push(generate::BasicBlock{statements: Vec::new(), nexts: Vec::new()});
+    }
+    res
+}
+
+pub fn lower(program: &Program) -> generate::Program {
+    generate::Program {
+        funcs: program.funcs.iter()
+            .map(|(name, func)| (name.to_string(), lower_function(func)))
+            .collect(),
+        structs: program.structs.clone(),
+        enums: program.enums.clone(),
+        labels: program.labels.clone()
+    }
+}
+
+fn print_value(value: &Value, program: &Program) -> String {
+    match *value {
+        Value::Reg(Reg(reg)) => format!("%{}", reg),
+        Value::Immediate(imm) => imm.to_string(),
+        Value::Label(index) => match program.labels.get(index) {
+            Some(name) => format!("&{}", name),
+            None => format!("&{}", index)
+        }
+    }
+}
+
+fn print_values(values: &[Value], program: &Program) -> String {
+    values.iter().map(|value| print_value(value, program)).collect::<Vec<_>>().join(", ")
+}
+
+fn print_regs(regs: &[Reg]) -> String {
+    regs.iter().map(|Reg(reg)| format!("%{}", reg)).collect::<Vec<_>>().join(", ")
+}
+
+// A listing for reading, in the style of the textual IR; there is no parser
+// for it.
+pub fn print(program: &Program) -> String {
+    let mut funcs: Vec<_> = program.funcs.values().filter(|func| !func.blocks.is_empty()).collect();
+    funcs.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut sections = Vec::new();
+    for func in funcs {
+        let mut out = format!("fn[{}] {}({}) {{\n", func.retnum, func.name, func.args.join(", "));
+        for (i, block) in func.blocks.iter().enumerate() {
+            if block.params.is_empty() {
+                out.push_str(&format!("  b{}:\n", i));
+            } else {
+                out.push_str(&format!("  b{}({}):\n", i, print_regs(&block.params)));
+            }
+            for s in &block.statements {
+                let mut text = String::new();
+                if !s.rets.is_empty() {
+                    text.push_str(&format!("{} = ", print_regs(&s.rets)));
+                }
+                text.push_str(&match s.op {
+                    Op::Call{ref name} => format!("call {}", name),
+                    Op::CallIndirect{retnum} => format!("call_indirect[{}]", retnum),
+                    Op::Asm{ref instructions, ..} => {
+                        let instructions: Vec<_> = instructions.iter().map(|inst| format::string(inst)).collect();
+                        format!("asm({})", instructions.join(", "))
+                    }
+                    Op::Print{ref pieces} => format!("print {}", format::format_string(pieces)),
+                    ref op => ir::operator_name(&operator(op)).to_string()
+                });
+                if !s.args.is_empty() {
+                    text.push_str(&format!(" {}", print_values(&s.args, program)));
+                }
+                out.push_str(&format!("    {};\n", text));
+            }
+            out.push_str(&format!("    {};\n", match block.terminator {
+                Terminator::Jump{target, ref args} if args.is_empty() => format!("jump b{}", target),
+                Terminator::Jump{target, ref args} => {
+                    format!("jump b{}({})", target, print_values(args, program))
+                }
+                Terminator::Branch{ref cond, then, otherwise} => {
+                    format!("branch {}, b{}, b{}", print_value(cond, program), then, otherwise)
+                }
+                Terminator::Return(ref args) if args.is_empty() => "return".to_string(),
+                Terminator::Return(ref args) => format!("return {}", print_values(args, program))
+            }));
+        }
+        out.push_str("}\n");
+        sections.push(out);
+    }
+    sections.join("\n")
+}
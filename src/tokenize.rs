@@ -5,7 +5,14 @@ pub enum Keyword {
     VAL,
     IF,
     ELSIF,
-    ELSE
+    ELSE,
+    STRUCT,
+    IMPORT,
+    PUB,
+    ASM,
+    MATCH,
+    CONST,
+    ENUM
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -26,7 +33,19 @@ pub enum Punctuator {
     DoubleEqual,
     SemiColon,
     LessThan,
-    Greater
+    Greater,
+    Dot,
+    Colon,
+    Ampersand,
+    FatArrow,
+    Pipe,
+    Hash,
+    DotDotEqual,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -34,20 +53,33 @@ pub enum TokenType {
     Keyword(Keyword),
     Identifier(String),
     Constant(i32),
+    Char(char),
+    String(String),
     Punctuator(Punctuator)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Pos {
-    line: usize,
+    pub line: usize,
     block_pos: usize,
     char_pos: usize
 }
 
+// A `//` comment. The parser ignores comments, but keeps them where fmt can
+// print them back: between statements and between declarations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub line: usize,
+    pub trailing: bool
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub token: TokenType,
-    pub pos: Pos
+    pub pos: Pos,
+    // Comments before the token; on the last token, also the ones after it.
+    pub comments: Vec<Comment>
 }
 
 fn is_identifier_nondigit(c: char) -> bool {
@@ -66,6 +98,13 @@ fn make_keyword(keyword: &str) -> Option<Keyword> {
         "if" => Some(Keyword::IF),
         "elsif" => Some(Keyword::ELSIF),
         "else" => Some(Keyword::ELSE),
+        "struct" => Some(Keyword::STRUCT),
+        "import" => Some(Keyword::IMPORT),
+        "pub" => Some(Keyword::PUB),
+        "asm" => Some(Keyword::ASM),
+        "match" => Some(Keyword::MATCH),
+        "const" => Some(Keyword::CONST),
+        "enum" => Some(Keyword::ENUM),
         _ => None
     }
 }
@@ -88,6 +127,22 @@ fn make_punctuator(punctuator: char) -> Option<Punctuator> {
         ';' => Some(Punctuator::SemiColon),
         '<' => Some(Punctuator::LessThan),
         '>' => Some(Punctuator::Greater),
+        '.' => Some(Punctuator::Dot),
+        ':' => Some(Punctuator::Colon),
+        '&' => Some(Punctuator::Ampersand),
+        '|' => Some(Punctuator::Pipe),
+        '#' => Some(Punctuator::Hash),
+        _ => None
+    }
+}
+
+fn compound(punc: &Punctuator) -> Option<Punctuator> {
+    match punc {
+        Punctuator::Plus => Some(Punctuator::PlusEqual),
+        Punctuator::Minus => Some(Punctuator::MinusEqual),
+        Punctuator::Star => Some(Punctuator::StarEqual),
+        Punctuator::Slash => Some(Punctuator::SlashEqual),
+        Punctuator::Percent => Some(Punctuator::PercentEqual),
         _ => None
     }
 }
@@ -97,24 +152,22 @@ fn tokenize_impl(code: &str, pos: Pos) -> Option<(Token, usize)> {
     match chars.next() {
         Some(c) => {
             if is_identifier_nondigit(c) {
-                let mut identifier: String = c.to_string();
-                let mut len: usize = 1;
+                let mut len: usize = c.len_utf8();
                 loop {
-                    match chars.next() {
-                        Some(d) => {
-                            if is_identifier_chars(d) {
-                                identifier.push(d);
-                            } else {
-                                break;
-                            }
+                    let rest = code.get(len..).unwrap();
+                    match rest.chars().next() {
+                        Some(d) if is_identifier_chars(d) => len += d.len_utf8(),
+                        Some(':') if rest.starts_with("::")
+                                && rest[2..].chars().next().is_some_and(is_identifier_nondigit) => {
+                            len += 2;
                         }
-                        None => break
+                        _ => break
                     }
-                    len += 1;
                 }
+                let identifier = code[..len].to_string();
                 match make_keyword(&identifier) {
-                    Some(keyword) => Some((Token{token:TokenType::Keyword(keyword), pos}, len)),
-                    None => Some((Token{token:TokenType::Identifier(identifier), pos}, len))
+                    Some(keyword) => Some((Token{token:TokenType::Keyword(keyword), pos, comments: Vec::new()}, len)),
+                    None => Some((Token{token:TokenType::Identifier(identifier), pos, comments: Vec::new()}, len))
                 }
             } else if c.is_digit(10) {
                 let mut imm: i32 = c.to_digit(10).unwrap() as i32;
@@ -133,21 +186,46 @@ fn tokenize_impl(code: &str, pos: Pos) -> Option<(Token, usize)> {
                     }
                     len += 1;
                 }
-                Some((Token{token:TokenType::Constant(imm), pos}, len))
+                Some((Token{token:TokenType::Constant(imm), pos, comments: Vec::new()}, len))
+            } else if c == '"' {
+                let mut string = String::new();
+                let mut len: usize = 1;
+                loop {
+                    match chars.next() {
+                        Some('"') => {
+                            return Some((Token{token:TokenType::String(string), pos, comments: Vec::new()}, len+1));
+                        }
+                        Some('\\') => {
+                            match chars.next() {
+                                Some('n') => string.push('\n'),
+                                Some('t') => string.push('\t'),
+                                Some('\\') => string.push('\\'),
+                                Some('"') => string.push('"'),
+                                _ => return None
+                            }
+                            len += 2;
+                        }
+                        Some(d) => {
+                            string.push(d);
+                            len += d.len_utf8();
+                        }
+                        None => return None
+                    }
+                }
             } else if c == '\'' {
                 match chars.next() {
                     Some('\\') => {
                         match chars.next() {
                             Some('\\') => {
                                 if chars.next() == Some('\'') {
-                                    Some((Token{token:TokenType::Constant('\\' as i32), pos}, 4))
+                                    Some((Token{token:TokenType::Char('\\'), pos, comments: Vec::new()}, 4))
                                 } else {
                                     None
                                 }
                             }
                             Some('\'') => {
                                 if chars.next() == Some('\'') {
-                                    Some((Token{token:TokenType::Constant('\'' as i32), pos}, 4))
+                                    Some((Token{token:TokenType::Char('\''), pos, comments: Vec::new()}, 4))
                                 } else {
                                     None
                                 }
@@ -157,7 +235,7 @@ fn tokenize_impl(code: &str, pos: Pos) -> Option<(Token, usize)> {
                     }
                     Some(d) => {
                         if chars.next() == Some('\'') {
-                            Some((Token{token:TokenType::Constant(d as i32), pos}, 3))
+                            Some((Token{token:TokenType::Char(d), pos, comments: Vec::new()}, 3))
                         } else {
                             None
                         }
@@ -169,12 +247,24 @@ fn tokenize_impl(code: &str, pos: Pos) -> Option<(Token, usize)> {
                 match punc {
                     Some(Punctuator::Equal) => {
                         if chars.peek() == Some(&'=') {
-                            Some((Token{token:TokenType::Punctuator(Punctuator::DoubleEqual), pos}, 2))
+                            Some((Token{token:TokenType::Punctuator(Punctuator::DoubleEqual), pos, comments: Vec::new()}, 2))
+                        } else if chars.peek() == Some(&'>') {
+                            Some((Token{token:TokenType::Punctuator(Punctuator::FatArrow), pos, comments: Vec::new()}, 2))
                         } else {
-                            Some((Token{token:TokenType::Punctuator(Punctuator::Equal), pos}, 1))
+                            Some((Token{token:TokenType::Punctuator(Punctuator::Equal), pos, comments: Vec::new()}, 1))
                         }
                     }
-                    Some(punc) => Some((Token{token:TokenType::Punctuator(punc), pos}, 1)),
+                    Some(Punctuator::Dot) => {
+                        if code[1..].starts_with(".=") {
+                            Some((Token{token:TokenType::Punctuator(Punctuator::DotDotEqual), pos, comments: Vec::new()}, 3))
+                        } else {
+                            Some((Token{token:TokenType::Punctuator(Punctuator::Dot), pos, comments: Vec::new()}, 1))
+                        }
+                    }
+                    Some(punc) if chars.peek() == Some(&'=') && compound(&punc).is_some() => {
+                        Some((Token{token:TokenType::Punctuator(compound(&punc).unwrap()), pos, comments: Vec::new()}, 2))
+                    }
+                    Some(punc) => Some((Token{token:TokenType::Punctuator(punc), pos, comments: Vec::new()}, 1)),
                     None => None
                 }
             }
@@ -183,36 +273,59 @@ fn tokenize_impl(code: &str, pos: Pos) -> Option<(Token, usize)> {
     }
 }
 
-fn tokenize_loop(block: &str, pos: Pos, tokens: &mut Vec<Token>) -> bool {
+fn tokenize_loop(block: &str, pos: Pos, tokens: &mut Vec<Token>,
+                 comments: &mut Vec<Comment>) -> bool {
     if block.len() == 0 {
         return true;
     }
+    let rest = block.trim_start();
+    if rest.len() != block.len() {
+        return tokenize_loop(rest, Pos {
+            line: pos.line,
+            block_pos: pos.block_pos + 1,
+            char_pos: 0
+        }, tokens, comments);
+    }
+    if let Some(text) = block.strip_prefix("//") {
+        comments.push(Comment {
+            text: text.trim_end().to_string(),
+            line: pos.line,
+            trailing: tokens.last().is_some_and(|token| token.pos.line == pos.line)
+        });
+        return true;
+    }
     match tokenize_impl(block, pos) {
-        Some((token, seek)) => {
+        Some((mut token, seek)) => {
+            token.comments = std::mem::take(comments);
             tokens.push(token);
             tokenize_loop(block.get(seek..).unwrap(), Pos {
                 line: pos.line,
                 block_pos: pos.block_pos,
                 char_pos: pos.char_pos + seek
-            }, tokens)
+            }, tokens, comments)
+        }
+        None => {
+            println!("Failed to tokenize at line {}, block {}: {}",
+                     pos.line, pos.block_pos, block);
+            false
         }
-        None => false
     }
 }
 
 pub fn tokenize(code: &str) -> Option<Vec<Token>> {
     let mut res: Vec<Token> = Vec::new();
+    let mut comments = Vec::new();
     for (i, line) in code.split_terminator('\n').enumerate() {
-        for (j, block) in line.split_whitespace().enumerate() {
-            if !tokenize_loop(block, Pos {
-                        line: i,
-                        block_pos: j,
-                        char_pos: 0
-                    }, &mut res) {
-                println!("Failed to tokenize at line {}, block {}: {}", i, j, block);
-                return None;
-            }
+        if !tokenize_loop(line.trim_start(), Pos {
+                    line: i,
+                    block_pos: 0,
+                    char_pos: 0
+                }, &mut res, &mut comments) {
+            return None;
         }
     }
+    if let Some(last) = res.last_mut() {
+        last.comments.append(&mut comments);
+    }
     Some(res)
 }
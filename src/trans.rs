@@ -1,11 +1,16 @@
 use super::generate::Program;
 use super::generate::Function;
 use super::generate::Value;
-use super::parse::Operator;
+use super::generate::Statement;
+use super::generate::Operator;
+use super::parse::Piece;
+use super::runtime::{HEAP_SIZE, HEAP_START};
 
+use std::io;
+use std::collections::BTreeSet;
 use std::io::{BufWriter, Write};
 
-fn pullup<W: Write>(regs: &mut Vec<usize>, reg: usize, writer: &mut BufWriter<W>) {
+fn pullup<W: Write>(regs: &[usize], reg: usize, writer: &mut BufWriter<W>) -> io::Result<()> {
     let mut index = 0;
     for (i, r) in regs.iter().enumerate() {
         if *r == reg {
@@ -14,199 +19,679 @@ fn pullup<W: Write>(regs: &mut Vec<usize>, reg: usize, writer: &mut BufWriter<W>
         }
     }
     let regcount = regs.len();
-    write!(writer, "PUSH {}\n", regcount - index);
-    write!(writer, "PUSH -1\n");
-    write!(writer, "ROLL\n");
-    write!(writer, "DUP\n");
-    write!(writer, "PUSH {}\n", regcount - index + 1);
-    write!(writer, "PUSH 1\n");
-    write!(writer, "ROLL\n");
+    writeln!(writer, "PUSH {}", regcount - index)?;
+    writeln!(writer, "PUSH -1")?;
+    writeln!(writer, "ROLL")?;
+    writeln!(writer, "DUP")?;
+    writeln!(writer, "PUSH {}", regcount - index + 1)?;
+    writeln!(writer, "PUSH 1")?;
+    writeln!(writer, "ROLL")?;
+    Ok(())
 }
 
-fn substitute<W: Write>(val: &Value, ret: usize, regs: &mut Vec<usize>, writer: &mut BufWriter<W>) {
+fn substitute<W: Write>(val: &Value, ret: usize, regs: &mut Vec<usize>, writer: &mut BufWriter<W>) -> io::Result<()> {
     match val {
         Value::Register(reg) => {
-            pullup(regs, *reg, writer);
+            pullup(regs, *reg, writer)?;
         }
         Value::Immediate(imm) => {
-            write!(writer, "PUSH {}\n", imm);
+            writeln!(writer, "PUSH {}", imm)?;
+        }
+        Value::Label(label) => {
+            writeln!(writer, "PUSH {}", label)?;
         }
-        _ => {}
     }
     regs.push(ret);
+    Ok(())
+}
+
+// Stores the value on top of the stack into the slot of its register when
+// that register already exists, so reassignment does not grow the stack.
+fn overwrite<W: Write>(regs: &mut Vec<usize>, writer: &mut BufWriter<W>) -> io::Result<()> {
+    let top = *regs.last().unwrap();
+    if let Some(i) = regs[..regs.len()-1].iter().position(|reg| *reg == top) {
+        let depth = regs.len() - 1 - i;
+        writeln!(writer, "PUSH {}", depth + 1)?;
+        writeln!(writer, "PUSH -1")?;
+        writeln!(writer, "ROLL")?;
+        writeln!(writer, "POP")?;
+        if depth > 1 {
+            writeln!(writer, "PUSH {}", depth)?;
+            writeln!(writer, "PUSH 1")?;
+            writeln!(writer, "ROLL")?;
+        }
+        regs.pop();
+        regs.remove(i);
+        regs.insert(i, top);
+    }
+    Ok(())
+}
+
+fn bin_op<W: Write>(args: &[Value], ret: usize, regs: &mut Vec<usize>, writer: &mut BufWriter<W>) -> io::Result<()> {
+    substitute(&args[0], 0, regs, writer)?;
+    substitute(&args[1], 0, regs, writer)?;
+    regs.pop();
+    regs.pop();
+    regs.push(ret);
+    Ok(())
+}
+
+// With a heap, the bottom HEAP_SIZE cells of the stack belong to it and every
+// frame carries a hidden register SP right after its arguments. SP holds the
+// absolute stack index of the frame's first register, so the cell at address
+// p is at depth SP + regs.len() - p from the top of the stack.
+const SP: usize = usize::MAX;
+
+pub fn is_builtin(name: &str) -> bool {
+    matches!(name, "getnum" | "getchar" | "putnum" | "putchar" | "halt" | "load" | "store")
+}
+
+fn uses_heap(program: &Program) -> bool {
+    program.funcs.values().any(|func| {
+        func.basicblocks.iter().any(|block| {
+            block.statements.iter().any(|inst| match inst.op {
+                Operator::Call{ref name} => name == "load" || name == "store",
+                _ => false
+            })
+        })
+    })
 }
 
-fn bin_op<W: Write>(args: &[Value], ret: usize, regs: &mut Vec<usize>, writer: &mut BufWriter<W>) {
-    substitute(&args[0], 0, regs, writer);
-    substitute(&args[1], 0, regs, writer);
+// Push the depth of heap cell `addr`, measured while regs has `len` entries.
+fn heap_depth<W: Write>(addr: &Value, len: usize, regs: &mut Vec<usize>, writer: &mut BufWriter<W>) -> io::Result<()> {
+    substitute(&Value::Register(SP), 0, regs, writer)?;
+    substitute(addr, 0, regs, writer)?;
+    writeln!(writer, "SUB")?;
+    writeln!(writer, "PUSH {}", len)?;
+    writeln!(writer, "ADD")?;
     regs.pop();
+    Ok(())
+}
+
+fn load<W: Write>(args: &[Value], ret: usize, regs: &mut Vec<usize>, writer: &mut BufWriter<W>) -> io::Result<()> {
+    let len = regs.len();
+    heap_depth(&args[0], len, regs, writer)?;
+    writeln!(writer, "DUP")?;
+    writeln!(writer, "PUSH 1")?;
+    writeln!(writer, "ADD")?;
+    writeln!(writer, "PUSH -1")?;
+    writeln!(writer, "ROLL")?;
+    writeln!(writer, "DUP")?;
+    writeln!(writer, "PUSH 3")?;
+    writeln!(writer, "PUSH -1")?;
+    writeln!(writer, "ROLL")?;
+    writeln!(writer, "PUSH 1")?;
+    writeln!(writer, "ADD")?;
+    writeln!(writer, "PUSH 1")?;
+    writeln!(writer, "ROLL")?;
     regs.pop();
     regs.push(ret);
+    Ok(())
+}
+
+fn store<W: Write>(args: &[Value], regs: &mut Vec<usize>, writer: &mut BufWriter<W>) -> io::Result<()> {
+    let len = regs.len();
+    substitute(&args[1], 0, regs, writer)?;
+    heap_depth(&args[0], len, regs, writer)?;
+    writeln!(writer, "DUP")?;
+    writeln!(writer, "PUSH 2")?;
+    writeln!(writer, "ADD")?;
+    writeln!(writer, "PUSH -1")?;
+    writeln!(writer, "ROLL")?;
+    writeln!(writer, "POP")?;
+    writeln!(writer, "PUSH 1")?;
+    writeln!(writer, "ROLL")?;
+    regs.pop();
+    regs.pop();
+    Ok(())
+}
+
+// Prints `fill` once for each column the number on top of the stack leaves
+// empty in `width`. The number stays on the stack.
+fn pad<W: Write>(width: usize, fill: char, label: &str, writer: &mut BufWriter<W>) -> io::Result<()> {
+    for m in (1..width as u32).rev() {
+        // The number fits in m columns if -10^(m-1) < n < 10^m.
+        let upper = 10i64.checked_pow(m).filter(|b| *b <= i32::MAX as i64);
+        let lower = 10i64.checked_pow(m - 1).filter(|b| *b <= i32::MAX as i64);
+        if upper.is_none() && lower.is_none() {
+            writeln!(writer, "PUSH {}", fill as i32)?;
+            writeln!(writer, "OUTC")?;
+            continue;
+        }
+        match upper {
+            Some(upper) => {
+                writeln!(writer, "DUP")?;
+                writeln!(writer, "PUSH {}", upper)?;
+                writeln!(writer, "SWAP")?;
+                writeln!(writer, "GREATER")?;
+            }
+            None => {
+                writeln!(writer, "PUSH 1")?;
+            }
+        }
+        if let Some(lower) = lower {
+            writeln!(writer, "SWAP")?;
+            writeln!(writer, "DUP")?;
+            writeln!(writer, "PUSH {}", -lower)?;
+            writeln!(writer, "GREATER")?;
+            writeln!(writer, "PUSH 3")?;
+            writeln!(writer, "PUSH -1")?;
+            writeln!(writer, "ROLL")?;
+            writeln!(writer, "MUL")?;
+        }
+        writeln!(writer, "JEZ {}_{}", label, m)?;
+        writeln!(writer, "PUSH {}", fill as i32)?;
+        writeln!(writer, "OUTC")?;
+        writeln!(writer, "LABEL {}_{}", label, m)?;
+    }
+    Ok(())
+}
+
+fn pad_char<W: Write>(width: usize, writer: &mut BufWriter<W>) -> io::Result<()> {
+    for _ in 1..width {
+        writeln!(writer, "PUSH 32")?;
+        writeln!(writer, "OUTC")?;
+    }
+    Ok(())
+}
+
+fn print<W: Write>(pieces: &[Piece], args: &[Value], label: &str, regs: &mut Vec<usize>,
+                   writer: &mut BufWriter<W>) -> io::Result<()> {
+    let mut args = args.iter();
+    for (i, piece) in pieces.iter().enumerate() {
+        let label = format!("{}_{}", label, i);
+        match piece {
+            Piece::Text(text) => {
+                for c in text.chars() {
+                    writeln!(writer, "PUSH {}", c as i32)?;
+                    writeln!(writer, "OUTC")?;
+                }
+                continue;
+            }
+            Piece::Number{width, fill: '0', ..} => {
+                substitute(args.next().unwrap(), 0, regs, writer)?;
+                writeln!(writer, "DUP")?;
+                writeln!(writer, "PUSH 0")?;
+                writeln!(writer, "SWAP")?;
+                writeln!(writer, "GREATER")?;
+                writeln!(writer, "JEZ {}_sign", label)?;
+                writeln!(writer, "PUSH {}", '-' as i32)?;
+                writeln!(writer, "OUTC")?;
+                writeln!(writer, "LABEL {}_sign", label)?;
+                pad(*width, '0', &label, writer)?;
+                writeln!(writer, "DUP")?;
+                writeln!(writer, "PUSH 0")?;
+                writeln!(writer, "SWAP")?;
+                writeln!(writer, "GREATER")?;
+                writeln!(writer, "PUSH -2")?;
+                writeln!(writer, "MUL")?;
+                writeln!(writer, "PUSH 1")?;
+                writeln!(writer, "ADD")?;
+                writeln!(writer, "MUL")?;
+                writeln!(writer, "OUTN")?;
+            }
+            Piece::Number{width, fill, left: true} => {
+                substitute(args.next().unwrap(), 0, regs, writer)?;
+                writeln!(writer, "DUP")?;
+                writeln!(writer, "OUTN")?;
+                pad(*width, *fill, &label, writer)?;
+                writeln!(writer, "POP")?;
+            }
+            Piece::Number{width, fill, left: false} => {
+                substitute(args.next().unwrap(), 0, regs, writer)?;
+                pad(*width, *fill, &label, writer)?;
+                writeln!(writer, "OUTN")?;
+            }
+            Piece::Char{width, left} => {
+                substitute(args.next().unwrap(), 0, regs, writer)?;
+                if !left {
+                    pad_char(*width, writer)?;
+                }
+                writeln!(writer, "OUTC")?;
+                if *left {
+                    pad_char(*width, writer)?;
+                }
+            }
+        }
+        regs.pop();
+    }
+    Ok(())
+}
+
+// A call whose results are returned right away reuses the caller's pending
+// control id: the arguments replace the caller's registers and the callee
+// returns straight to the caller's caller.
+fn is_tail_call(func: &Function, statements: &[Statement], index: usize, retnum: usize) -> bool {
+    // A call without results need not name a register.
+    let ret = statements[index].ret.unwrap_or(0);
+    match statements.get(index + 1) {
+        Some(Statement{op: Operator::Return, args, ..}) => {
+            func.name != "main" && func.retnum == retnum && args.len() == retnum &&
+                args.iter().enumerate().all(|(i, arg)| *arg == Value::Register(ret + i))
+        }
+        _ => false
+    }
+}
+
+fn tail_call<W: Write>(target: &str, callee: Option<&Value>, args: &[Value], pass_sp: bool,
+                       regs: &mut Vec<usize>, writer: &mut BufWriter<W>) -> io::Result<()> {
+    let len = regs.len();
+    for arg in args {
+        substitute(arg, 0, regs, writer)?;
+    }
+    if pass_sp {
+        substitute(&Value::Register(SP), 0, regs, writer)?;
+    }
+    if let Some(callee) = callee {
+        substitute(callee, 0, regs, writer)?;
+    }
+    let depth = regs.len() - len;
+    for _ in 0..len {
+        writeln!(writer, "PUSH {}", depth + 1)?;
+        writeln!(writer, "PUSH -1")?;
+        writeln!(writer, "ROLL")?;
+        writeln!(writer, "POP")?;
+    }
+    writeln!(writer, "JMP {}", target)?;
+    regs.clear();
+    Ok(())
+}
+
+// A call that returns to the caller, which waits under the next control id.
+fn call<W: Write>(ctx: &mut Context<W>, target: &str, callee: Option<&Value>, args: &[Value],
+                  retnum: usize, ret: usize, pass_sp: bool) -> io::Result<()> {
+    let id = ctx.start + ctx.count;
+    ctx.count += 1;
+    let regs = &mut ctx.regs;
+    let writer = &mut *ctx.writer;
+    let base = regs.len() + 1;
+    writeln!(writer, "PUSH {}", id)?;
+    regs.push(0);
+    for arg in args {
+        substitute(arg, 0, regs, writer)?;
+    }
+    if pass_sp {
+        substitute(&Value::Register(SP), 0, regs, writer)?;
+        writeln!(writer, "PUSH {}", base)?;
+        writeln!(writer, "ADD")?;
+    }
+    if let Some(callee) = callee {
+        substitute(callee, 0, regs, writer)?;
+        regs.pop();
+    }
+    writeln!(writer, "JMP {}", target)?;
+    writeln!(writer, "LABEL control_{}", id)?;
+    writeln!(writer, "POP")?;
+    for _ in args {
+        regs.pop();
+    }
+    if pass_sp {
+        regs.pop();
+    }
+    regs.pop();
+    for i in 0..retnum {
+        regs.push(ret + i);
+    }
+    Ok(())
+}
+
+// Registers live on the stack in the order they were defined. With blocks
+// that join, such as the arms of a match or an if without else, the
+// predecessors of a block hold different registers, so one layout per block
+// is not enough. A block starts with the registers shared by all of its
+// predecessors, so every edge drops whatever else the source block holds,
+// wherever it sits on the stack. A register defined in only some of the
+// predecessors is never read after the join, so dropping it is safe.
+fn common_layout(a: &[usize], b: &[usize]) -> Vec<usize> {
+    a.iter().filter(|x| b.contains(x)).cloned().collect()
+}
+
+fn drops(regs: &[usize], layout: &Option<Vec<usize>>) -> bool {
+    layout.as_ref().is_some_and(|layout| regs.iter().any(|reg| !layout.contains(reg)))
+}
+
+fn reconcile<W: Write>(regs: &mut Vec<usize>, layout: &Option<Vec<usize>>, writer: &mut BufWriter<W>) -> io::Result<()> {
+    if let Some(layout) = layout {
+        for i in (0..regs.len()).rev() {
+            if !layout.contains(&regs[i]) {
+                let depth = regs.len() - 1 - i;
+                if depth > 0 {
+                    writeln!(writer, "PUSH {}", depth + 1)?;
+                    writeln!(writer, "PUSH -1")?;
+                    writeln!(writer, "ROLL")?;
+                }
+                writeln!(writer, "POP")?;
+                regs.remove(i);
+            }
+        }
+    }
+    Ok(())
+}
+
+// The state of translating the blocks of one function: the registers on the
+// stack, the layout each block starts with, the edges the current block
+// leaves by with the registers they carry, and the control ids of its calls,
+// which count up from `start`.
+struct Context<'a, W: Write> {
+    program: &'a Program,
+    func: &'a Function,
+    heap: bool,
+    start: usize,
+    count: usize,
+    regs: Vec<usize>,
+    entries: Vec<Option<Vec<usize>>>,
+    exits: Vec<(usize, Vec<usize>)>,
+    writer: &'a mut BufWriter<W>
+}
+
+fn block<W: Write>(ctx: &mut Context<W>, index: usize) -> io::Result<()> {
+    let (func, program, heap) = (ctx.func, ctx.program, ctx.heap);
+    let block = &func.basicblocks[index];
+    for (i, inst) in block.statements.iter().enumerate() {
+        let regs = &mut ctx.regs;
+        let writer = &mut *ctx.writer;
+        match inst.op {
+            Operator::Call{ref name} if name == "load" => {
+                load(&inst.args, inst.ret.unwrap(), regs, writer)?;
+            }
+            Operator::Call{ref name} if name == "store" => {
+                store(&inst.args, regs, writer)?;
+            }
+            Operator::Call{ref name} => {
+                let retnum = program.funcs[name].retnum;
+                if is_tail_call(func, &block.statements, i, retnum) {
+                    tail_call(&format!("func_{}", name), None, &inst.args,
+                              heap && !is_builtin(name), regs, writer)?;
+                    return Ok(());
+                }
+                call(ctx, &format!("func_{}", name), None, &inst.args, retnum,
+                     inst.ret.unwrap_or(0), heap && !is_builtin(name))?;
+            }
+            Operator::CallIndirect{retnum} => {
+                let target = format!("indirect_{}_{}", inst.args.len() - 1, retnum);
+                if is_tail_call(func, &block.statements, i, retnum) {
+                    tail_call(&target, Some(&inst.args[0]), &inst.args[1..], heap, regs, writer)?;
+                    return Ok(());
+                }
+                call(ctx, &target, Some(&inst.args[0]), &inst.args[1..], retnum,
+                     inst.ret.unwrap_or(0), heap)?;
+            }
+            Operator::Asm{ref instructions, ref outputs} => {
+                for arg in &inst.args {
+                    substitute(arg, 0, regs, writer)?;
+                }
+                for instruction in instructions {
+                    writeln!(writer, "{}", instruction)?;
+                }
+                for _ in &inst.args {
+                    regs.pop();
+                }
+                for i in 0..outputs.len() {
+                    regs.push(inst.ret.unwrap() + i);
+                }
+            }
+            Operator::Print{ref pieces} => {
+                print(pieces, &inst.args, &format!("print_{}_{}_{}", func.name, index, i),
+                      regs, writer)?;
+            }
+            Operator::If => {
+                substitute(&inst.args[0], 0, regs, writer)?;
+                let jump_to = *block.nexts.get(1).unwrap();
+                regs.pop();
+                ctx.exits.push((index + 1, regs.clone()));
+                ctx.exits.push((jump_to, regs.clone()));
+                // Each edge drops the registers its own target does not hold.
+                // When the jump drops any, it goes through a trampoline after
+                // the fall through.
+                let entries = &ctx.entries;
+                if !drops(regs, &entries[jump_to]) {
+                    writeln!(writer, "JEZ block_{}_{}", func.name, jump_to)?;
+                    reconcile(regs, &entries[index + 1], writer)?;
+                    return Ok(());
+                }
+                let mut taken = regs.clone();
+                writeln!(writer, "JEZ edge_{}_{}_{}", func.name, index, jump_to)?;
+                reconcile(regs, &entries[index + 1], writer)?;
+                writeln!(writer, "JMP block_{}_{}", func.name, index + 1)?;
+                writeln!(writer, "LABEL edge_{}_{}_{}", func.name, index, jump_to)?;
+                reconcile(&mut taken, &entries[jump_to], writer)?;
+                writeln!(writer, "JMP block_{}_{}", func.name, jump_to)?;
+                return Ok(());
+            }
+            Operator::Jump => {
+                let jump_to = block.nexts[0];
+                ctx.exits.push((jump_to, regs.clone()));
+                reconcile(regs, &ctx.entries[jump_to], writer)?;
+                writeln!(writer, "JMP block_{}_{}", func.name, jump_to)?;
+                return Ok(());
+            }
+            Operator::Return => {
+                let retnum = func.retnum;
+                for arg in &inst.args {
+                    substitute(arg, 0, regs, writer)?;
+                }
+                for _ in retnum..regs.len() {
+                    writeln!(writer, "PUSH {}", retnum+1)?;
+                    writeln!(writer, "PUSH -1")?;
+                    writeln!(writer, "ROLL")?;
+                    writeln!(writer, "POP")?;
+                }
+                writeln!(writer, "PUSH {}", retnum+1)?;
+                writeln!(writer, "PUSH -1")?;
+                writeln!(writer, "ROLL")?;
+                writeln!(writer, "JMP return")?;
+                return Ok(());
+            }
+            Operator::Substitute => {
+                substitute(&inst.args[0], inst.ret.unwrap(), regs, writer)?;
+            }
+            Operator::Add => {
+                bin_op(&inst.args, inst.ret.unwrap(), regs, writer)?;
+                writeln!(writer, "ADD")?;
+            }
+            Operator::Sub => {
+                bin_op(&inst.args, inst.ret.unwrap(), regs, writer)?;
+                writeln!(writer, "SUB")?;
+            }
+            Operator::Multiply => {
+                bin_op(&inst.args, inst.ret.unwrap(), regs, writer)?;
+                writeln!(writer, "MUL")?;
+            }
+            Operator::Division => {
+                bin_op(&inst.args, inst.ret.unwrap(), regs, writer)?;
+                writeln!(writer, "DIV")?;
+            }
+            Operator::Modulo => {
+                bin_op(&inst.args, inst.ret.unwrap(), regs, writer)?;
+                writeln!(writer, "MOD")?;
+            }
+            Operator::LessThan => {
+                substitute(&inst.args[1], 0, regs, writer)?;
+                substitute(&inst.args[0], 0, regs, writer)?;
+                regs.pop();
+                regs.pop();
+                regs.push(inst.ret.unwrap());
+                writeln!(writer, "GREATER")?;
+            }
+            Operator::Greater => {
+                bin_op(&inst.args, inst.ret.unwrap(), regs, writer)?;
+                writeln!(writer, "GREATER")?;
+            }
+            Operator::Equal => {
+                bin_op(&inst.args, inst.ret.unwrap(), regs, writer)?;
+                writeln!(writer, "SUB")?;
+                writeln!(writer, "NOT")?;
+            }
+        }
+        if matches!(inst.op, Operator::Substitute | Operator::Add | Operator::Sub |
+                    Operator::Multiply | Operator::Division | Operator::Modulo |
+                    Operator::LessThan | Operator::Greater | Operator::Equal) {
+            overwrite(&mut ctx.regs, ctx.writer)?;
+        }
+    }
+    if index + 1 < func.basicblocks.len() {
+        ctx.exits.push((index + 1, ctx.regs.clone()));
+        reconcile(&mut ctx.regs, &ctx.entries[index + 1], ctx.writer)?;
+    }
+    Ok(())
 }
 
-fn function<W: Write>(func: &Function, start: usize, program: &Program, writer: &mut BufWriter<W>) -> Option<usize> {
-    write!(writer, "LABEL func_{}\n", func.name);
+fn function<W: Write>(func: &Function, start: usize, program: &Program, heap: bool, writer: &mut BufWriter<W>) -> io::Result<usize> {
+    writeln!(writer, "LABEL func_{}", func.name)?;
     let mut count = 0;
     match &*func.name {
         "getnum" => {
-            write!(writer, "INN\n");
-            write!(writer, "SWAP\n");
-            write!(writer, "JMP return\n");
+            writeln!(writer, "INN")?;
+            writeln!(writer, "SWAP")?;
+            writeln!(writer, "JMP return")?;
         }
         "getchar" => {
-            write!(writer, "INC\n");
-            write!(writer, "SWAP\n");
-            write!(writer, "JMP return\n");
+            writeln!(writer, "INC")?;
+            writeln!(writer, "SWAP")?;
+            writeln!(writer, "JMP return")?;
         }
         "putnum" => {
-            write!(writer, "OUTN\n");
-            write!(writer, "JMP return\n");
+            writeln!(writer, "OUTN")?;
+            writeln!(writer, "JMP return")?;
         }
         "putchar" => {
-            write!(writer, "OUTC\n");
-            write!(writer, "JMP return\n");
+            writeln!(writer, "OUTC")?;
+            writeln!(writer, "JMP return")?;
         }
         "halt" => {
-            write!(writer, "HALT\n");
+            writeln!(writer, "HALT")?;
         }
+        "load" | "store" => {}
         _ => {
-            eprintln!("{}", func.name);
             let mut regs = Vec::new();
             for i in 0..func.args.len() {
                 regs.push(i);
             }
-            for (i, block) in func.basicblocks.iter().enumerate() {
-                let regs_old = regs.clone();
-                write!(writer, "LABEL block_{}_{}\n", func.name, i);
-                for inst in &block.statements {
-                    eprintln!("{:?}", regs);
-                    match inst.op {
-                        Operator::Call{ref name} => {
-                            write!(writer, "PUSH {}\n", start + count);
-                            regs.push(0);
-                            for arg in &inst.args {
-                                substitute(arg, 0, &mut regs, writer);
-                            }
-                            write!(writer, "JMP func_{}\n", name);
-                            write!(writer, "LABEL control_{}\n", start + count);
-                            write!(writer, "POP\n");
-                            for _ in &inst.args {
-                                regs.pop();
-                            }
-                            regs.pop();
-                            match program.funcs.get(name) {
-                                Some(callee) => {
-                                    for i in 0..callee.retnum {
-                                        regs.push(inst.ret.unwrap())
-                                    }
-                                }
-                                None => return None
-                            }
-                            count += 1;
-                        }
-                        Operator::If => {
-                            substitute(&inst.args[0], 0, &mut regs, writer);
-                            for i in func.args.len()..regs.len()-1 {
-                                write!(writer, "SWAP\n");
-                                write!(writer, "POP\n");
-                            }
-                            let jump_to = block.nexts.get(1).unwrap();
-                            write!(writer, "JEZ block_{}_{}\n", func.name, jump_to);
-                            regs.pop();
-                        }
-                        Operator::Jump => {
-                            let jump_to = block.nexts.get(0).unwrap();
-                            write!(writer, "JMP block_{}_{}\n", func.name, jump_to);
-                        }
-                        Operator::Return => {
-                            let retnum = func.retnum;
-                            for arg in &inst.args {
-                                substitute(arg, 0, &mut regs, writer);
-                            }
-                            for i in retnum..regs.len() {
-                                write!(writer, "PUSH {}\n", retnum+1);
-                                write!(writer, "PUSH 1\n");
-                                write!(writer, "ROLL\n");
-                                write!(writer, "POP\n");
-                            }
-                            write!(writer, "PUSH {}\n", retnum+1);
-                            write!(writer, "PUSH 1\n");
-                            write!(writer, "ROLL\n");
-                            write!(writer, "JMP return\n");
-                        }
-                        Operator::Substitute => {
-                            substitute(&inst.args[0], inst.ret.unwrap(), &mut regs, writer);
-                        }
-                        Operator::Add => {
-                            bin_op(&inst.args, inst.ret.unwrap(), &mut regs, writer);
-                            write!(writer, "ADD\n");
-                        }
-                        Operator::Sub => {
-                            bin_op(&inst.args, inst.ret.unwrap(), &mut regs, writer);
-                            write!(writer, "SUB\n");
-                        }
-                        Operator::Multiply => {
-                            bin_op(&inst.args, inst.ret.unwrap(), &mut regs, writer);
-                            write!(writer, "MUL\n");
-                        }
-                        Operator::Division => {
-                            bin_op(&inst.args, inst.ret.unwrap(), &mut regs, writer);
-                            write!(writer, "DIV\n");
-                        }
-                        Operator::Modulo => {
-                            bin_op(&inst.args, inst.ret.unwrap(), &mut regs, writer);
-                            write!(writer, "MOD\n");
-                        }
-                        Operator::LessThan => {
-                            substitute(&inst.args[1], 0, &mut regs, writer);
-                            substitute(&inst.args[0], 0, &mut regs, writer);
-                            regs.pop();
-                            regs.pop();
-                            regs.push(inst.ret.unwrap());
-                            write!(writer, "GREATER\n");
-                        }
-                        Operator::Greater => {
-                            bin_op(&inst.args, inst.ret.unwrap(), &mut regs, writer);
-                            write!(writer, "GREATER\n");
-                        }
-                        Operator::Equal => {
-                            bin_op(&inst.args, inst.ret.unwrap(), &mut regs, writer);
-                            write!(writer, "SUB\n");
-                            write!(writer, "NOT\n");
-                        }
-                        _ => {
-                            println!("Unsupported operator: {:?}", inst.op);
-                            return None;
-                        }
+            if heap {
+                regs.push(SP);
+            }
+            // A dry run without output finds the layout each block starts
+            // with. Blocks come in order, so one pass sees every edge forward
+            // into a block before the block itself. An edge backward closes a
+            // loop, such as an inlined self tail call, and reconciles to the
+            // layout its header got from the edges into the loop.
+            let mut entries = vec![None; func.basicblocks.len()];
+            entries[0] = Some(regs.clone());
+            let mut sink = BufWriter::new(io::sink());
+            let mut dry = Context {
+                program, func, heap, start, count,
+                regs: Vec::new(),
+                entries,
+                exits: Vec::new(),
+                writer: &mut sink
+            };
+            for i in 0..func.basicblocks.len() {
+                dry.regs = match dry.entries[i] {
+                    Some(ref layout) => layout.clone(),
+                    None => continue
+                };
+                block(&mut dry, i)?;
+                for (target, layout) in std::mem::take(&mut dry.exits) {
+                    if target > i {
+                        dry.entries[target] = Some(match dry.entries[target] {
+                            Some(ref entry) => common_layout(entry, &layout),
+                            None => layout
+                        });
                     }
                 }
-                regs = regs_old;
             }
+            let mut ctx = Context {
+                program, func, heap, start, count,
+                regs: Vec::new(),
+                entries: dry.entries,
+                exits: Vec::new(),
+                writer: &mut *writer
+            };
+            for i in 0..func.basicblocks.len() {
+                ctx.regs = ctx.entries[i].clone().unwrap_or_else(|| regs.clone());
+                writeln!(ctx.writer, "LABEL block_{}_{}", func.name, i)?;
+                block(&mut ctx, i)?;
+            }
+            count = ctx.count;
             if func.name == "main" {
-                write!(writer, "HALT\n");
+                writeln!(writer, "HALT")?;
             }
         }
     }
-    Some(count)
+    Ok(count)
 }
 
-pub fn trans<W: Write>(program: &Program, writer: &mut BufWriter<W>) -> Option<()>{
-    write!(writer, "JMP func_main\n");
-    let mut start = 0;
-    for (name, func) in program.funcs.iter() {
-        match function(func, start, program, writer) {
-            Some(count) => {
-                start += count;
+// The argument and result counts of the functions values can refer to and
+// of the indirect calls.
+fn signatures(program: &Program) -> BTreeSet<(usize, usize)> {
+    let mut res: BTreeSet<(usize, usize)> = program.labels.iter()
+        .map(|name| (program.funcs[name].args.len(), program.funcs[name].retnum))
+        .collect();
+    for func in program.funcs.values() {
+        for block in &func.basicblocks {
+            for inst in &block.statements {
+                if let Operator::CallIndirect{retnum} = inst.op {
+                    res.insert((inst.args.len() - 1, retnum));
+                }
             }
-            None => return None
         }
     }
-    write!(writer, "LABEL return\n");
+    res
+}
+
+pub fn trans<W: Write>(program: &Program, writer: &mut BufWriter<W>) -> io::Result<()> {
+    let heap = uses_heap(program);
+    if heap {
+        writeln!(writer, "PUSH {}", HEAP_START)?;
+        writeln!(writer, "PUSH 0")?;
+        writeln!(writer, "PUSH {}", HEAP_SIZE - HEAP_START)?;
+        writeln!(writer, "LABEL heap_init")?;
+        writeln!(writer, "DUP")?;
+        writeln!(writer, "JEZ heap_ready")?;
+        writeln!(writer, "PUSH 0")?;
+        writeln!(writer, "SWAP")?;
+        writeln!(writer, "PUSH 1")?;
+        writeln!(writer, "SUB")?;
+        writeln!(writer, "JMP heap_init")?;
+        writeln!(writer, "LABEL heap_ready")?;
+        writeln!(writer, "POP")?;
+        writeln!(writer, "PUSH {}", HEAP_SIZE)?;
+    }
+    writeln!(writer, "JMP func_main")?;
+    let mut start = 0;
+    for func in program.funcs.values() {
+        start += function(func, start, program, heap, writer)?;
+    }
+    // Each signature has its own dispatch, so a function value can only reach
+    // the functions taking and returning as many values as the call. Any
+    // other value halts instead of calling the wrong function.
+    for (args, retnum) in signatures(program) {
+        writeln!(writer, "LABEL indirect_{}_{}", args, retnum)?;
+        let labels: Vec<(usize, &String)> = program.labels.iter().enumerate()
+            .filter(|(_, name)| {
+                let callee = &program.funcs[*name];
+                callee.args.len() == args && callee.retnum == retnum
+            })
+            .collect();
+        for (i, _) in &labels {
+            writeln!(writer, "DUP")?;
+            writeln!(writer, "PUSH {}", i)?;
+            writeln!(writer, "SUB")?;
+            writeln!(writer, "JEZ indirect_{}_{}_{}", args, retnum, i)?;
+        }
+        writeln!(writer, "HALT")?;
+        for (i, name) in labels {
+            writeln!(writer, "LABEL indirect_{}_{}_{}", args, retnum, i)?;
+            writeln!(writer, "POP")?;
+            writeln!(writer, "JMP func_{}", name)?;
+        }
+    }
+    writeln!(writer, "LABEL return")?;
     for i in 0..start {
-        write!(writer, "DUP\n");
-        write!(writer, "JEZ control_{}\n", i);
-        write!(writer, "PUSH 1\n");
-        write!(writer, "SUB\n");
+        writeln!(writer, "DUP")?;
+        writeln!(writer, "JEZ control_{}", i)?;
+        writeln!(writer, "PUSH 1")?;
+        writeln!(writer, "SUB")?;
     }
-    Some(())
+    Ok(())
 }
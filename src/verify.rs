@@ -0,0 +1,224 @@
+use super::generate::Value;
+use super::generate::Operator;
+use super::generate::Statement;
+use super::generate::Function;
+use super::generate::Program;
+use super::parse::Piece;
+use super::trans;
+use super::ir;
+
+use std::collections::HashSet;
+
+// Checks the shape of a program that trans relies on: blocks continue to
+// blocks that exist, an if falls through to the block right after it, every
+// block is first reached by an edge forward, every register is defined on all
+// paths before it is read, functions other than main end in a return or jump,
+// and calls, labels and operators have the arguments they need. Each problem
+// is printed; the result is whether there were none.
+//
+// It does not check the stack layouts trans derives from this. A backward
+// edge must still hold every register its target starts with, which the
+// loops the passes make do, as their header dominates the edge.
+
+fn report(program: &Program, func: &Function, block: usize, index: usize, statement: &Statement,
+          message: &str) {
+    println!("In function {}, block {}, statement {} `{}`: {}",
+             func.name, block, index, ir::print_statement(statement, program), message);
+}
+
+fn operands(statement: &Statement, func: &Function, program: &Program) -> Result<(), String> {
+    let args = statement.args.len();
+    let expected = match statement.op {
+        Operator::Add | Operator::Sub | Operator::Multiply | Operator::Division |
+        Operator::Modulo | Operator::Equal | Operator::LessThan | Operator::Greater => Some(2),
+        Operator::Substitute | Operator::If => Some(1),
+        Operator::Jump => Some(0),
+        Operator::Return => Some(func.retnum),
+        Operator::Call{ref name} => match program.funcs.get(name) {
+            Some(callee) => Some(callee.args.len()),
+            None => return Err(format!("Function {} is not defined", name))
+        },
+        Operator::CallIndirect{..} if args == 0 => {
+            return Err("Indirect call has no function to call".to_string())
+        }
+        Operator::Print{ref pieces} => {
+            Some(pieces.iter().filter(|piece| !matches!(piece, Piece::Text(_))).count())
+        }
+        Operator::CallIndirect{..} | Operator::Asm{..} => None
+    };
+    if let Some(expected) = expected {
+        if args != expected {
+            return Err(format!("Takes {} arguments, but has {}", expected, args));
+        }
+    }
+    let needs_ret = match statement.op {
+        Operator::If | Operator::Jump | Operator::Return | Operator::Print{..} => Some(false),
+        Operator::Call{ref name} if program.funcs[name].retnum == 0 => None,
+        Operator::CallIndirect{retnum: 0} => None,
+        Operator::Call{..} | Operator::CallIndirect{..} => Some(true),
+        Operator::Asm{ref outputs, ..} => Some(!outputs.is_empty()),
+        _ => Some(true)
+    };
+    match (needs_ret, statement.ret) {
+        (Some(true), None) => return Err("Has no result register".to_string()),
+        (Some(false), Some(ret)) => return Err(format!("Cannot write a result to %{}", ret)),
+        _ => {}
+    }
+    for arg in &statement.args {
+        if let Value::Label(index) = arg {
+            if *index >= program.labels.len() {
+                return Err(format!("Label {} is out of range, there are {}", index, program.labels.len()));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn function(func: &Function, program: &Program) -> bool {
+    if func.basicblocks.is_empty() {
+        if !trans::is_builtin(&func.name) {
+            println!("Function {} has no blocks", func.name);
+            return false;
+        }
+        return true;
+    }
+    let mut ok = true;
+    for (i, block) in func.basicblocks.iter().enumerate() {
+        for next in &block.nexts {
+            if *next >= func.basicblocks.len() {
+                println!("In function {}, block {}: Next block {} is out of range, there are {}",
+                         func.name, i, next, func.basicblocks.len());
+                ok = false;
+            }
+        }
+        for (j, statement) in block.statements.iter().enumerate() {
+            let error = match statement.op {
+                Operator::If if block.nexts.len() < 2 => {
+                    Some(format!("If needs two next blocks, but the block has {}", block.nexts.len()))
+                }
+                Operator::If if i + 1 == func.basicblocks.len() => {
+                    Some("If is in the last block, so it has no block to fall through to".to_string())
+                }
+                Operator::If if block.nexts[0] != i + 1 => {
+                    Some(format!("If falls through to block {}, but its first next block is {}",
+                                 i + 1, block.nexts[0]))
+                }
+                Operator::Jump if block.nexts.is_empty() => {
+                    Some("Jump needs a next block, but the block has none".to_string())
+                }
+                _ => operands(statement, func, program).err()
+            };
+            if let Some(error) = error {
+                report(program, func, i, j, statement, &error);
+                ok = false;
+            }
+        }
+    }
+    if !ok {
+        return false;
+    }
+    // The registers defined on every path into each block; None until the
+    // block is found to be reachable.
+    let mut entries: Vec<Option<HashSet<usize>>> = vec![None; func.basicblocks.len()];
+    entries[0] = Some((0..func.args.len()).collect());
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..func.basicblocks.len() {
+            let mut defined = match entries[i] {
+                Some(ref entry) => entry.clone(),
+                None => continue
+            };
+            for statement in &func.basicblocks[i].statements {
+                defined.extend(statement.defines(program));
+            }
+            for next in func.successors(i) {
+                let entry = match entries[next] {
+                    Some(ref entry) => entry.intersection(&defined).cloned().collect(),
+                    None => defined.clone()
+                };
+                if entries[next].as_ref() != Some(&entry) {
+                    entries[next] = Some(entry);
+                    changed = true;
+                }
+            }
+        }
+    }
+    // trans finds the layout a block starts with from the edges into it
+    // from earlier blocks, so a block must not be reached by a backward
+    // edge alone.
+    let mut forward = vec![false; func.basicblocks.len()];
+    forward[0] = true;
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.is_some() {
+            for next in func.successors(i) {
+                forward[next] |= next > i;
+            }
+        }
+    }
+    for i in 0..func.basicblocks.len() {
+        if entries[i].is_some() && !forward[i] {
+            println!("In function {}, block {}: The block is only reached by a backward edge",
+                     func.name, i);
+            ok = false;
+        }
+    }
+    // trans only ends main with a halt, so any other function reaching the
+    // end of its last block would run into the code after it.
+    let last = func.basicblocks.len() - 1;
+    let terminated = func.basicblocks[last].statements.iter().any(|statement| {
+        matches!(statement.op, Operator::If | Operator::Jump | Operator::Return)
+    });
+    if entries[last].is_some() && !terminated && func.name != "main" {
+        println!("In function {}, block {}: The last block ends without a return or jump",
+                 func.name, last);
+        ok = false;
+    }
+    for (i, block) in func.basicblocks.iter().enumerate() {
+        let mut defined = match entries[i] {
+            Some(ref entry) => entry.clone(),
+            None => continue
+        };
+        for (j, statement) in block.statements.iter().enumerate() {
+            for arg in &statement.args {
+                if let Value::Register(reg) = arg {
+                    if !defined.contains(reg) {
+                        report(program, func, i, j, statement, &format!("Register %{} is used before it is defined", reg));
+                        ok = false;
+                    }
+                }
+            }
+            defined.extend(statement.defines(program));
+        }
+    }
+    ok
+}
+
+pub fn verify(program: &Program) -> bool {
+    let mut ok = true;
+    if !program.funcs.contains_key("main") {
+        println!("There is no main function");
+        ok = false;
+    }
+    for label in &program.labels {
+        match program.funcs.get(label) {
+            Some(func) if !trans::is_builtin(&func.name) => {}
+            Some(_) => {
+                println!("Label {} is a builtin, which cannot be called indirectly", label);
+                ok = false;
+            }
+            None => {
+                println!("Label {} is not a function", label);
+                ok = false;
+            }
+        }
+    }
+    let mut names: Vec<_> = program.funcs.keys().collect();
+    names.sort();
+    for name in names {
+        if !function(&program.funcs[name], program) {
+            ok = false;
+        }
+    }
+    ok
+}
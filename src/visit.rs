@@ -0,0 +1,309 @@
+use super::parse::Pattern;
+use super::parse::Call;
+use super::parse::Expr;
+use super::parse::Stmt;
+use super::parse::Arm;
+use super::parse::FnDecl;
+use super::parse::StructDecl;
+use super::parse::EnumDecl;
+use super::parse::Decl;
+use super::parse::Program;
+use super::tokenize::Comment;
+
+// Walks the syntax tree by reference. Every method defaults to the walk
+// function of the same node, which visits its children, so a pass overrides
+// the nodes it cares about and calls the walk function to keep descending.
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program)
+    }
+
+    fn visit_decl(&mut self, decl: &Decl) {
+        walk_decl(self, decl)
+    }
+
+    fn visit_fn(&mut self, decl: &FnDecl) {
+        walk_fn(self, decl)
+    }
+
+    fn visit_struct(&mut self, _decl: &StructDecl) {}
+
+    fn visit_enum(&mut self, _decl: &EnumDecl) {}
+
+    fn visit_body(&mut self, body: &[Stmt]) {
+        walk_body(self, body)
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_arm(&mut self, arm: &Arm) {
+        walk_arm(self, arm)
+    }
+
+    fn visit_pattern(&mut self, _pattern: &Pattern) {}
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_call(&mut self, call: &Call) {
+        walk_call(self, call)
+    }
+
+    fn visit_comment(&mut self, _comment: &Comment) {}
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for decl in &program.decls {
+        visitor.visit_decl(decl);
+    }
+}
+
+pub fn walk_decl<V: Visitor + ?Sized>(visitor: &mut V, decl: &Decl) {
+    match decl {
+        Decl::Fn(decl) => visitor.visit_fn(decl),
+        Decl::Struct(decl) => visitor.visit_struct(decl),
+        Decl::Enum(decl) => visitor.visit_enum(decl),
+        Decl::Import{..} => {}
+        Decl::Comment(comment) => visitor.visit_comment(comment)
+    }
+}
+
+pub fn walk_fn<V: Visitor + ?Sized>(visitor: &mut V, decl: &FnDecl) {
+    visitor.visit_body(&decl.body);
+}
+
+pub fn walk_body<V: Visitor + ?Sized>(visitor: &mut V, body: &[Stmt]) {
+    for stmt in body {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Substitute{value, ..} | Stmt::Update{value, ..} => visitor.visit_expr(value),
+        Stmt::Call(call) => visitor.visit_call(call),
+        Stmt::If{cond, then, otherwise} => {
+            visitor.visit_expr(cond);
+            visitor.visit_body(then);
+            if let Some(otherwise) = otherwise {
+                visitor.visit_body(otherwise);
+            }
+        }
+        Stmt::Match{scrutinee, arms} => {
+            visitor.visit_expr(scrutinee);
+            for arm in arms {
+                visitor.visit_arm(arm);
+            }
+        }
+        Stmt::Return(value) => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        Stmt::Asm{inputs, ..} => {
+            for input in inputs {
+                visitor.visit_expr(input);
+            }
+        }
+        Stmt::Assert{cond, ..} => visitor.visit_expr(cond),
+        Stmt::Print{args, ..} => {
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        Stmt::Fn(decl) => visitor.visit_fn(decl),
+        Stmt::Comment(comment) => visitor.visit_comment(comment)
+    }
+}
+
+pub fn walk_arm<V: Visitor + ?Sized>(visitor: &mut V, arm: &Arm) {
+    for pattern in &arm.patterns {
+        visitor.visit_pattern(pattern);
+    }
+    visitor.visit_body(&arm.body);
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Call(call) => visitor.visit_call(call),
+        _ => {
+            for child in expr.children() {
+                visitor.visit_expr(child);
+            }
+        }
+    }
+}
+
+pub fn walk_call<V: Visitor + ?Sized>(visitor: &mut V, call: &Call) {
+    for arg in &call.args {
+        visitor.visit_expr(arg);
+    }
+}
+
+// Rebuilds the syntax tree from owned nodes. Like the visitor, every method
+// defaults to the fold function of the same node, which folds its children.
+// A pass fails by returning None after printing why, and a statement can be
+// replaced by any number of statements, including none.
+pub trait Folder {
+    fn fold_program(&mut self, program: Program) -> Option<Program> {
+        fold_program(self, program)
+    }
+
+    fn fold_decl(&mut self, decl: Decl) -> Option<Decl> {
+        fold_decl(self, decl)
+    }
+
+    fn fold_fn(&mut self, decl: FnDecl) -> Option<FnDecl> {
+        fold_fn(self, decl)
+    }
+
+    fn fold_struct(&mut self, decl: StructDecl) -> Option<StructDecl> {
+        Some(decl)
+    }
+
+    fn fold_enum(&mut self, decl: EnumDecl) -> Option<EnumDecl> {
+        Some(decl)
+    }
+
+    fn fold_body(&mut self, body: Vec<Stmt>) -> Option<Vec<Stmt>> {
+        fold_body(self, body)
+    }
+
+    fn fold_stmt(&mut self, stmt: Stmt) -> Option<Vec<Stmt>> {
+        Some(vec![fold_stmt(self, stmt)?])
+    }
+
+    fn fold_arm(&mut self, arm: Arm) -> Option<Arm> {
+        fold_arm(self, arm)
+    }
+
+    fn fold_pattern(&mut self, pattern: Pattern) -> Option<Pattern> {
+        Some(pattern)
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Option<Expr> {
+        fold_expr(self, expr)
+    }
+
+    fn fold_call(&mut self, call: Call) -> Option<Call> {
+        fold_call(self, call)
+    }
+}
+
+pub fn fold_program<F: Folder + ?Sized>(folder: &mut F, program: Program) -> Option<Program> {
+    let mut decls = Vec::new();
+    for decl in program.decls {
+        decls.push(folder.fold_decl(decl)?);
+    }
+    Some(Program { decls })
+}
+
+pub fn fold_decl<F: Folder + ?Sized>(folder: &mut F, decl: Decl) -> Option<Decl> {
+    Some(match decl {
+        Decl::Fn(decl) => Decl::Fn(folder.fold_fn(decl)?),
+        Decl::Struct(decl) => Decl::Struct(folder.fold_struct(decl)?),
+        Decl::Enum(decl) => Decl::Enum(folder.fold_enum(decl)?),
+        Decl::Import{..} | Decl::Comment(_) => decl
+    })
+}
+
+pub fn fold_fn<F: Folder + ?Sized>(folder: &mut F, decl: FnDecl) -> Option<FnDecl> {
+    let body = folder.fold_body(decl.body)?;
+    Some(FnDecl { body, ..decl })
+}
+
+pub fn fold_body<F: Folder + ?Sized>(folder: &mut F, body: Vec<Stmt>) -> Option<Vec<Stmt>> {
+    let mut res = Vec::new();
+    for stmt in body {
+        res.extend(folder.fold_stmt(stmt)?);
+    }
+    Some(res)
+}
+
+fn fold_exprs<F: Folder + ?Sized>(folder: &mut F, exprs: Vec<Expr>) -> Option<Vec<Expr>> {
+    let mut res = Vec::new();
+    for expr in exprs {
+        res.push(folder.fold_expr(expr)?);
+    }
+    Some(res)
+}
+
+pub fn fold_stmt<F: Folder + ?Sized>(folder: &mut F, stmt: Stmt) -> Option<Stmt> {
+    Some(match stmt {
+        Stmt::Substitute{name, value} => Stmt::Substitute{name, value: folder.fold_expr(value)?},
+        Stmt::Update{name, op, value} => Stmt::Update{name, op, value: folder.fold_expr(value)?},
+        Stmt::Call(call) => Stmt::Call(folder.fold_call(call)?),
+        Stmt::If{cond, then, otherwise} => {
+            Stmt::If{
+                cond: folder.fold_expr(cond)?,
+                then: folder.fold_body(then)?,
+                otherwise: match otherwise {
+                    Some(otherwise) => Some(folder.fold_body(otherwise)?),
+                    None => None
+                }
+            }
+        }
+        Stmt::Match{scrutinee, arms} => {
+            let scrutinee = folder.fold_expr(scrutinee)?;
+            let mut new_arms = Vec::new();
+            for arm in arms {
+                new_arms.push(folder.fold_arm(arm)?);
+            }
+            Stmt::Match{scrutinee, arms: new_arms}
+        }
+        Stmt::Return(Some(value)) => Stmt::Return(Some(folder.fold_expr(value)?)),
+        Stmt::Asm{inputs, instructions, outputs} => {
+            Stmt::Asm{inputs: fold_exprs(folder, inputs)?, instructions, outputs}
+        }
+        Stmt::Assert{cond, file, line, message} => {
+            Stmt::Assert{cond: folder.fold_expr(cond)?, file, line, message}
+        }
+        Stmt::Print{pieces, args} => Stmt::Print{pieces, args: fold_exprs(folder, args)?},
+        Stmt::Fn(decl) => Stmt::Fn(folder.fold_fn(decl)?),
+        Stmt::Return(None) | Stmt::Comment(_) => stmt
+    })
+}
+
+pub fn fold_arm<F: Folder + ?Sized>(folder: &mut F, arm: Arm) -> Option<Arm> {
+    let mut patterns = Vec::new();
+    for pattern in arm.patterns {
+        patterns.push(folder.fold_pattern(pattern)?);
+    }
+    Some(Arm { patterns, body: folder.fold_body(arm.body)? })
+}
+
+pub fn fold_expr<F: Folder + ?Sized>(folder: &mut F, expr: Expr) -> Option<Expr> {
+    Some(match expr {
+        Expr::Binary{op, lhs, rhs} => {
+            Expr::Binary{
+                op,
+                lhs: Box::new(folder.fold_expr(*lhs)?),
+                rhs: Box::new(folder.fold_expr(*rhs)?)
+            }
+        }
+        Expr::Call(call) => Expr::Call(folder.fold_call(call)?),
+        Expr::StructLiteral{name, fields} => {
+            let mut new_fields = Vec::new();
+            for (field, value) in fields {
+                new_fields.push((field, folder.fold_expr(value)?));
+            }
+            Expr::StructLiteral{name, fields: new_fields}
+        }
+        Expr::Field{base, name} => Expr::Field{base: Box::new(folder.fold_expr(*base)?), name},
+        Expr::Conditional{cond, then, otherwise} => {
+            Expr::Conditional{
+                cond: Box::new(folder.fold_expr(*cond)?),
+                then: Box::new(folder.fold_expr(*then)?),
+                otherwise: Box::new(folder.fold_expr(*otherwise)?)
+            }
+        }
+        Expr::Constant(_) | Expr::Char(_) | Expr::Identifier(_) | Expr::Function(_) => expr
+    })
+}
+
+pub fn fold_call<F: Folder + ?Sized>(folder: &mut F, call: Call) -> Option<Call> {
+    Some(Call { name: call.name, args: fold_exprs(folder, call.args)? })
+}
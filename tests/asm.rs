@@ -0,0 +1,66 @@
+mod common;
+
+fn rejected(code: &str) -> String {
+    let output = common::poyot_on(code, "pyt", &[common::temp("asm").to_str().unwrap()]);
+    assert!(!output.status.success());
+    common::stdout(&output)
+}
+
+// A main function reading `a` and `b` and running `body` on them in an asm
+// block with outputs `outputs`.
+fn block(outputs: &str, body: &str) -> String {
+    format!("fn[0] main() {{\n  a = getnum();\n  b = getnum();\n  asm(a, b){} {{\n    {}\n  }}\n  \
+             putnum(7);\n}}\n", outputs, body)
+}
+
+// The last output is the value on top of the stack.
+#[test]
+fn outputs_are_bound_to_variables() {
+    let code = "fn[0] main() {\n  a = getnum();\n  b = getnum();\n  asm(a, b) -> (sum, product) {\n    \
+                DUP PUSH 3 PUSH 2 ROLL DUP PUSH 4 PUSH 1 ROLL ADD PUSH 3 PUSH 1 ROLL MUL\n  }\n  \
+                putnum(sum);\n  putchar(',');\n  putnum(product);\n}\n";
+    assert_eq!(common::compile_and_run(code, "3 4"), "7,12");
+}
+
+#[test]
+fn a_block_without_outputs_runs_for_its_effects() {
+    let code = "fn[0] main() {\n  a = getnum();\n  asm(a) {\n    OUTN\n  }\n  putnum(a + 1);\n}\n";
+    assert_eq!(common::compile_and_run(code, "5"), "56");
+}
+
+#[test]
+fn leaving_more_values_than_the_outputs_is_rejected() {
+    assert!(rejected(&block(" -> (x)", "DUP")).contains("asm: block declares 1 outputs, but leaves 3 values"));
+}
+
+#[test]
+fn leaving_fewer_values_than_the_outputs_is_rejected() {
+    assert!(rejected(&block(" -> (x, y)", "ADD")).contains("asm: block declares 2 outputs, but leaves 1 values"));
+}
+
+#[test]
+fn popping_below_the_inputs_is_rejected() {
+    assert!(rejected(&block("", "ADD ADD")).contains("asm: ADD needs 2 values, but the stack has 1"));
+}
+
+#[test]
+fn a_roll_deeper_than_the_block_is_rejected() {
+    assert!(rejected(&block("", "PUSH 3 PUSH 1 ROLL POP POP"))
+        .contains("asm: ROLL depth 3 exceeds the 2 values of the block"));
+}
+
+#[test]
+fn a_roll_depth_computed_at_run_time_is_rejected() {
+    assert!(rejected(&block("", "PUSH 1 ROLL"))
+        .contains("asm: ROLL depth must be pushed as a constant"));
+}
+
+#[test]
+fn control_flow_is_rejected() {
+    assert!(rejected(&block("", "POP POP HALT")).contains("asm: control flow instruction HALT is not allowed"));
+}
+
+#[test]
+fn an_unknown_instruction_is_rejected() {
+    assert!(rejected(&block("", "POP POP FROB")).contains("asm: unknown instruction FROB"));
+}
@@ -0,0 +1,32 @@
+mod common;
+
+fn program(message: &str) -> String {
+    format!("fn[0] main() {{\n  n = getnum();\n  assert(n < 10, \"{}\");\n  putnum(n);\n}}\n", message)
+}
+
+#[test]
+fn a_failing_assert_reports_the_line_and_halts() {
+    let run = common::run(&common::compile(&program("too big"), "pyt", &[]), "12");
+    assert!(run.output.ends_with(".pyt:3: assertion failed: too big\n"), "{}", run.output);
+    assert!(run.halted);
+}
+
+#[test]
+fn a_passing_assert_prints_nothing() {
+    assert_eq!(common::compile_and_run(&program("too big"), "7"), "7");
+}
+
+#[test]
+fn the_message_does_not_add_calls() {
+    let calls = |asm: String| asm.lines().filter(|line| line.starts_with("LABEL control_")).count();
+    let short = calls(common::compile(&program("x"), "pyt", &[]));
+    let long = calls(common::compile(&program(&"x".repeat(80)), "pyt", &[]));
+    assert_eq!(short, long);
+    assert!(!common::compile(&program("x"), "pyt", &[]).contains("JMP func_putchar"));
+}
+
+#[test]
+fn release_builds_drop_the_assert() {
+    let run = common::run(&common::compile(&program("too big"), "pyt", &["--release"]), "12");
+    assert_eq!(run.output, "12");
+}
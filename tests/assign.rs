@@ -0,0 +1,43 @@
+mod common;
+
+fn rejected(code: &str) -> String {
+    let output = common::poyot_on(code, "pyt", &[common::temp("asm").to_str().unwrap()]);
+    assert!(!output.status.success());
+    common::stdout(&output)
+}
+
+#[test]
+fn compound_assignments_update_the_variable() {
+    let code = "fn[0] main() {\n  x = getnum();\n  x += 3;\n  x *= 4;\n  x -= 1;\n  x /= 2;\n  \
+                x %= 7;\n  x++;\n  putnum(x);\n  putchar(',');\n  x--;\n  x--;\n  putnum(x);\n}\n";
+    assert_eq!(common::compile_and_run(code, "5"), "2,0");
+}
+
+#[test]
+fn plain_assignment_to_a_defined_variable_is_rejected() {
+    let code = "fn[0] main() {\n  x = 1;\n  x = 2;\n  putnum(x);\n}\n";
+    assert!(rejected(code).contains("Variable x is already defined."));
+}
+
+#[test]
+fn assignment_to_a_defined_struct_is_rejected() {
+    let code = "struct Point { x, y }\n\nfn[0] main() {\n  p = Point { x: 1, y: 2 };\n  \
+                p = Point { x: 3, y: 4 };\n  putnum(p.x);\n}\n";
+    assert!(rejected(code).contains("Variable p is already defined."));
+}
+
+#[test]
+fn compound_assignment_to_an_undefined_variable_is_rejected() {
+    let code = "fn[0] main() {\n  y += 1;\n}\n";
+    assert!(rejected(code).contains("Undefined variable y"));
+}
+
+#[test]
+fn fmt_does_not_turn_an_assignment_into_an_update() {
+    let code = "fn[0] main() {\n  y = 1;\n  y = y + 1;\n  y += 2;\n  y++;\n}\n";
+    let path = common::temp("pyt");
+    std::fs::write(&path, code).unwrap();
+    let output = common::poyot(&["fmt", "--check", path.to_str().unwrap()]);
+    let _ = std::fs::remove_file(&path);
+    assert!(output.status.success(), "{}", common::stdout(&output));
+}
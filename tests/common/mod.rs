@@ -0,0 +1,149 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// A fresh path in the temporary directory ending in `extension`.
+pub fn temp(extension: &str) -> PathBuf {
+    let count = COUNT.fetch_add(1, Ordering::SeqCst);
+    std::env::temp_dir().join(format!("poyot-test-{}-{}.{}", std::process::id(), count, extension))
+}
+
+pub fn poyot(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_poyot")).args(args).output().expect("Cannot run poyot")
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+// Writes `code` to a file ending in `extension` and runs poyot on it with
+// `args` after the file name.
+pub fn poyot_on(code: &str, extension: &str, args: &[&str]) -> Output {
+    let path = temp(extension);
+    fs::write(&path, code).unwrap();
+    let mut all = vec![path.to_str().unwrap()];
+    all.extend(args);
+    let output = poyot(&all);
+    let _ = fs::remove_file(&path);
+    output
+}
+
+// Compiles `code`, a .pyt or .ir program, and returns the assembly.
+pub fn compile(code: &str, extension: &str, args: &[&str]) -> String {
+    let out = temp("asm");
+    let mut all = vec![out.to_str().unwrap()];
+    all.extend(args);
+    let output = poyot_on(code, extension, &all);
+    assert!(output.status.success(), "poyot failed:\n{}{}", stdout(&output),
+            String::from_utf8_lossy(&output.stderr));
+    let asm = fs::read_to_string(&out).unwrap();
+    let _ = fs::remove_file(&out);
+    asm
+}
+
+pub struct Run {
+    pub output: String,
+    pub stack: Vec<i64>,
+    // The most values the stack held at once.
+    pub depth: usize,
+    pub halted: bool
+}
+
+// Runs the assembly trans emits the way the Piet program would. Numbers and
+// characters are read from whitespace separated words of `input`.
+pub fn run(asm: &str, input: &str) -> Run {
+    let lines: Vec<Vec<&str>> = asm.lines().map(|line| line.split_whitespace().collect())
+        .filter(|line: &Vec<&str>| !line.is_empty())
+        .collect();
+    let mut labels = HashMap::new();
+    for (i, line) in lines.iter().enumerate() {
+        if line[0] == "LABEL" {
+            labels.insert(line[1], i);
+        }
+    }
+    let mut input = input.split_whitespace();
+    let mut stack: Vec<i64> = Vec::new();
+    let mut output = String::new();
+    let mut pc = 0;
+    let mut steps = 0;
+    let mut depth = 0;
+    while pc < lines.len() {
+        depth = depth.max(stack.len());
+        steps += 1;
+        assert!(steps < 10_000_000, "too many steps");
+        let line = &lines[pc];
+        pc += 1;
+        match line[0] {
+            "LABEL" => {}
+            "PUSH" => stack.push(line[1].parse().unwrap()),
+            "POP" => { stack.pop().expect("stack underflow"); }
+            "DUP" => stack.push(*stack.last().expect("stack underflow")),
+            "SWAP" => {
+                let len = stack.len();
+                stack.swap(len - 1, len - 2);
+            }
+            "ROLL" => {
+                let rolls = stack.pop().unwrap();
+                let depth = stack.pop().unwrap() as usize;
+                assert!(depth <= stack.len(), "roll deeper than the stack");
+                if depth > 0 {
+                    let start = stack.len() - depth;
+                    let rolls = rolls.rem_euclid(depth as i64) as usize;
+                    stack[start..].rotate_right(rolls);
+                }
+            }
+            "ADD" | "SUB" | "MUL" | "DIV" | "MOD" | "GREATER" => {
+                let a = stack.pop().unwrap();
+                let b = stack.pop().unwrap();
+                stack.push(match line[0] {
+                    "ADD" => b + a,
+                    "SUB" => b - a,
+                    "MUL" => b * a,
+                    "DIV" => b / a,
+                    // Piet's mod takes the sign of the divisor.
+                    "MOD" if a < 0 && b.rem_euclid(a) != 0 => b.rem_euclid(a) + a,
+                    "MOD" => b.rem_euclid(a),
+                    _ => (b > a) as i64
+                });
+            }
+            "NOT" => {
+                let a = stack.pop().unwrap();
+                stack.push((a == 0) as i64);
+            }
+            "INN" => stack.push(input.next().expect("no more input").parse().unwrap()),
+            "INC" => stack.push(input.next().expect("no more input").chars().next().unwrap() as i64),
+            "OUTN" => output.push_str(&stack.pop().unwrap().to_string()),
+            "OUTC" => output.push(char::from_u32(stack.pop().unwrap() as u32).unwrap()),
+            "JMP" => pc = labels[line[1]],
+            "JEZ" => {
+                if stack.pop().unwrap() == 0 {
+                    pc = labels[line[1]];
+                }
+            }
+            "HALT" => return Run { output, stack, depth, halted: true },
+            other => panic!("unknown instruction {}", other)
+        }
+    }
+    Run { output, stack, depth, halted: false }
+}
+
+pub fn compile_and_run(code: &str, input: &str) -> String {
+    run(&compile(code, "pyt", &[]), input).output
+}
+
+pub fn scripts() -> Vec<PathBuf> {
+    let mut res: Vec<PathBuf> = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/scripts")).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pyt"))
+        .collect();
+    res.sort();
+    res
+}
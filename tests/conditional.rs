@@ -0,0 +1,54 @@
+mod common;
+
+fn rejected(code: &str) -> String {
+    let output = common::poyot_on(code, "pyt", &[common::temp("asm").to_str().unwrap()]);
+    assert!(!output.status.success());
+    common::stdout(&output)
+}
+
+#[test]
+fn an_if_expression_picks_the_value_of_its_arm() {
+    let code = "fn[0] main() {\n  x = getnum();\n  y = if x > 0 { x * 2 } else { 0 - x };\n  putnum(y);\n}\n";
+    assert_eq!(common::compile_and_run(code, "4"), "8");
+    assert_eq!(common::compile_and_run(code, "-3"), "3");
+}
+
+#[test]
+fn if_expressions_nest_in_arms_and_conditions() {
+    let code = "fn[0] main() {\n  x = getnum();\n  \
+                y = if x > 0 { if x > 9 { 2 } else { 1 } } else { if x < 0 { 0 - 1 } else { 0 } };\n  \
+                z = if if x > 5 { 1 } else { 0 } { 10 } else { 20 };\n  \
+                putnum(y);\n  putchar(',');\n  putnum(z);\n}\n";
+    let asm = common::compile(code, "pyt", &[]);
+    assert_eq!(common::run(&asm, "12").output, "2,10");
+    assert_eq!(common::run(&asm, "3").output, "1,20");
+    assert_eq!(common::run(&asm, "0").output, "0,20");
+    assert_eq!(common::run(&asm, "-7").output, "-1,20");
+}
+
+#[test]
+fn if_expressions_inside_larger_expressions_keep_the_order_of_calls() {
+    let code = "fn[0] main() {\n  a = getnum() - if getnum() > 0 { getnum() } else { 100 };\n  \
+                putnum(a + if a > 0 { 1 } else { 2 });\n}\n";
+    assert_eq!(common::compile_and_run(code, "10 1 3"), "8");
+    assert_eq!(common::compile_and_run(code, "10 0"), "-88");
+}
+
+#[test]
+fn the_stack_does_not_grow_across_if_expressions_in_a_loop() {
+    let code = |n: i32| format!("fn[1] sum(n, acc) {{\n  if n < 1 {{\n    return acc;\n  }}\n  \
+                                 return sum(n - 1, acc + if n % 2 == 0 {{ n }} else {{ 0 }});\n}}\n\n\
+                                 fn[0] main() {{\n  putnum(sum({}, 0));\n}}\n", n);
+    let short = common::run(&common::compile(&code(4), "pyt", &[]), "");
+    let long = common::run(&common::compile(&code(40), "pyt", &[]), "");
+    assert_eq!(short.output, "6");
+    assert_eq!(long.output, "420");
+    assert_eq!(short.stack.len(), long.stack.len());
+}
+
+#[test]
+fn struct_arms_are_rejected() {
+    let code = "struct Point { x, y }\n\nfn[0] main() {\n  a = Point { x: 1, y: 2 };\n  \
+                b = Point { x: 3, y: 4 };\n  p = if getnum() > 0 { a } else { b };\n  putnum(p.x);\n}\n";
+    assert!(rejected(code).contains("if-expression arms must be integers"));
+}
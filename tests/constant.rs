@@ -0,0 +1,183 @@
+use poyot::constant;
+use poyot::ir;
+use poyot::ssa;
+use poyot::tokenize;
+
+// Builds the SSA form of the textual IR `text` and returns its listing before
+// and after constant folding.
+fn fold(text: &str) -> (String, String) {
+    let program = ir::parse(&tokenize::tokenize(text).expect("Cannot tokenize the IR")).expect("Cannot parse the IR");
+    let mut program = ssa::build(&program);
+    let before = ssa::print(&program);
+    constant::fold(&mut program);
+    (before, ssa::print(&program))
+}
+
+#[test]
+fn constant_operators_fold_into_the_statements_reading_them() {
+    let (before, after) = fold("fn[1] getnum();
+
+fn[1] main() {
+  0:
+    %0 = add 2, 3;
+    %1 = multiply %0, 4;
+    %2 = call getnum;
+    %3 = sub %1, %2;
+    return %3;
+}
+");
+    assert_eq!(before, "fn[1] main() {
+  b0:
+    %0 = add 2, 3;
+    %1 = multiply %0, 4;
+    %2 = call getnum;
+    %3 = sub %1, %2;
+    return %3;
+}
+");
+    assert_eq!(after, "fn[1] main() {
+  b0:
+    %2 = call getnum;
+    %3 = sub 20, %2;
+    return %3;
+}
+");
+}
+
+#[test]
+fn a_branch_on_a_constant_becomes_a_jump() {
+    let (before, after) = fold("fn[1] getnum();
+
+fn[1] main() {
+  0 -> 1, 2:
+    %0 = greater 2, 1;
+    if %0;
+  1 -> 3:
+    %1 = call getnum;
+    jump;
+  2 -> 3:
+    %1 = add 0, 7;
+    jump;
+  3:
+    return %1;
+}
+");
+    assert_eq!(before, "fn[1] main() {
+  b0:
+    %1 = greater 2, 1;
+    branch %1, b1, b2;
+  b1:
+    %2 = call getnum;
+    jump b3(%2);
+  b2:
+    %3 = add 0, 7;
+    jump b3(%3);
+  b3(%0):
+    return %0;
+}
+");
+    // The block that is no longer reached goes, and with it the only other
+    // value of the parameter.
+    assert_eq!(after, "fn[1] main() {
+  b0:
+    jump b1;
+  b1:
+    %2 = call getnum;
+    jump b2;
+  b2:
+    return %2;
+}
+");
+}
+
+#[test]
+fn a_parameter_receiving_the_same_constant_from_every_branch_folds() {
+    let (before, after) = fold("fn[1] getnum();
+
+fn[1] main() {
+  0 -> 1, 2:
+    %0 = call getnum;
+    if %0;
+  1 -> 3:
+    %1 = add 1, 2;
+    jump;
+  2 -> 3:
+    %1 = sub 5, 2;
+    jump;
+  3:
+    %2 = multiply %1, 2;
+    return %2;
+}
+");
+    assert_eq!(before, "fn[1] main() {
+  b0:
+    %1 = call getnum;
+    branch %1, b1, b2;
+  b1:
+    %2 = add 1, 2;
+    jump b3(%2);
+  b2:
+    %3 = sub 5, 2;
+    jump b3(%3);
+  b3(%0):
+    %4 = multiply %0, 2;
+    return %4;
+}
+");
+    assert_eq!(after, "fn[1] main() {
+  b0:
+    %1 = call getnum;
+    branch %1, b1, b2;
+  b1:
+    jump b3;
+  b2:
+    jump b3;
+  b3:
+    return 6;
+}
+");
+}
+
+#[test]
+fn statements_with_effects_take_constants_but_are_not_folded() {
+    let (_, after) = fold("fn[1] getnum();
+
+fn[1] load(address);
+
+fn[1] main() {
+  0:
+    %0 = add 1, 1;
+    %1 = call getnum;
+    %2 = call load %0;
+    %3 = asm(\"ADD\") -> (sum) %0, %0;
+    print \"{}\\n\" %0;
+    %4 = add %1, %2;
+    %5 = add %4, %3;
+    return %5;
+}
+");
+    assert_eq!(after, "fn[1] main() {
+  b0:
+    %1 = call getnum;
+    %2 = call load 2;
+    %3 = asm(\"ADD\") 2, 2;
+    print \"{}\\n\" 2;
+    %4 = add %1, %2;
+    %5 = add %4, %3;
+    return %5;
+}
+");
+}
+
+#[test]
+fn division_by_zero_is_left_for_the_program() {
+    let (before, after) = fold("fn[1] main() {
+  0:
+    %0 = division 1, 0;
+    %1 = modulo 7, 0;
+    %2 = add %0, %1;
+    return %2;
+}
+");
+    assert_eq!(after, before);
+}
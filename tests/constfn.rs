@@ -0,0 +1,83 @@
+mod common;
+
+use poyot::eval;
+
+// Compiles `code` and returns the textual IR of main with poyot's warnings.
+// The IR is emitted after inlining, so a call meant to stay is marked
+// #[noinline].
+fn main_ir(code: &str) -> (String, String) {
+    let output = common::poyot_on(code, "pyt", &["--emit", "ir-text"]);
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    let text = common::stdout(&output);
+    let start = text.find("fn[0] main()").unwrap();
+    let end = start + text[start..].find("\n}\n").unwrap();
+    (text[start..end].to_string(), String::from_utf8_lossy(&output.stderr).to_string())
+}
+
+fn rejected(code: &str) -> String {
+    let output = common::poyot_on(code, "pyt", &[common::temp("asm").to_str().unwrap()]);
+    assert!(!output.status.success());
+    common::stdout(&output)
+}
+
+const FACT: &str = "const fn[1] fact(n) {\n  return if n < 2 { 1 } else { n * fact(n - 1) };\n}\n\n";
+
+#[test]
+fn a_call_with_constant_arguments_is_evaluated() {
+    let code = format!("{}fn[0] main() {{\n  putnum(fact(6));\n}}\n", FACT);
+    let (ir, warnings) = main_ir(&code);
+    assert!(ir.contains("call putnum 720;"), "{}", ir);
+    assert!(!ir.contains("call fact"), "{}", ir);
+    assert_eq!(warnings, "");
+}
+
+#[test]
+fn a_call_with_an_argument_known_at_run_time_is_left_alone() {
+    let code = format!("{}fn[0] main() {{\n  putnum(fact(getnum()));\n}}\n", FACT);
+    let (ir, _) = main_ir(&code);
+    assert!(ir.contains("call fact"), "{}", ir);
+    assert_eq!(common::compile_and_run(&code, "5"), "120");
+}
+
+#[test]
+fn too_many_nested_calls_fall_back_to_a_call_at_run_time() {
+    let code = format!("const fn[1] count(n) {{\n  return if n == 0 {{ 0 }} else {{ 1 + count(n - 1) }};\n}}\n\n\
+                        fn[0] main() {{\n  putnum(count({}));\n}}\n", eval::MAX_DEPTH + 10);
+    let (ir, warnings) = main_ir(&code);
+    assert!(ir.contains("call count"), "{}", ir);
+    assert!(warnings.contains(&format!("Warning: cannot evaluate count({}) at compile time: it exceeded {} nested calls",
+                                       eval::MAX_DEPTH + 10, eval::MAX_DEPTH)), "{}", warnings);
+    assert_eq!(common::compile_and_run(&code, ""), (eval::MAX_DEPTH + 10).to_string());
+}
+
+#[test]
+fn too_many_steps_fall_back_to_a_call_at_run_time() {
+    let code = format!("#[noinline]\nconst fn[1] spin(n, acc) {{\n  if n == 0 {{\n    return acc;\n  }}\n  \
+                        return spin(n - 1, acc + 1);\n}}\n\nfn[0] main() {{\n  putnum(spin({}, 0));\n}}\n",
+                       eval::MAX_STEPS);
+    let (ir, warnings) = main_ir(&code);
+    assert!(ir.contains("call spin"), "{}", ir);
+    assert!(warnings.contains(&format!("it did not finish within {} steps", eval::MAX_STEPS)), "{}", warnings);
+}
+
+#[test]
+fn dividing_by_zero_falls_back_to_a_call_at_run_time() {
+    let code = "#[noinline]\nconst fn[1] inverse(n) {\n  return 100 / n;\n}\n\n\
+                fn[0] main() {\n  putnum(inverse(4));\n  putnum(inverse(0));\n}\n";
+    let (ir, warnings) = main_ir(code);
+    assert!(ir.contains("call putnum 25;"), "{}", ir);
+    assert!(ir.contains("call inverse 0"), "{}", ir);
+    assert!(warnings.contains("Warning: cannot evaluate inverse(0) at compile time: it divides by zero"), "{}", warnings);
+}
+
+#[test]
+fn a_const_fn_calling_a_function_that_is_not_const_is_rejected() {
+    let code = "const fn[1] f(n) {\n  return n + getnum();\n}\n\nfn[0] main() {\n  putnum(f(1));\n}\n";
+    assert!(rejected(code).contains("const fn f calls getnum, which is not const"));
+}
+
+#[test]
+fn a_const_fn_that_prints_is_rejected() {
+    let code = "const fn[1] f(n) {\n  print(\"{}\", n);\n  return n;\n}\n\nfn[0] main() {\n  putnum(f(1));\n}\n";
+    assert!(rejected(code).contains("const fn f cannot print"));
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use poyot::dead;
+use poyot::ir;
+use poyot::ssa;
+use poyot::tokenize;
+
+fn functions(code: &str) -> Vec<String> {
+    let output = common::poyot_on(code, "pyt", &["--emit", "ir-text"]);
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    common::stdout(&output).lines()
+        .filter(|line| line.starts_with("fn[") && line.ends_with('{'))
+        .map(|line| line[line.find(' ').unwrap() + 1..line.find('(').unwrap()].to_string())
+        .collect()
+}
+
+// Never inlined, so that calls to it stay calls.
+fn body(name: &str) -> String {
+    format!("#[noinline]\nfn[1] {}(x) {{\n  return x * 2;\n}}\n\n", name)
+}
+
+#[test]
+fn a_function_only_referenced_by_a_label_is_kept() {
+    let code = format!("{}fn[0] main() {{\n  f = &twice;\n  putnum(f(getnum()));\n}}\n", body("twice"));
+    assert_eq!(functions(&code), ["main", "twice"]);
+    assert_eq!(common::compile_and_run(&code, "21"), "42");
+}
+
+#[test]
+fn a_function_nothing_calls_is_dropped() {
+    let code = format!("{}{}fn[0] main() {{\n  putnum(used(getnum()));\n}}\n", body("unused"), body("used"));
+    assert_eq!(functions(&code), ["main", "used"]);
+}
+
+#[test]
+fn a_function_only_called_by_a_dropped_function_is_dropped() {
+    let code = format!("{}#[noinline]\nfn[1] outer(x) {{\n  return inner(x) + 1;\n}}\n\n\
+                        fn[0] main() {{\n  putnum(getnum());\n}}\n", body("inner"));
+    assert_eq!(functions(&code), ["main"]);
+}
+
+#[test]
+fn a_function_inlined_everywhere_is_dropped() {
+    let code = "fn[1] double(x) {\n  return x * 2;\n}\n\nfn[0] main() {\n  putnum(double(getnum()));\n}\n";
+    assert_eq!(functions(code), ["main"]);
+    assert_eq!(common::compile_and_run(code, "8"), "16");
+}
+
+// Builds the SSA form of the textual IR `text` and returns its listing after
+// dead code elimination.
+fn eliminate(text: &str) -> String {
+    let program = ir::parse(&tokenize::tokenize(text).expect("Cannot tokenize the IR")).expect("Cannot parse the IR");
+    let mut program = ssa::build(&program);
+    dead::eliminate(&mut program);
+    ssa::print(&program)
+}
+
+#[test]
+fn blocks_no_path_reaches_are_dropped() {
+    assert_eq!(eliminate("fn[1] getnum();
+
+fn[0] putnum(x);
+
+fn[0] main() {
+  0 -> 2:
+    %0 = call getnum;
+    jump;
+  1 -> 2:
+    call putnum 1;
+    jump;
+  2:
+    call putnum %0;
+}
+"), "fn[0] main() {
+  b0:
+    %1 = call getnum;
+    call putnum %1;
+    return;
+}
+");
+}
+
+#[test]
+fn unused_pure_statements_are_dropped() {
+    assert_eq!(eliminate("fn[1] getnum();
+
+fn[1] main() {
+  0:
+    %0 = call getnum;
+    %1 = add %0, 1;
+    %2 = multiply %1, %1;
+    %3 = division %0, 4;
+    %4 = modulo %2, -3;
+    %5 = greater %4, %3;
+    %6 = call getnum;
+    return %0;
+}
+"), "fn[1] main() {
+  b0:
+    %0 = call getnum;
+    %6 = call getnum;
+    return %0;
+}
+");
+}
+
+#[test]
+fn an_unused_division_that_may_divide_by_zero_is_kept() {
+    assert_eq!(eliminate("fn[1] getnum();
+
+fn[1] main() {
+  0:
+    %0 = call getnum;
+    %1 = division %0, 0;
+    %2 = modulo 7, %0;
+    %3 = division %0, %0;
+    return %0;
+}
+"), "fn[1] main() {
+  b0:
+    %0 = call getnum;
+    %1 = division %0, 0;
+    %2 = modulo 7, %0;
+    %3 = division %0, %0;
+    return %0;
+}
+");
+}
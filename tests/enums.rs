@@ -0,0 +1,63 @@
+mod common;
+
+const ENUMS: &str = "enum Dir {\n  North,\n  East,\n  South = 10,\n  West\n}\n\n\
+                     enum Key {\n  Left = 'l',\n  Right = 'r'\n}\n\n";
+
+fn compile(main: &str) -> (String, String) {
+    let code = format!("{}fn[0] main() {{\n{}}}\n", ENUMS, main);
+    let output = common::poyot_on(&code, "pyt", &[common::temp("asm").to_str().unwrap()]);
+    (common::stdout(&output), String::from_utf8_lossy(&output.stderr).to_string())
+}
+
+#[test]
+fn variants_count_up_from_the_last_explicit_value() {
+    let code = format!("{}fn[0] main() {{\n  print(\"{{}} {{}} {{}} {{}} {{}}\", Dir::North, Dir::East, Dir::South, \
+                        Dir::West, Key::Right);\n}}\n", ENUMS);
+    assert_eq!(common::compile_and_run(&code, ""), "0 1 10 11 114");
+}
+
+#[test]
+fn a_match_on_variants_picks_the_arm_of_the_value() {
+    let code = format!("{}fn[0] main() {{\n  d = getnum();\n  match d {{\n    Dir::North | Dir::South => {{ putchar('v'); }}\n    \
+                        Dir::East => {{ putchar('e'); }}\n    Dir::West => {{ putchar('w'); }}\n  }}\n  \
+                        match getchar() {{\n    Key::Left => {{ putchar('<'); }}\n    _ => {{ putchar('>'); }}\n  }}\n}}\n",
+                       ENUMS);
+    let asm = common::compile(&code, "pyt", &[]);
+    assert_eq!(common::run(&asm, "0 l").output, "v<");
+    assert_eq!(common::run(&asm, "10 r").output, "v>");
+    assert_eq!(common::run(&asm, "1 l").output, "e<");
+    assert_eq!(common::run(&asm, "11 x").output, "w>");
+}
+
+#[test]
+fn a_match_covering_every_variant_needs_no_wildcard() {
+    let (_, warnings) = compile("  match getnum() {\n    Dir::North | Dir::East => {}\n    Dir::South | Dir::West => {}\n  }\n");
+    assert!(!warnings.contains("Warning"), "{}", warnings);
+}
+
+#[test]
+fn a_missing_variant_is_reported() {
+    let (_, warnings) = compile("  match getnum() {\n    Dir::North | Dir::East => {}\n    Dir::West => {}\n  }\n");
+    assert!(warnings.contains("Warning: match is not exhaustive, Dir::South is not covered"), "{}", warnings);
+}
+
+#[test]
+fn a_match_mixing_enums_must_cover_every_value() {
+    let (_, warnings) = compile("  match getnum() {\n    Dir::North => {}\n    Key::Left | Key::Right => {}\n  }\n");
+    assert!(warnings.contains("Warning: match is not exhaustive"), "{}", warnings);
+    assert!(!warnings.contains("Dir::"), "{}", warnings);
+}
+
+#[test]
+fn an_unknown_variant_is_rejected() {
+    let (errors, _) = compile("  match getnum() {\n    Dir::Up => {}\n    _ => {}\n  }\n");
+    assert!(errors.contains("Unknown enum variant Dir::Up"), "{}", errors);
+}
+
+#[test]
+fn an_enum_defined_twice_is_rejected() {
+    let code = format!("{}enum Dir {{\n  Up\n}}\n\nfn[0] main() {{\n  putnum(Dir::Up);\n}}\n", ENUMS);
+    let output = common::poyot_on(&code, "pyt", &[common::temp("asm").to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(common::stdout(&output).contains("Enum Dir is already defined."));
+}
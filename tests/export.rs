@@ -0,0 +1,34 @@
+mod common;
+
+const CODE: &str = "fn[0] main() {\n  n = getnum();\n  assert(n < 10, \"big\");\n}\n";
+
+fn emit(args: &[&str]) -> String {
+    let output = common::poyot_on(CODE, "pyt", args);
+    assert!(output.status.success());
+    common::stdout(&output).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[test]
+fn assert_lines_are_the_source_lines() {
+    let json = emit(&["--emit", "ast"]).replace(' ', "");
+    assert!(json.contains("\"line\":3,\"message\":\"big\""), "{}", json);
+    let sexp = emit(&["--emit", "ast", "--sexp"]);
+    assert!(sexp.contains(":line 3 :message \"big\""), "{}", sexp);
+}
+
+#[test]
+fn tokens_and_assert_agree_on_the_line() {
+    let json = emit(&["--emit", "tokens"]).replace(' ', "");
+    assert!(json.contains("\"value\":\"assert\",\"line\":3"), "{}", json);
+}
+
+#[test]
+fn compiling_writes_nothing_but_warnings_to_stderr() {
+    let out = common::temp("asm");
+    for args in [vec![out.to_str().unwrap()], vec!["unused.asm", "--emit", "ir"]] {
+        let output = common::poyot_on(CODE, "pyt", &args);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    }
+    let _ = std::fs::remove_file(&out);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Every script and the library they import, which are kept formatted.
+fn sources() -> Vec<PathBuf> {
+    let lib = Path::new(env!("CARGO_MANIFEST_DIR")).join("scripts/lib");
+    let mut res = common::scripts();
+    res.extend(fs::read_dir(lib).unwrap().map(|entry| entry.unwrap().path()));
+    res
+}
+
+// `code` with the indentation removed and runs of spaces squeezed to one.
+fn mangle(code: &str) -> String {
+    code.lines()
+        .map(|line| line.split(' ').filter(|word| !word.is_empty()).collect::<Vec<_>>().join(" ") + "\n")
+        .collect()
+}
+
+#[test]
+fn formatting_restores_every_script_and_is_idempotent() {
+    let sources = sources();
+    let copies: Vec<PathBuf> = sources.iter().map(|_| common::temp("pyt")).collect();
+    for (source, copy) in sources.iter().zip(&copies) {
+        fs::write(copy, mangle(&fs::read_to_string(source).unwrap())).unwrap();
+    }
+    let args: Vec<&str> = copies.iter().map(|path| path.to_str().unwrap()).collect();
+    let output = common::poyot(&[&["fmt"], args.as_slice()].concat());
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    for (source, copy) in sources.iter().zip(&copies) {
+        assert_eq!(fs::read_to_string(copy).unwrap(), fs::read_to_string(source).unwrap(), "{}", source.display());
+    }
+
+    let output = common::poyot(&[&["fmt", "--check"], args.as_slice()].concat());
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    let output = common::poyot(&[&["fmt"], args.as_slice()].concat());
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    for (source, copy) in sources.iter().zip(&copies) {
+        assert_eq!(fs::read_to_string(copy).unwrap(), fs::read_to_string(source).unwrap(), "{}", source.display());
+        let _ = fs::remove_file(copy);
+    }
+}
+
+#[test]
+fn check_reports_unformatted_files_without_changing_them() {
+    let path = common::temp("pyt");
+    let code = "fn[0] main(){print(\"{}\",1);}\n";
+    fs::write(&path, code).unwrap();
+    let output = common::poyot(&["fmt", "--check", path.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(common::stdout(&output).contains("is not formatted"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), code);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn a_comment_inside_a_statement_is_not_dropped() {
+    let path = common::temp("pyt");
+    let code = "fn[0] main() {\n  print(\"{}\", // one\n    1);\n}\n";
+    fs::write(&path, code).unwrap();
+    let output = common::poyot(&["fmt", path.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(common::stdout(&output).contains(":2: Cannot keep a comment inside a statement or declaration"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), code);
+    let _ = fs::remove_file(&path);
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use std::fs;
+use std::process::Output;
+
+const MATH: &str = "fn[1] twice(x) {\n  return x * 2;\n}\n\npub fn[1] quad(x) {\n  return twice(twice(x));\n}\n\n\
+                    pub fn[1] square(x) {\n  return x * x;\n}\n";
+
+// Writes `files` into a fresh directory and compiles the first one, with
+// `args` after the input and output files. Returns poyot's output and the
+// output of the compiled program run on `input`.
+fn project(files: &[(&str, &str)], args: &[&str], input: &str) -> (Output, String) {
+    let dir = common::temp("dir");
+    fs::create_dir(&dir).unwrap();
+    for (name, code) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, code).unwrap();
+    }
+    let main = dir.join(files[0].0);
+    let out = dir.join("out.asm");
+    let mut all = vec![main.to_str().unwrap(), out.to_str().unwrap()];
+    all.extend(args);
+    let output = common::poyot(&all);
+    let run = match fs::read_to_string(&out) {
+        Ok(asm) => common::run(&asm, input).output,
+        Err(_) => String::new()
+    };
+    let _ = fs::remove_dir_all(&dir);
+    (output, run)
+}
+
+fn rejected(files: &[(&str, &str)]) -> String {
+    let (output, _) = project(files, &[], "");
+    assert!(!output.status.success());
+    common::stdout(&output)
+}
+
+#[test]
+fn public_functions_are_called_qualified_and_unqualified() {
+    let main = "import \"math.pyt\";\n\nfn[0] main() {\n  x = getnum();\n  putnum(math::square(x));\n  \
+                putchar(',');\n  putnum(quad(x));\n}\n";
+    let (output, run) = project(&[("main.pyt", main), ("math.pyt", MATH)], &[], "3");
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    assert_eq!(run, "9,12");
+}
+
+#[test]
+fn a_local_function_shadows_an_imported_one() {
+    let main = "import \"math.pyt\";\n\nfn[1] square(x) {\n  return x + x;\n}\n\n\
+                fn[0] main() {\n  putnum(square(5));\n  putchar(',');\n  putnum(math::square(5));\n}\n";
+    let (output, run) = project(&[("main.pyt", main), ("math.pyt", MATH)], &[], "");
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    assert_eq!(run, "10,25");
+}
+
+#[test]
+fn a_private_function_cannot_be_called_qualified() {
+    let main = "import \"math.pyt\";\n\nfn[0] main() {\n  putnum(math::twice(1));\n}\n";
+    assert!(rejected(&[("main.pyt", main), ("math.pyt", MATH)]).contains("twice is private in module math"));
+}
+
+#[test]
+fn a_private_function_is_not_imported_unqualified() {
+    let main = "import \"math.pyt\";\n\nfn[0] main() {\n  putnum(twice(1));\n}\n";
+    assert!(rejected(&[("main.pyt", main), ("math.pyt", MATH)]).contains("Function twice is not defined."));
+}
+
+#[test]
+fn a_name_exported_by_two_modules_must_be_qualified() {
+    let main = "import \"math.pyt\";\nimport \"other.pyt\";\n\nfn[0] main() {\n  putnum(square(2));\n}\n";
+    let other = "pub fn[1] square(x) {\n  return x;\n}\n";
+    assert!(rejected(&[("main.pyt", main), ("math.pyt", MATH), ("other.pyt", other)])
+        .contains("square is exported by several modules, qualify it with the module name"));
+}
+
+#[test]
+fn an_import_cycle_is_rejected() {
+    let main = "import \"a.pyt\";\n\nfn[0] main() {\n  putnum(a::f());\n}\n";
+    let a = "import \"b.pyt\";\n\npub fn[1] f() {\n  return b::g();\n}\n";
+    let b = "import \"a.pyt\";\n\npub fn[1] g() {\n  return 1;\n}\n";
+    let text = rejected(&[("main.pyt", main), ("a.pyt", a), ("b.pyt", b)]);
+    assert!(text.contains("Import cycle: "), "{}", text);
+    assert!(text.contains("a.pyt -> ") && text.trim_end().ends_with("a.pyt"), "{}", text);
+}
+
+#[test]
+fn a_module_imported_twice_is_loaded_once() {
+    let main = "import \"a.pyt\";\nimport \"math.pyt\";\n\nfn[0] main() {\n  putnum(a::f(2) + square(2));\n}\n";
+    let a = "import \"math.pyt\";\n\npub fn[1] f(x) {\n  return quad(x);\n}\n";
+    let (output, run) = project(&[("main.pyt", main), ("a.pyt", a), ("math.pyt", MATH)], &[], "");
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    assert_eq!(run, "12");
+}
+
+#[test]
+fn modules_are_found_on_the_search_path() {
+    let main = "import \"math.pyt\";\n\nfn[0] main() {\n  putnum(square(7));\n}\n";
+    let dir = common::temp("dir");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("math.pyt"), MATH).unwrap();
+    let (output, run) = project(&[("main.pyt", main)], &["-I", dir.to_str().unwrap()], "");
+    let _ = fs::remove_dir_all(&dir);
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    assert_eq!(run, "49");
+    assert!(rejected(&[("main.pyt", main)]).contains("Cannot find math.pyt"));
+}
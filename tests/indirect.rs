@@ -0,0 +1,55 @@
+mod common;
+
+const FUNCS: &str = "fn[1] one(a) {\n  return a + 1;\n}\n\nfn[1] two(a, b) {\n  return a + b;\n}\n\n";
+
+#[test]
+fn indirect_calls_reach_the_function_value() {
+    let code = format!("{}fn[0] main() {{\n  f = &one;\n  g = &two;\n  putnum(f(1));\n  \
+                        putnum(g(2, 3));\n}}\n", FUNCS);
+    let run = common::run(&common::compile(&code, "pyt", &[]), "");
+    assert_eq!(run.output, "25");
+}
+
+#[test]
+fn a_function_value_of_another_arity_halts() {
+    let code = format!("{}fn[0] main() {{\n  f = &one;\n  g = &two;\n  putnum(f(1));\n  \
+                        putnum(g(5));\n  putnum(9);\n}}\n", FUNCS);
+    let run = common::run(&common::compile(&code, "pyt", &[]), "");
+    assert_eq!(run.output, "2");
+    assert!(run.halted);
+}
+
+#[test]
+fn a_value_that_is_no_function_halts() {
+    let code = format!("{}fn[0] main() {{\n  f = &one;\n  g = &two;\n  h = 7;\n  \
+                        putnum(h(1));\n  putnum(9);\n}}\n", FUNCS);
+    let run = common::run(&common::compile(&code, "pyt", &[]), "");
+    assert_eq!(run.output, "");
+    assert!(run.halted);
+}
+
+#[test]
+fn call_statements_and_expressions_take_values_with_their_own_result_counts() {
+    let code = format!("{}fn[0] show(a) {{\n  putnum(a * 10);\n}}\n\n\
+                        fn[0] main() {{\n  f = &one;\n  g = &show;\n  g(f(getnum()));\n  \
+                        putnum(f(5));\n}}\n", FUNCS);
+    assert_eq!(common::compile_and_run(&code, "3"), "406");
+}
+
+#[test]
+fn calling_a_function_that_returns_a_value_as_a_statement_halts() {
+    let code = format!("{}fn[0] show(a) {{\n  putnum(a);\n}}\n\n\
+                        fn[0] main() {{\n  g = &show;\n  g(1);\n  f = &one;\n  f(2);\n  putnum(9);\n}}\n", FUNCS);
+    let run = common::run(&common::compile(&code, "pyt", &[]), "");
+    assert_eq!(run.output, "1");
+    assert!(run.halted);
+}
+
+#[test]
+fn an_indirect_call_without_a_matching_function_is_rejected() {
+    let code = format!("{}fn[0] main() {{\n  f = &one;\n  f(1);\n}}\n", FUNCS);
+    let output = common::poyot_on(&code, "pyt", &[common::temp("asm").to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(common::stdout(&output)
+        .contains("No function taking 1 args and returning 0 values is referenced, cannot call f"));
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use poyot::generate;
+use poyot::import;
+use poyot::ir;
+use poyot::parse;
+use poyot::tokenize;
+
+use std::fs;
+
+const DIVMOD: &str = include_str!("fixtures/divmod.ir");
+const COUNTDOWN: &str = include_str!("../scripts/countdown.ir");
+
+fn parse_ir(text: &str) -> generate::Program {
+    ir::parse(&tokenize::tokenize(text).expect("Cannot tokenize the IR")).expect("Cannot parse the IR")
+}
+
+#[test]
+fn generated_ir_prints_and_parses_back_to_the_same_program() {
+    for path in common::scripts() {
+        let code = fs::read_to_string(&path).unwrap();
+        let ast = parse::parse(&tokenize::tokenize(&code).unwrap()).unwrap();
+        let ast = import::resolve_imports(ast, &path, &[]).unwrap();
+        let program = generate::generate(&ast, true).unwrap();
+        assert_eq!(parse_ir(&ir::print(&program)), program, "{}", path.display());
+    }
+}
+
+#[test]
+fn optimized_ir_prints_and_parses_back_to_the_same_text() {
+    for path in common::scripts() {
+        let output = common::poyot(&[path.to_str().unwrap(), "--emit", "ir-text"]);
+        assert!(output.status.success(), "{}", path.display());
+        let text = common::stdout(&output);
+        assert_eq!(ir::print(&parse_ir(&text)), text, "{}", path.display());
+    }
+}
+
+#[test]
+fn hand_written_ir_round_trips() {
+    for text in [DIVMOD, COUNTDOWN] {
+        let program = parse_ir(text);
+        assert_eq!(ir::print(&program), text);
+        assert_eq!(parse_ir(&ir::print(&program)), program);
+    }
+}
+
+#[test]
+fn the_hand_written_fixture_runs() {
+    let asm = common::compile(DIVMOD, "ir", &[]);
+    assert_eq!(common::run(&asm, "17 5").output, "3:2 5\n");
+    assert_eq!(common::run(&asm, "17 0").output, "none\n");
+}
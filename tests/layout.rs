@@ -0,0 +1,47 @@
+mod common;
+
+// The registers on the stack at the start of a block are those all of its
+// predecessors hold; whatever else an edge carries is dropped before the jump.
+
+#[test]
+fn a_register_defined_in_one_arm_is_dropped_at_the_join() {
+    let code = "fn[0] main() {\n  n = getnum();\n  a = n * 2;\n  match n {\n    \
+                1 => {\n      t = a + 5;\n      putnum(t);\n    }\n    \
+                2 => {\n      u = a * 3;\n      v = u - 1;\n      putnum(v);\n    }\n    \
+                _ => {}\n  }\n  putchar(',');\n  putnum(a + n);\n}\n";
+    assert_eq!(common::compile_and_run(code, "1"), "7,3");
+    assert_eq!(common::compile_and_run(code, "2"), "11,6");
+    assert_eq!(common::compile_and_run(code, "3"), ",9");
+}
+
+#[test]
+fn an_if_without_else_keeps_the_registers_defined_before_it() {
+    let code = "fn[0] main() {\n  a = getnum();\n  b = getnum();\n  if a > b {\n    \
+                d = a - b;\n    putnum(d);\n    putchar(',');\n  }\n  putnum(a * 10 + b);\n}\n";
+    assert_eq!(common::compile_and_run(code, "5 2"), "3,52");
+    assert_eq!(common::compile_and_run(code, "2 5"), "25");
+}
+
+#[test]
+fn the_stack_does_not_grow_across_joins_in_a_loop() {
+    let code = |n: i32| format!("fn[1] count(n, acc) {{\n  if n < 1 {{\n    return acc;\n  }}\n  \
+                                 match n % 3 {{\n    0 => {{\n      t = n * 2;\n      \
+                                 acc += t;\n    }}\n    1 => {{ acc += 1; }}\n    _ => {{}}\n  }}\n  \
+                                 return count(n - 1, acc);\n}}\n\nfn[0] main() {{\n  \
+                                 putnum(count({}, 0));\n}}\n", n);
+    let short = common::run(&common::compile(&code(3), "pyt", &[]), "");
+    let long = common::run(&common::compile(&code(300), "pyt", &[]), "");
+    assert_eq!(short.output, "7");
+    assert_eq!(long.output, "30400");
+    assert_eq!(short.stack.len(), long.stack.len());
+}
+
+// Block 1 holds %0, %1 and %2 and branches to the join block 3, which starts
+// with %0 and %2 only, so the branch drops %1 from under %2.
+#[test]
+fn a_branch_into_a_join_drops_the_registers_its_target_does_not_hold() {
+    let asm = common::compile(include_str!("fixtures/join.ir"), "ir", &[]);
+    assert_eq!(common::run(&asm, "1 0").output, "1 101\n");
+    assert_eq!(common::run(&asm, "1 1").output, "1 201\n");
+    assert_eq!(common::run(&asm, "0").output, "0 200\n");
+}
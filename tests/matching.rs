@@ -0,0 +1,59 @@
+mod common;
+
+fn warnings(code: &str) -> String {
+    let output = common::poyot_on(code, "pyt", &[common::temp("asm").to_str().unwrap()]);
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+// A main function printing the arm of `arms` a number read from input takes.
+fn classify(arms: &str) -> String {
+    format!("fn[0] main() {{\n  n = getnum();\n  match n {{\n{}  }}\n  putchar('.');\n}}\n", arms)
+}
+
+#[test]
+fn ranges_and_or_patterns_pick_their_arm() {
+    let code = classify("    0 => { putchar('z'); }\n    -5..=-1 => { putchar('n'); }\n    \
+                         1 | 3 | 5 => { putchar('o'); }\n    2..=4 => { putchar('e'); }\n    \
+                         'a'..='c' | 100 => { putchar('c'); }\n    _ => { putchar('?'); }\n");
+    let asm = common::compile(&code, "pyt", &[]);
+    let cases = [("0", "z."), ("-5", "n."), ("-1", "n."), ("-6", "?."), ("3", "o."), ("4", "e."),
+                 ("6", "?."), ("98", "c."), ("100", "c."), ("101", "?.")];
+    for (input, output) in cases {
+        assert_eq!(common::run(&asm, input).output, output, "input {}", input);
+    }
+}
+
+#[test]
+fn the_first_arm_covering_a_value_takes_it() {
+    let code = classify("    1..=10 => { putchar('a'); }\n    5 | 20 => { putchar('b'); }\n    \
+                         _ => { putchar('c'); }\n");
+    let asm = common::compile(&code, "pyt", &[]);
+    assert_eq!(common::run(&asm, "5").output, "a.");
+    assert_eq!(common::run(&asm, "20").output, "b.");
+}
+
+#[test]
+fn a_value_no_arm_covers_skips_the_match() {
+    let code = classify("    1 => { putchar('a'); }\n");
+    assert_eq!(common::compile_and_run(&code, "2"), ".");
+}
+
+#[test]
+fn a_match_without_a_wildcard_warns_about_a_value_it_misses() {
+    let code = classify("    0 => { putchar('z'); }\n    -5..=-1 => { putchar('n'); }\n");
+    assert!(warnings(&code).contains(&format!("Warning: match is not exhaustive, {} is not covered", i32::MIN)));
+}
+
+#[test]
+fn a_wildcard_makes_the_match_exhaustive() {
+    let code = classify("    0 => { putchar('z'); }\n    _ => {}\n");
+    assert!(!warnings(&code).contains("Warning"));
+}
+
+#[test]
+fn an_arm_covered_by_earlier_arms_is_unreachable() {
+    let code = classify("    1..=5 => { putchar('a'); }\n    6 | 7 => { putchar('b'); }\n    \
+                         2 | 7 => { putchar('c'); }\n    _ => {}\n");
+    assert!(warnings(&code).contains("Warning: match arm 2 is unreachable"));
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use std::fs;
+
+const POINT: &str = "struct Point { x, y }\n\nstruct Segment { from: Point, to: Point }\n\n\
+                     fn[Point] mk(v) {\n  return Point { x: v, y: v * 2 };\n}\n\n";
+
+fn main_capturing(assign: &str) -> String {
+    format!("{}fn[0] main() {{\n  {}\n  fn[1] sum() {{\n    return p.x + p.y;\n  }}\n  \
+             putnum(sum());\n}}\n", POINT, assign)
+}
+
+#[test]
+fn captures_a_struct_returned_by_a_call() {
+    assert_eq!(common::compile_and_run(&main_capturing("p = mk(getnum());"), "4"), "12");
+}
+
+#[test]
+fn captures_a_struct_copied_from_another_variable() {
+    let code = main_capturing("q = mk(getnum());\n  p = q;");
+    assert_eq!(common::compile_and_run(&code, "5"), "15");
+}
+
+#[test]
+fn captures_a_struct_field_of_a_struct() {
+    let code = main_capturing("s = Segment { from: mk(1), to: mk(getnum()) };\n  p = s.to;");
+    assert_eq!(common::compile_and_run(&code, "3"), "9");
+}
+
+#[test]
+fn captures_a_struct_returned_by_a_nested_function() {
+    let code = format!("{}fn[0] main() {{\n  n = getnum();\n  fn[Point] make() {{\n    \
+                        return Point {{ x: n, y: 1 }};\n  }}\n  p = make();\n  \
+                        fn[1] sum() {{\n    return p.x + p.y;\n  }}\n  putnum(sum());\n}}\n", POINT);
+    assert_eq!(common::compile_and_run(&code, "6"), "7");
+}
+
+#[test]
+fn captures_a_struct_returned_by_an_imported_function() {
+    let dir = common::temp("dir");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("geo.pyt"), "pub struct Point { x, y }\n\n\
+                                    pub fn[Point] mk(v) {\n  return Point { x: v, y: v * 2 };\n}\n").unwrap();
+    let main = dir.join("main.pyt");
+    fs::write(&main, "import \"geo.pyt\";\n\nfn[0] main() {\n  p = geo::mk(getnum());\n  \
+                      fn[1] sum() {\n    return p.x + p.y;\n  }\n  putnum(sum());\n}\n").unwrap();
+    let out = dir.join("main.asm");
+    let output = common::poyot(&[main.to_str().unwrap(), out.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    let run = common::run(&fs::read_to_string(&out).unwrap(), "2");
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(run.output, "6");
+}
+
+#[test]
+fn captures_an_asm_output() {
+    let code = "fn[0] main() {\n  a = getnum();\n  asm(a) -> (d) {\n    DUP ADD\n  }\n  \
+                fn[1] next() {\n    return d + 1;\n  }\n  putnum(next());\n}\n";
+    assert_eq!(common::compile_and_run(code, "4"), "9");
+}
+
+#[test]
+fn captures_a_variable_defined_in_a_match_arm() {
+    let code = "fn[0] main() {\n  a = getnum();\n  match a {\n    1 => {\n      k = a * 7;\n      \
+                fn[1] f() {\n        return k + a;\n      }\n      putnum(f());\n    }\n    \
+                _ => { putnum(0); }\n  }\n}\n";
+    assert_eq!(common::compile_and_run(code, "1"), "8");
+    assert_eq!(common::compile_and_run(code, "2"), "0");
+}
+
+// inner calls itself, so it is not inlined away.
+#[test]
+fn lifted_functions_are_named_after_their_parents() {
+    let code = "fn[0] main() {\n  a = getnum();\n  fn[1] outer(x) {\n    fn[1] inner(y) {\n      \
+                if y < 1 {\n        return 0;\n      }\n      return 2 + inner(y - 1);\n    }\n    \
+                return inner(x) + 1;\n  }\n  putnum(outer(a));\n}\n";
+    let output = common::poyot_on(code, "pyt", &["--emit", "ir-text"]);
+    assert!(common::stdout(&output).contains("fn[1] main.outer.inner(y) {"), "{}", common::stdout(&output));
+    assert_eq!(common::compile_and_run(code, "5"), "11");
+}
@@ -0,0 +1,73 @@
+mod common;
+
+// Prints `args` with `format` from main.
+fn print(format: &str, args: &str) -> String {
+    format!("fn[0] main() {{\n  print(\"{}\"{});\n}}\n", format, args)
+}
+
+fn rejected(code: &str) -> String {
+    let output = common::poyot_on(code, "pyt", &[common::temp("asm").to_str().unwrap()]);
+    assert!(!output.status.success());
+    common::stdout(&output)
+}
+
+#[test]
+fn placeholders_print_numbers_and_characters() {
+    let code = print("{} and {:c}, {}{{}}", ", getnum(), 'x', 0 - 4");
+    assert_eq!(common::compile_and_run(&code, "12"), "12 and x, -4{}");
+}
+
+#[test]
+fn numbers_are_padded_to_the_width() {
+    let code = print("[{:5}][{:<5}][{:3}][{:1}]", ", getnum(), getnum(), 12345, 0 - 7");
+    assert_eq!(common::compile_and_run(&code, "42 -3"), "[   42][-3   ][12345][-7]");
+}
+
+#[test]
+fn zero_padding_goes_after_the_sign() {
+    let code = print("[{:05}][{:05}][{:02}]", ", getnum(), getnum(), 123");
+    assert_eq!(common::compile_and_run(&code, "42 -42"), "[00042][-0042][123]");
+}
+
+#[test]
+fn the_widest_numbers_fill_the_widest_width() {
+    let code = print("[{:11}][{:<11}][{:011}]", ", getnum(), getnum(), getnum()");
+    assert_eq!(common::compile_and_run(&code, "-2147483648 2147483647 -5"),
+               "[-2147483648][2147483647 ][-0000000005]");
+}
+
+#[test]
+fn characters_are_padded_to_the_width() {
+    let code = print("[{:3c}][{:<3c}]", ", 'a', 'b'");
+    assert_eq!(common::compile_and_run(&code, ""), "[  a][b  ]");
+}
+
+#[test]
+fn the_padding_code_does_not_grow_past_the_widest_number() {
+    let narrow = common::compile(&print("{:11}", ", 1"), "pyt", &[]);
+    let wide = common::compile(&print("{:11}{:11}", ", 1, 2"), "pyt", &[]);
+    assert!(wide.lines().count() < 2 * narrow.lines().count(), "{}", wide.lines().count());
+}
+
+#[test]
+fn a_width_wider_than_any_number_is_rejected() {
+    assert!(rejected(&print("{:12}", ", 1")).contains("Width in format {:12} exceeds 11, the widest a number prints"));
+    assert!(rejected(&print("{:99999999999}", ", 1")).contains("Width in format {:99999999999} exceeds 11"));
+    assert!(rejected(&print("{:999999999999999999999}", ", 1")).contains("Invalid width in format"));
+}
+
+#[test]
+fn invalid_specs_are_rejected() {
+    assert!(rejected(&print("{x}", ", 1")).contains("Invalid format {x}, expected {} or {:...}"));
+    assert!(rejected(&print("{:5x}", ", 1")).contains("Invalid width in format {:5x}"));
+    assert!(rejected(&print("{:<05}", ", 1")).contains("Zero padding in format {:<05} applies to right-aligned numbers only"));
+    assert!(rejected(&print("{:05c}", ", 1")).contains("Zero padding in format {:05c}"));
+    assert!(rejected(&print("{", "")).contains("Unterminated { in format string"));
+    assert!(rejected(&print("}", "")).contains("Unmatched } in format string, use }} to print it"));
+}
+
+#[test]
+fn the_number_of_arguments_must_match_the_placeholders() {
+    assert!(rejected(&print("{} {}", ", 1")).contains("Format string takes 2 arguments, but 1 were given"));
+    assert!(rejected(&print("{}", ", 1, 2")).contains("Format string takes 1 arguments, but 2 were given"));
+}
@@ -0,0 +1,43 @@
+mod common;
+
+#[test]
+fn redefining_a_runtime_function_is_rejected() {
+    let code = "fn[1] alloc(n) {\n  return n;\n}\n\nfn[0] main() {\n  putnum(alloc(3));\n}\n";
+    let output = common::poyot_on(code, "pyt", &["--emit", "ir"]);
+    assert!(!output.status.success());
+    assert!(common::stdout(&output)
+        .contains("Function alloc is defined by the heap runtime and cannot be redefined"));
+}
+
+#[test]
+fn the_names_of_runtime_helpers_are_free_to_use() {
+    let code = "#[noinline]\nfn[1] alloc_fit(n) {\n  return n + 1;\n}\n\n\
+                fn[0] main() {\n  p = alloc(1);\n  store(p, alloc_fit(3));\n  putnum(load(p));\n}\n";
+    let output = common::poyot_on(code, "pyt", &["--emit", "ir-text"]);
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    let text = common::stdout(&output);
+    assert!(text.contains(" alloc_fit(n) {"), "{}", text);
+    assert!(text.contains(" heap.alloc_fit(n, prev, cur) {"), "{}", text);
+    assert_eq!(common::compile_and_run(code, ""), "4");
+}
+
+#[test]
+fn a_program_without_the_heap_does_not_get_the_runtime() {
+    let code = "fn[0] main() {\n  putnum(1);\n}\n";
+    let output = common::poyot_on(code, "pyt", &["--emit", "ir-text"]);
+    assert!(!common::stdout(&output).contains("heap."));
+    assert!(!common::compile(code, "pyt", &[]).contains("heap_init"));
+}
+
+#[test]
+fn alloc_returns_zero_when_the_heap_is_full() {
+    let code = "fn[0] main() {\n  p = alloc(200);\n  q = alloc(100);\n  putnum(p > 0);\n  putnum(q);\n}\n";
+    assert_eq!(common::compile_and_run(code, ""), "10");
+}
+
+#[test]
+fn alloc_and_free_use_the_runtime() {
+    let code = "fn[0] main() {\n  p = alloc(2);\n  store(p, 7);\n  store(p + 1, 5);\n  \
+                putnum(load(p) * load(p + 1));\n  free(p);\n  q = alloc(1);\n  putnum(q - p);\n}\n";
+    assert_eq!(common::compile_and_run(code, ""), "350");
+}
@@ -0,0 +1,35 @@
+mod common;
+
+// The passes run on the SSA form one after another before it is lowered, so
+// the inlined call is folded and the blocks it left behind are merged.
+#[test]
+fn emitted_ssa_is_inlined_folded_and_merged() {
+    let code = "fn[1] double(x) {\n  return x * 2;\n}\n\nfn[0] main() {\n  putnum(double(3) + getnum());\n}\n";
+    let output = common::poyot_on(code, "pyt", &["--emit", "ssa"]);
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    assert_eq!(common::stdout(&output), "fn[0] main() {\n  b0:\n    %1 = call getnum;\n    %2 = add 6, %1;\n    \
+                                         call putnum %2;\n    return;\n}\n");
+    assert_eq!(common::compile_and_run(code, "4"), "10");
+}
+
+// In the register form a variable updated on one path is written in both,
+// while in the SSA form each register is written once and the join block
+// takes the value as a parameter.
+#[test]
+fn a_variable_updated_on_one_path_becomes_a_block_parameter() {
+    let code = "fn[0] main() {\n  x = getnum();\n  if x > 0 {\n    x += 1;\n  }\n  putnum(x);\n}\n";
+    let output = common::poyot_on(code, "pyt", &["--emit", "ssa"]);
+    assert!(output.status.success(), "{}", common::stdout(&output));
+    let text = common::stdout(&output);
+    assert!(text.contains("  b2(%1):\n    call putnum %1;\n"), "{}", text);
+    let mut written: Vec<&str> = text.lines()
+        .filter(|line| line.contains(" = "))
+        .map(|line| line.trim().split(" = ").next().unwrap())
+        .collect();
+    let count = written.len();
+    written.sort();
+    written.dedup();
+    assert_eq!(written.len(), count, "{}", text);
+    assert_eq!(common::compile_and_run(code, "4"), "5");
+    assert_eq!(common::compile_and_run(code, "-4"), "-4");
+}
@@ -0,0 +1,63 @@
+mod common;
+
+const POINT: &str = "struct Point { x, y }\n\nstruct Segment { from: Point, to: Point }\n\n";
+
+fn rejected(code: &str) -> String {
+    let output = common::poyot_on(code, "pyt", &[common::temp("asm").to_str().unwrap()]);
+    assert!(!output.status.success());
+    common::stdout(&output)
+}
+
+#[test]
+fn fields_are_read_from_literals_and_variables() {
+    let code = format!("{}fn[0] main() {{\n  p = Point {{ y: getnum(), x: getnum() }};\n  \
+                        putnum(p.x - p.y);\n}}\n", POINT);
+    assert_eq!(common::compile_and_run(&code, "3 10"), "7");
+}
+
+#[test]
+fn nested_fields_are_read_through_each_struct() {
+    let code = format!("{}fn[0] main() {{\n  a = Point {{ x: 1, y: 2 }};\n  \
+                        s = Segment {{ from: a, to: Point {{ x: getnum(), y: 4 }} }};\n  \
+                        putnum(s.to.x * 10 + s.from.y);\n}}\n", POINT);
+    assert_eq!(common::compile_and_run(&code, "5"), "52");
+}
+
+#[test]
+fn structs_are_passed_to_and_returned_from_functions() {
+    let code = format!("{}#[noinline]\nfn[Point] swap(p: Point) {{\n  return Point {{ x: p.y, y: p.x }};\n}}\n\n\
+                        #[noinline]\nfn[1] first(s: Segment) {{\n  return s.from.x;\n}}\n\n\
+                        fn[0] main() {{\n  p = swap(Point {{ x: getnum(), y: getnum() }});\n  \
+                        putnum(first(Segment {{ from: p, to: p }}));\n}}\n", POINT);
+    assert_eq!(common::compile_and_run(&code, "8 9"), "9");
+}
+
+#[test]
+fn a_missing_field_is_rejected() {
+    let code = format!("{}fn[0] main() {{\n  p = Point {{ x: 1, z: 2 }};\n  putnum(p.x);\n}}\n", POINT);
+    assert!(rejected(&code).contains("Missing field y of struct Point"));
+}
+
+#[test]
+fn reading_an_unknown_field_is_rejected() {
+    let code = format!("{}fn[0] main() {{\n  p = Point {{ x: 1, y: 2 }};\n  putnum(p.z);\n}}\n", POINT);
+    assert!(rejected(&code).contains("No field named z"));
+}
+
+#[test]
+fn a_literal_with_too_many_fields_is_rejected() {
+    let code = format!("{}fn[0] main() {{\n  p = Point {{ x: 1, y: 2, z: 3 }};\n  putnum(p.x);\n}}\n", POINT);
+    assert!(rejected(&code).contains("Struct Point has 2 fields, but 3 provided"));
+}
+
+#[test]
+fn a_struct_containing_itself_is_rejected() {
+    let code = "struct List { head, tail: List }\n\nfn[0] main() {\n  putnum(1);\n}\n";
+    assert!(rejected(code).contains("Struct List contains itself"));
+}
+
+#[test]
+fn a_duplicate_field_is_rejected() {
+    let code = "struct Point { x, x }\n\nfn[0] main() {\n  putnum(1);\n}\n";
+    assert!(rejected(code).contains("Field x of struct Point is already defined."));
+}
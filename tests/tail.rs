@@ -0,0 +1,49 @@
+mod common;
+
+fn depth(code: &str, input: &str) -> (String, usize) {
+    let run = common::run(&common::compile(code, "pyt", &[]), input);
+    (run.output, run.depth)
+}
+
+const COUNT: &str = "fn[1] count(n, acc) {\n  if n < 1 {\n    return acc;\n  }\n  return count(n - 1, acc + 2);\n}\n\n\
+                     fn[0] main() {\n  putnum(count(getnum(), 0));\n}\n";
+
+const PARITY: &str = "fn[1] even(n) {\n  if n == 0 {\n    return 1;\n  }\n  return odd(n - 1);\n}\n\n\
+                      fn[1] odd(n) {\n  if n == 0 {\n    return 0;\n  }\n  return even(n - 1);\n}\n\n\
+                      fn[0] main() {\n  putnum(even(getnum()));\n}\n";
+
+#[test]
+fn a_self_tail_call_becomes_a_jump() {
+    // Once count no longer calls itself it is small enough to be inlined.
+    let output = common::poyot_on(&format!("#[noinline]\n{}", COUNT), "pyt", &["--emit", "ir-text"]);
+    let text = common::stdout(&output);
+    let start = text.find("count(n, acc) {").unwrap();
+    let body = &text[start..start + text[start..].find("\n}\n").unwrap()];
+    assert!(!body.contains("call count"), "{}", body);
+}
+
+#[test]
+fn a_self_tail_call_runs_in_constant_stack_depth() {
+    let (short, shallow) = depth(COUNT, "5");
+    let (long, deep) = depth(COUNT, "500");
+    assert_eq!((short.as_str(), long.as_str()), ("10", "1000"));
+    assert_eq!(shallow, deep);
+}
+
+#[test]
+fn mutual_tail_calls_run_in_constant_stack_depth() {
+    let (short, shallow) = depth(PARITY, "6");
+    let (long, deep) = depth(PARITY, "601");
+    assert_eq!((short.as_str(), long.as_str()), ("1", "0"));
+    assert_eq!(shallow, deep);
+}
+
+#[test]
+fn a_call_that_is_not_in_tail_position_grows_the_stack() {
+    let code = "fn[1] sum(n) {\n  if n < 1 {\n    return 0;\n  }\n  return n + sum(n - 1);\n}\n\n\
+                fn[0] main() {\n  putnum(sum(getnum()));\n}\n";
+    let (short, shallow) = depth(code, "5");
+    let (long, deep) = depth(code, "50");
+    assert_eq!((short.as_str(), long.as_str()), ("15", "1275"));
+    assert!(deep > shallow + 45, "{} {}", shallow, deep);
+}
@@ -0,0 +1,122 @@
+mod common;
+
+const BUILTINS: &str = "fn[1] getnum();\n\nfn[0] putnum(x);\n\n";
+
+// Compiles hand-written IR, which the verifier checks before trans, and
+// returns what it reported.
+fn rejected(functions: &str) -> String {
+    let code = format!("{}{}", BUILTINS, functions);
+    let output = common::poyot_on(&code, "ir", &[common::temp("asm").to_str().unwrap()]);
+    assert!(!output.status.success(), "accepted:\n{}", code);
+    common::stdout(&output)
+}
+
+fn assert_rejected(functions: &str, message: &str) {
+    let report = rejected(functions);
+    assert!(report.contains(message), "expected {:?} in:\n{}", message, report);
+}
+
+#[test]
+fn valid_ir_is_accepted() {
+    let asm = common::compile(&format!("{}fn[0] main() {{\n  0:\n    %0 = call getnum;\n    \
+                                        call putnum %0;\n}}\n", BUILTINS), "ir", &[]);
+    assert_eq!(common::run(&asm, "4").output, "4");
+}
+
+#[test]
+fn a_next_block_out_of_range() {
+    assert_rejected("fn[0] main() {\n  0 -> 5:\n    jump;\n  1:\n}\n",
+                    "In function main, block 0: Next block 5 is out of range, there are 2");
+}
+
+#[test]
+fn an_if_with_one_next_block() {
+    assert_rejected("fn[0] main() {\n  0 -> 1:\n    if 1;\n  1:\n}\n",
+                    "If needs two next blocks, but the block has 1");
+}
+
+#[test]
+fn a_register_that_is_never_defined() {
+    assert_rejected("fn[0] main() {\n  0:\n    call putnum %3;\n}\n",
+                    "statement 0 `call putnum %3;`: Register %3 is used before it is defined");
+}
+
+#[test]
+fn a_register_defined_on_only_one_path() {
+    assert_rejected("fn[0] main() {\n  0 -> 1, 2:\n    %0 = call getnum;\n    if %0;\n  \
+                     1 -> 2:\n    %1 = substitute 7;\n  2:\n    call putnum %1;\n}\n",
+                    "In function main, block 2, statement 0 `call putnum %1;`: \
+                     Register %1 is used before it is defined");
+}
+
+#[test]
+fn a_call_with_the_wrong_number_of_arguments() {
+    assert_rejected("fn[0] main() {\n  0:\n    call putnum 1, 2;\n}\n",
+                    "`call putnum 1, 2;`: Takes 1 arguments, but has 2");
+}
+
+#[test]
+fn a_call_to_an_undefined_function() {
+    assert_rejected("fn[0] main() {\n  0:\n    call missing;\n}\n", "Function missing is not defined");
+}
+
+#[test]
+fn a_return_with_the_wrong_number_of_results() {
+    assert_rejected("fn[1] two(a) {\n  0:\n    return %0, %0;\n}\n\nfn[0] main() {\n  0:\n    \
+                     %0 = call two 1;\n}\n",
+                    "In function two, block 0, statement 0 `return %0, %0;`: Takes 1 arguments, but has 2");
+}
+
+#[test]
+fn a_result_that_is_dropped() {
+    assert_rejected("fn[0] main() {\n  0:\n    call getnum;\n}\n", "`call getnum;`: Has no result register");
+}
+
+#[test]
+fn a_result_written_by_a_jump() {
+    assert_rejected("fn[0] main() {\n  0 -> 1:\n    %0 = jump;\n  1:\n}\n", "Cannot write a result to %0");
+}
+
+#[test]
+fn a_function_without_a_terminator() {
+    assert_rejected("fn[1] inc(a) {\n  0:\n    %1 = add %0, 1;\n}\n\nfn[0] main() {\n  0:\n    \
+                     %0 = call inc 1;\n}\n",
+                    "In function inc, block 0: The last block ends without a return or jump");
+}
+
+#[test]
+fn a_label_that_is_no_function() {
+    assert_rejected("labels { missing }\n\nfn[0] main() {\n  0:\n}\n", "Label missing is not a function");
+}
+
+#[test]
+fn a_builtin_label() {
+    assert_rejected("labels { putnum }\n\nfn[0] main() {\n  0:\n}\n",
+                    "Label putnum is a builtin, which cannot be called indirectly");
+}
+
+#[test]
+fn a_program_without_main() {
+    assert_rejected("fn[1] id(a) {\n  0:\n    return %0;\n}\n", "There is no main function");
+}
+
+#[test]
+fn an_if_that_does_not_fall_through_to_its_first_next_block() {
+    assert_rejected("fn[0] main() {\n  0 -> 2, 1:\n    if 1;\n  1:\n    return;\n  2:\n}\n",
+                    "If falls through to block 1, but its first next block is 2");
+}
+
+#[test]
+fn a_block_only_reached_by_a_backward_edge() {
+    assert_rejected("fn[0] main() {\n  0 -> 2:\n    jump;\n  1 -> 3:\n    %0 = substitute 1;\n    jump;\n  \
+                     2 -> 1:\n    jump;\n  3:\n}\n",
+                    "In function main, block 1: The block is only reached by a backward edge");
+}
+
+#[test]
+fn a_loop_back_to_a_block_after_the_entry_is_accepted() {
+    let asm = common::compile(&format!("{}fn[0] main() {{\n  0 -> 1:\n    %0 = call getnum;\n  \
+                                        1 -> 2, 3:\n    if %0;\n  2 -> 1:\n    call putnum %0;\n    \
+                                        %0 = sub %0, 1;\n    jump;\n  3:\n}}\n", BUILTINS), "ir", &[]);
+    assert_eq!(common::run(&asm, "3").output, "321");
+}
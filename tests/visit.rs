@@ -0,0 +1,119 @@
+use poyot::format;
+use poyot::parse;
+use poyot::parse::Call;
+use poyot::parse::Expr;
+use poyot::parse::Program;
+use poyot::parse::Stmt;
+use poyot::tokenize;
+use poyot::visit;
+use poyot::visit::Folder;
+use poyot::visit::Visitor;
+
+fn parse(code: &str) -> Program {
+    parse::parse(&tokenize::tokenize(code).unwrap()).unwrap()
+}
+
+const CODE: &str = "\
+fn[1] twice(x) {
+  return x * 2;
+}
+
+fn[0] main() {
+  y = twice(3);
+  print(\"{}\", y);
+  if y > 4 {
+    print(\"big\");
+    y = twice(y + 1);
+  }
+  match y {
+    0..=9 => { y = 1; }
+    _ => {
+      fn[1] inner(z) {
+        return twice(z);
+      }
+      y = inner(y);
+    }
+  }
+}
+";
+
+// Doubles every number and drops every print.
+struct Double;
+
+impl Folder for Double {
+    fn fold_stmt(&mut self, stmt: Stmt) -> Option<Vec<Stmt>> {
+        match stmt {
+            Stmt::Print{..} => Some(Vec::new()),
+            _ => Some(vec![visit::fold_stmt(self, stmt)?])
+        }
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Option<Expr> {
+        match expr {
+            Expr::Constant(value) => Some(Expr::Constant(value * 2)),
+            _ => visit::fold_expr(self, expr)
+        }
+    }
+}
+
+#[test]
+fn a_folder_rewrites_nodes_everywhere_and_can_drop_statements() {
+    let folded = Double.fold_program(parse(CODE)).unwrap();
+    assert_eq!(format::format(&folded), "\
+fn[1] twice(x) {
+  return x * 4;
+}
+
+fn[0] main() {
+  y = twice(6);
+  if y > 8 {
+    y = twice(y + 2);
+  }
+  match y {
+    0..=9 => { y = 2; }
+    _ => {
+      fn[1] inner(z) {
+        return twice(z);
+      }
+      y = inner(y);
+    }
+  }
+}
+");
+}
+
+// Fails on any call to `name`.
+struct Forbid(&'static str);
+
+impl Folder for Forbid {
+    fn fold_call(&mut self, call: Call) -> Option<Call> {
+        if call.name == self.0 {
+            return None
+        }
+        visit::fold_call(self, call)
+    }
+}
+
+#[test]
+fn a_failing_fold_fails_the_whole_program() {
+    assert!(Forbid("inner").fold_program(parse(CODE)).is_none());
+    assert!(Forbid("twice").fold_program(parse(CODE)).is_none());
+    assert_eq!(Forbid("thrice").fold_program(parse(CODE)), Some(parse(CODE)));
+}
+
+// Records the called functions in order.
+struct Calls(Vec<String>);
+
+impl Visitor for Calls {
+    fn visit_call(&mut self, call: &Call) {
+        self.0.push(call.name.clone());
+        visit::walk_call(self, call)
+    }
+}
+
+#[test]
+fn a_visitor_reaches_calls_in_nested_bodies() {
+    let mut calls = Calls(Vec::new());
+    calls.visit_program(&parse(CODE));
+    assert_eq!(calls.0, ["twice", "twice", "twice", "inner"]);
+}